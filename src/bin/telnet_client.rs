@@ -1,5 +1,8 @@
 use ardl::{
-    layer::{Builder, Downloader, IObserver, SetUploadState, Uploader},
+    layer::{
+        Builder, Downloader, IObserver, OnFull, OnReassemblyTimeout, SetUploadState, Uploader,
+        MAX_RTO, MIN_RTO,
+    },
     protocol::{frag::PUSH_HDR_LEN, packet_hdr::PACKET_HDR_LEN},
     utils::buf::{BufSlice, BufWtr, OwnedBufWtr},
 };
@@ -18,7 +21,6 @@ const STAT_INTERVAL_S: u64 = 1;
 const LISTEN_ADDR: &str = "0.0.0.0:19479";
 const LOCAL_RECV_BUF_LEN: usize = 2;
 const NACK_DUPLICATE_THRESHOLD_TO_ACTIVATE_FAST_RETRANSMIT: usize = 0;
-const RATIO_RTO_TO_ONE_RTT: f64 = 1.5;
 // const TO_SEND_QUEUE_LEN_CAP: usize = 1024 * 64;
 const TO_SEND_QUEUE_LEN_CAP: usize = 1;
 const MAX_SWND_SIZE: usize = usize::MAX;
@@ -42,9 +44,24 @@ fn main() {
         local_recv_buf_len: LOCAL_RECV_BUF_LEN,
         nack_duplicate_threshold_to_activate_fast_retransmit:
             NACK_DUPLICATE_THRESHOLD_TO_ACTIVATE_FAST_RETRANSMIT,
-        ratio_rto_to_one_rtt: RATIO_RTO_TO_ONE_RTT,
+        min_rto: MIN_RTO,
+        max_rto: MAX_RTO,
         to_send_queue_len_cap: TO_SEND_QUEUE_LEN_CAP,
         swnd_size_cap: MAX_SWND_SIZE,
+        max_inflight_bytes: usize::MAX,
+        frag_checksum: false,
+        packet_checksum: false,
+        timestamps: false,
+        delimited: false,
+        strict_framing: false,
+        delayed_ack: None,
+        ordered: true,
+        local_recv_buf_bytes: usize::MAX,
+        local_on_full: OnFull::DropNew,
+        legacy_mode: false,
+        messages: false,
+        reassembly_timeout: None,
+        on_reassembly_timeout: OnReassemblyTimeout::Skip,
         mtu: MTU,
     }
     .build()
@@ -186,7 +203,7 @@ fn downloading(
         match msg {
             DownloadingMessaging::ConnRecv(wtr) => {
                 let rdr = wtr.into_slice();
-                let set_upload_state = match downloader.write(rdr) {
+                let set_upload_state = match downloader.write(rdr, &Instant::now()) {
                     Ok(x) => x,
                     Err(e) => {
                         println!("err: download.input ({:?})", e);
@@ -0,0 +1,251 @@
+use super::{BuildError, Builder, Downloader, Error, SendError, SetStateError, Uploader};
+use crate::utils::buf::{BufSlice, BufWtr, OwnedBufWtr};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Both halves of one end of a connection, with the changes from the downloader applied to the uploader
+///
+/// `C` is whatever the application keeps along with the connection, e.g. a peer id.
+pub struct Connection<C = ()> {
+    uploader: Uploader,
+    downloader: Downloader,
+    to_output: VecDeque<Vec<u8>>,
+    mtu: usize,
+    context: C,
+}
+
+impl Connection {
+    pub fn new(builder: Builder) -> Result<Self, BuildError> {
+        Self::with_context(builder, ())
+    }
+}
+
+impl<C> Connection<C> {
+    pub fn with_context(builder: Builder, context: C) -> Result<Self, BuildError> {
+        let mtu = builder.mtu;
+        let (uploader, downloader) = builder.build()?;
+        Ok(Connection {
+            uploader,
+            downloader,
+            to_output: VecDeque::new(),
+            mtu,
+            context,
+        })
+    }
+
+    #[must_use]
+    pub fn context(&self) -> &C {
+        &self.context
+    }
+
+    #[must_use]
+    pub fn context_mut(&mut self) -> &mut C {
+        &mut self.context
+    }
+
+    /// Take in a datagram from the peer
+    pub fn input(&mut self, datagram: BufSlice, now: &Instant) -> Result<(), InputError> {
+        let changes = self
+            .downloader
+            .write(datagram, now)
+            .map_err(InputError::Decoding)?;
+        self.uploader
+            .set_state(changes, now)
+            .map_err(InputError::SetState)
+    }
+
+    /// Take in a datagram borrowed from a buffer to be reused
+    pub fn input_slice(&mut self, datagram: &[u8], now: &Instant) -> Result<(), InputError> {
+        let changes = self
+            .downloader
+            .write_slice(datagram, now)
+            .map_err(InputError::Decoding)?;
+        self.uploader
+            .set_state(changes, now)
+            .map_err(InputError::SetState)
+    }
+
+    /// The next bytes received in order
+    #[must_use]
+    pub fn recv(&mut self) -> Option<BufSlice> {
+        self.downloader.emit()
+    }
+
+    pub fn send(&mut self, data: &[u8]) -> Result<(), SendError<BufSlice>> {
+        self.uploader.write(BufSlice::from_bytes(data.to_vec()))
+    }
+
+    /// The next datagram to send to the peer
+    ///
+    /// Call it until `None` whenever the time passes or anything is input or sent.
+    #[must_use]
+    pub fn output(&mut self, now: &Instant) -> Option<Vec<u8>> {
+        if self.to_output.is_empty() {
+            for seq in self.downloader.on_timer(now) {
+                self.uploader.queue_ack(seq);
+            }
+            for packet in self.uploader.emit(now) {
+                let mut wtr = OwnedBufWtr::new(self.mtu, 0);
+                packet.append_to(&mut wtr).unwrap();
+                self.to_output.push_back(wtr.data().to_vec());
+            }
+        }
+        self.to_output.pop_front()
+    }
+
+    #[must_use]
+    pub fn uploader(&self) -> &Uploader {
+        &self.uploader
+    }
+
+    /// e.g. to set the congestion control
+    #[must_use]
+    pub fn uploader_mut(&mut self) -> &mut Uploader {
+        &mut self.uploader
+    }
+
+    #[must_use]
+    pub fn downloader(&self) -> &Downloader {
+        &self.downloader
+    }
+
+    /// The state of the path to the peer as of now, e.g. for a periodic log line
+    #[must_use]
+    pub fn path_summary(&self) -> PathSummary {
+        PathSummary {
+            srtt: self.uploader.stat().srtt,
+            rto: self.uploader.rto(),
+            cwnd: self.uploader.cwnd(),
+            ssthresh: self.uploader.ssthresh(),
+            inflight: self.uploader.inflight(),
+            inflight_bytes: self.uploader.inflight_bytes(),
+            remote_rwnd: self.uploader.remote_rwnd(),
+            retransmissions: self.uploader.stat().retransmissions,
+        }
+    }
+}
+
+/// Unit of windows: sequence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathSummary {
+    /// `None` before the first RTT sample
+    pub srtt: Option<Duration>,
+    pub rto: Duration,
+    /// `None` without congestion control
+    pub cwnd: Option<usize>,
+    /// `None` without congestion control, or one with no slow start threshold
+    pub ssthresh: Option<usize>,
+    pub inflight: usize,
+    /// Unit: byte
+    pub inflight_bytes: usize,
+    /// The rwnd last advertised by the peer
+    pub remote_rwnd: usize,
+    pub retransmissions: u64,
+}
+
+#[derive(Debug)]
+pub enum InputError {
+    Decoding(Error),
+    SetState(SetStateError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::NewReno;
+
+    #[test]
+    fn test_send_and_ack() {
+        let now = Instant::now();
+        let mut conn1 = Connection::new(Builder::default()).unwrap();
+        let mut conn2 = Connection::new(Builder::default()).unwrap();
+
+        // push: 1 -> 2
+        conn1.send(&[0, 1, 2]).map_err(|_| ()).unwrap();
+        let datagram = conn1.output(&now).unwrap();
+        assert!(conn1.output(&now).is_none());
+        conn2.input_slice(&datagram, &now).unwrap();
+        assert_eq!(conn2.recv().unwrap().data(), vec![0, 1, 2]);
+        assert!(conn2.recv().is_none());
+        assert_eq!(conn1.uploader().stat().bytes_acked, 0);
+
+        // ack: 1 <- 2
+        let datagram = conn2.output(&now).unwrap();
+        assert!(conn2.output(&now).is_none());
+        conn1.input(BufSlice::from_bytes(datagram), &now).unwrap();
+        assert!(conn1.recv().is_none());
+        assert_eq!(conn1.uploader().stat().bytes_acked, 3);
+
+        // nothing left to retransmit
+        assert!(conn1.output(&(now + conn1.uploader().rto())).is_none());
+    }
+
+    #[test]
+    fn test_path_summary() {
+        let mut now = Instant::now();
+        let mut conn1 = Connection::new(Builder::default()).unwrap();
+        let mut conn2 = Connection::new(Builder::default()).unwrap();
+        conn1
+            .uploader_mut()
+            .set_congestion_control(Some(Box::new(NewReno::new(4))));
+        let summary = conn1.path_summary();
+        assert_eq!(summary.srtt, None);
+        assert_eq!(summary.cwnd, Some(4));
+        assert_eq!(summary.ssthresh, Some(usize::MAX));
+
+        // a round trip for an RTT sample
+        conn1.send(&[0, 1, 2]).map_err(|_| ()).unwrap();
+        let datagram = conn1.output(&now).unwrap();
+        conn2.input_slice(&datagram, &now).unwrap();
+        now += Duration::from_millis(10);
+        let datagram = conn2.output(&now).unwrap();
+        conn1.input_slice(&datagram, &now).unwrap();
+        assert_eq!(conn1.path_summary().srtt, Some(Duration::from_millis(10)));
+
+        // the next push is lost
+        conn1.send(&[3, 4]).map_err(|_| ()).unwrap();
+        let _ = conn1.output(&now).unwrap();
+        let summary = conn1.path_summary();
+        assert_eq!((summary.inflight, summary.inflight_bytes), (1, 2));
+        assert_eq!(summary.retransmissions, 0);
+
+        now += summary.rto;
+        let datagram = conn1.output(&now).unwrap();
+        let summary = conn1.path_summary();
+        assert_eq!(summary.retransmissions, 1);
+        assert_eq!((summary.cwnd, summary.ssthresh), (Some(1), Some(2)));
+        assert_eq!(summary.inflight, 1);
+
+        conn2.input_slice(&datagram, &now).unwrap();
+        let datagram = conn2.output(&now).unwrap();
+        conn1.input_slice(&datagram, &now).unwrap();
+        let summary = conn1.path_summary();
+        assert_eq!((summary.inflight, summary.inflight_bytes), (0, 0));
+        assert_eq!((summary.cwnd, summary.ssthresh), (Some(2), Some(2)));
+        assert_eq!(summary.retransmissions, 1);
+        // the two pushes are still unread by the peer
+        assert_eq!(summary.remote_rwnd, 1024 - 2);
+        assert_eq!(summary.srtt, Some(Duration::from_millis(10)));
+        assert_eq!(summary.rto, conn1.uploader().rto());
+    }
+
+    #[test]
+    fn test_context() {
+        let now = Instant::now();
+        let mut conn1 = Connection::with_context(Builder::default(), (1_u64, "peer")).unwrap();
+        let mut conn2 = Connection::new(Builder::default()).unwrap();
+
+        conn1.send(&[0, 1, 2]).map_err(|_| ()).unwrap();
+        let datagram = conn1.output(&now).unwrap();
+        conn2.input_slice(&datagram, &now).unwrap();
+        let datagram = conn2.output(&now).unwrap();
+        conn1.input_slice(&datagram, &now).unwrap();
+        assert_eq!(conn1.context(), &(1, "peer"));
+
+        conn1.context_mut().0 += 1;
+        assert_eq!(conn1.context(), &(2, "peer"));
+        assert_eq!(conn2.context(), &());
+    }
+}
@@ -1,42 +1,236 @@
-use super::SetUploadState;
+use super::{DropReason, IDeliveryObserver, IDownloadObserver, SetUploadState};
 use crate::{
     protocol::{
         frag::{Body, Frag, FragCommand},
         packet::Packet,
+        packet_hdr::{PacketFormat, PacketHeader, Timestamps, MAX_SCALED_RWND},
+        DecodingError,
     },
     utils::{
-        buf::{self, BufSlice},
-        RecvBuf, Seq32, SeqLocationToRwnd,
+        buf::{self, BufSlice, FragAllocator, GlobalAllocator},
+        RecvBuf, Seq32, SeqLocationToRwnd, CHECK_REP,
     },
 };
+use std::{
+    collections::HashSet,
+    collections::VecDeque,
+    io::IoSlice,
+    ops::Range,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
 
 pub struct Downloader {
-    recv_buf: RecvBuf<Seq32, BufSlice>,
+    recv_buf: RecvBuf<Seq32, RecvFrag>,
     leftover: Option<BufSlice>,
     stat: LocalStat,
+    strict_framing: bool,
+    max_frag_body_len: usize,
+    max_frags_per_packet: usize,
+    delayed_ack: Option<DelayedAckPolicy>,
+    pending_acks: Vec<Seq32>,
+    pending_acks_since: Option<Instant>,
+    ordered: bool,
+    /// Unordered only: pushes delivered as they arrive
+    arrived: VecDeque<(Seq32, BufSlice)>,
+    /// Unordered only: emitted pushes whose sorted counterparts in `recv_buf` are yet to be released
+    consumed_ahead: usize,
+    recv_buf_bytes: usize,
+    on_full: OnFull,
+    legacy_mode: bool,
+    messages: bool,
+    /// Messages only: the start of a message was dropped for never fitting the rwnd, so are its remaining pushes
+    skipping_message: bool,
+    /// Unordered messages only: pushes of the messages emitted whose sorted counterparts in `recv_buf` are yet to be released
+    delivered_ahead: HashSet<Seq32>,
+    reassembly_timeout: Option<Duration>,
+    on_reassembly_timeout: OnReassemblyTimeout,
+    /// Under `OnReassemblyTimeout::Error`: the start of the message timed out, until `reset`
+    timed_out_message: Option<Seq32>,
+    /// The epoch of the last `Reset` taken, to tell a replay of it
+    last_reset_epoch: Option<Seq32>,
+    /// Unit: byte
+    buffered_bytes: usize,
+    frag_allocator: Arc<dyn FragAllocator + Send + Sync + 'static>,
+
+    // observer
+    on_delivered: Option<Weak<dyn IDeliveryObserver + Send + Sync + 'static>>,
+    on_download_event: Option<Weak<dyn IDownloadObserver + Send + Sync + 'static>>,
 }
 
 pub struct DownloaderBuilder {
     pub recv_buf_len: usize,
+    /// Reject a packet that both pushes and acks the same sequence
+    ///
+    /// The two live in separate sequence spaces, one per direction, so such a packet is processed frag by frag when this is off.
+    pub strict_framing: bool,
+    /// Pushes with longer bodies are dropped, or rejected under `strict_framing`
+    ///
+    /// Unit: byte
+    pub max_frag_body_len: usize,
+    /// Fragments beyond are left undecoded, or the packet is rejected under `strict_framing`
+    pub max_frags_per_packet: usize,
+    /// Hold back the acks of pushes in order instead of surfacing them with every written packet
+    ///
+    /// `None` surfaces the ack of every push in the `SetUploadState` of the write taking it.
+    pub delayed_ack: Option<DelayedAckPolicy>,
+    /// Emit pushes in sequence order, or else as soon as they arrive without head-of-line blocking
+    ///
+    /// The pushes emitted ahead of a gap still take up the rwnd until the gap is filled.
+    pub ordered: bool,
+    /// Pushes held out of order are dropped rather than take the bytes in the receive buffer beyond this
+    ///
+    /// A push at the start of the rwnd is always taken, for it may be the one filling the gap ahead of those held.
+    ///
+    /// Unit: byte
+    pub recv_buf_bytes: usize,
+    /// What gives way when a push held out of order would take the bytes beyond `recv_buf_bytes`
+    pub on_full: OnFull,
+    /// Where the bodies copied by `Downloader::write_slice` get their buffers; `None` for the global allocator
+    pub frag_allocator: Option<Arc<dyn FragAllocator + Send + Sync + 'static>>,
+    /// Decode a packet not led by `CURRENT_VERSION` in the layout from before the version existed, so a fleet can upgrade gradually
+    ///
+    /// See `PacketFormat::Legacy` and `Stat::legacy_packets`.
+    pub legacy_mode: bool,
+    /// Reassemble the pushes into the messages of `Uploader::write_message` for `Downloader::emit_message`
+    ///
+    /// When unordered, a message is emitted once all its pushes arrive, ahead of the messages before it.
+    pub messages: bool,
+    /// Give up on the message at the start of the rwnd once it has been held incomplete this long since its first push arrived; `None` to wait forever
+    ///
+    /// Checked by `Downloader::on_timer`. For a peer that gave up retransmitting its pushes, or the stream never advances. Messages only.
+    pub reassembly_timeout: Option<Duration>,
+    /// What happens to a message past `reassembly_timeout`
+    pub on_reassembly_timeout: OnReassemblyTimeout,
+}
+
+/// What happens to a message held incomplete past `DownloaderBuilder::reassembly_timeout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnReassemblyTimeout {
+    /// Drop the pushes of the message held and expect the push after its last one, or drop its remaining pushes as they arrive if its last one is yet to
+    ///
+    /// The pushes are dropped as `DropReason::ReassemblyTimeout` and the message counted in `Stat::timed_out_messages`.
+    Skip,
+    /// Hold the message, and have `Downloader::emit_message` fail from then on until `Downloader::reset`
+    Error,
+}
+
+/// The acks of duplicate, late and out-of-order pushes are never held back, for the peer may be waiting on them to retransmit; they take the pending acks along
+#[derive(Debug, Clone)]
+pub struct DelayedAckPolicy {
+    /// Flush the pending acks once the oldest of them has been held this long
+    ///
+    /// It should stay below the RTO of the peer, or the peer retransmits the pushes whose acks are only held back. `Builder` checks it against the local `min_rto`, assuming the peer is built alike.
+    pub max_delay: Duration,
+    /// Flush the pending acks once this many have accumulated
+    pub max_unacked: usize,
+}
+
+/// What gives way when a push held out of order would exceed `DownloaderBuilder::recv_buf_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFull {
+    /// Drop the incoming push without acking it
+    DropNew,
+    /// Evict the pushes held furthest ahead of the incoming one until it fits
+    ///
+    /// The incoming push is dropped as under `DropNew` if evicting all those beyond it would not make room, or when unordered, for the pushes held may have been emitted already.
+    ///
+    /// Pushes held out of order are then not acked one by one but only by the nack once the gap ahead of them fills, so an evicted push has never been taken as received and the peer retransmits it.
+    ///
+    /// Only `recv_buf_bytes` gives way. `recv_buf_len` bounds a range of sequences rather than a count of pushes held, so evicting makes no room for a push beyond it.
+    DropFurthest,
+}
+
+impl Default for DownloaderBuilder {
+    fn default() -> Self {
+        Self {
+            recv_buf_len: 1024,
+            strict_framing: false,
+            max_frag_body_len: usize::MAX,
+            max_frags_per_packet: usize::MAX,
+            delayed_ack: None,
+            ordered: true,
+            recv_buf_bytes: usize::MAX,
+            on_full: OnFull::DropNew,
+            frag_allocator: None,
+            legacy_mode: false,
+            messages: false,
+            reassembly_timeout: None,
+            on_reassembly_timeout: OnReassemblyTimeout::Skip,
+        }
+    }
 }
 
 impl DownloaderBuilder {
     pub fn build(self) -> Result<Downloader, BuildError> {
-        if !(self.recv_buf_len <= u16::MAX as usize) {
+        if !(self.recv_buf_len <= MAX_SCALED_RWND) {
             return Err(BuildError::RecvBufTooLarge);
         }
+        if self.recv_buf_len == 0 {
+            return Err(BuildError::RecvBufEmpty);
+        }
+        if self.max_frags_per_packet == 0 {
+            return Err(BuildError::MaxFragsPerPacketZero);
+        }
+        if self.recv_buf_bytes == 0 {
+            return Err(BuildError::RecvBufBytesZero);
+        }
+        if let Some(policy) = &self.delayed_ack {
+            if policy.max_unacked == 0 {
+                return Err(BuildError::MaxUnackedZero);
+            }
+        }
+        if self.reassembly_timeout.is_some() && !self.messages {
+            return Err(BuildError::ReassemblyTimeoutWithoutMessages);
+        }
         let this = Downloader {
             recv_buf: RecvBuf::new(self.recv_buf_len),
             leftover: None,
             stat: LocalStat {
+                in_orders: 0,
                 early_pushes: 0,
                 late_pushes: 0,
+                duplicate_pushes: 0,
                 out_of_orders: 0,
                 decoding_errors: 0,
+                corrupted_frags: 0,
+                unknown_frags: 0,
+                oversized_pushes: 0,
+                over_budget_pushes: 0,
+                evicted_pushes: 0,
+                truncated_packets: 0,
+                legacy_packets: 0,
+                too_long_messages: 0,
+                timed_out_messages: 0,
                 packets: 0,
                 acks: 0,
                 pushes: 0,
             },
+            strict_framing: self.strict_framing,
+            max_frag_body_len: self.max_frag_body_len,
+            max_frags_per_packet: self.max_frags_per_packet,
+            delayed_ack: self.delayed_ack,
+            pending_acks: Vec::new(),
+            pending_acks_since: None,
+            ordered: self.ordered,
+            arrived: VecDeque::new(),
+            consumed_ahead: 0,
+            recv_buf_bytes: self.recv_buf_bytes,
+            on_full: self.on_full,
+            legacy_mode: self.legacy_mode,
+            messages: self.messages,
+            skipping_message: false,
+            delivered_ahead: HashSet::new(),
+            reassembly_timeout: self.reassembly_timeout,
+            on_reassembly_timeout: self.on_reassembly_timeout,
+            timed_out_message: None,
+            last_reset_epoch: None,
+            buffered_bytes: 0,
+            frag_allocator: self
+                .frag_allocator
+                .unwrap_or_else(|| Arc::new(GlobalAllocator)),
+            on_delivered: None,
+            on_download_event: None,
         };
         this.check_rep();
         Ok(this)
@@ -46,26 +240,164 @@ impl DownloaderBuilder {
 #[derive(Debug)]
 pub enum BuildError {
     RecvBufTooLarge,
+    RecvBufEmpty,
+    MaxUnackedZero,
+    MaxFragsPerPacketZero,
+    RecvBufBytesZero,
+    ReassemblyTimeoutWithoutMessages,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageError {
+    /// The message starting at `seq` was held incomplete past `reassembly_timeout` under `OnReassemblyTimeout::Error`
+    ReassemblyTimedOut { seq: Seq32 },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SetRecvBufLenError {
+    RecvBufTooLarge,
+    RecvBufEmpty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
-    Decoding,
+    PacketHeaderTooShort,
+    PacketHeaderInvalid,
+    VersionMismatch {
+        version: u8,
+    },
+    PacketChecksum,
+    /// Unit of `declared_len` and `available`: byte
+    PacketLenMismatch {
+        declared_len: usize,
+        available: usize,
+    },
+    /// Unit of `offset`: byte from the start of the packet
+    FragHeaderInvalid {
+        offset: usize,
+    },
+    /// Unit of `declared_len` and `available`: byte
+    PushBodyTruncated {
+        seq: Seq32,
+        declared_len: usize,
+        available: usize,
+    },
+    /// Only under `strict_framing`
+    PushAckSameSeq {
+        seq: Seq32,
+    },
+    /// Only under `strict_framing`
+    ///
+    /// Unit of `len`: byte
+    PushBodyTooLong {
+        seq: Seq32,
+        len: usize,
+    },
+    /// Only under `strict_framing`
+    TooManyFrags {
+        max: usize,
+    },
+    /// A checksummed fragment is corrupted
+    ///
+    /// The downloader drops such fragments one by one rather than fail the packet.
+    FragChecksum,
+    /// A fragment of a command this version does not know
+    ///
+    /// The downloader skips such fragments one by one rather than fail the packet.
+    UnknownCommand {
+        cmd: u8,
+    },
+}
+
+impl From<DecodingError> for Error {
+    fn from(e: DecodingError) -> Self {
+        match e {
+            DecodingError::PacketHeaderTooShort => Error::PacketHeaderTooShort,
+            DecodingError::PacketHeaderInvalid => Error::PacketHeaderInvalid,
+            DecodingError::VersionMismatch { version } => Error::VersionMismatch { version },
+            DecodingError::PacketChecksum => Error::PacketChecksum,
+            DecodingError::PacketLenMismatch {
+                declared_len,
+                available,
+            } => Error::PacketLenMismatch {
+                declared_len,
+                available,
+            },
+            DecodingError::FragHeaderInvalid { offset } => Error::FragHeaderInvalid { offset },
+            DecodingError::PushBodyTruncated {
+                seq,
+                declared_len,
+                available,
+            } => Error::PushBodyTruncated {
+                seq,
+                declared_len,
+                available,
+            },
+            DecodingError::Checksum => Error::FragChecksum,
+            DecodingError::UnknownCommand { cmd } => Error::UnknownCommand { cmd },
+        }
+    }
 }
 
 impl Downloader {
     #[inline]
     fn check_rep(&self) {
-        assert!(self.recv_buf.rwnd_size() <= u16::MAX as usize);
+        if !CHECK_REP {
+            return;
+        }
+        assert!(self.recv_buf.rwnd_size() <= MAX_SCALED_RWND);
+        assert!(self.recv_buf.capacity() <= MAX_SCALED_RWND);
+        assert!(self.recv_buf.capacity() > 0);
+        assert!(self.recv_buf.rwnd_size() <= self.recv_buf.capacity());
+        assert_eq!(
+            self.pending_acks.is_empty(),
+            self.pending_acks_since.is_none()
+        );
+        if self.ordered {
+            assert!(self.arrived.is_empty());
+            assert_eq!(self.consumed_ahead, 0);
+        }
+        if !self.messages {
+            assert!(!self.skipping_message);
+            assert!(self.timed_out_message.is_none());
+        } else {
+            assert!(self.arrived.is_empty());
+            assert_eq!(self.consumed_ahead, 0);
+        }
+        if self.ordered {
+            assert!(self.delivered_ahead.is_empty());
+        }
+        assert!(self.consumed_ahead == 0 || self.recv_buf.sorted_len() == 0);
+        if self.recv_buf.sorted_len() + self.recv_buf.out_of_order_len() == 0 {
+            assert_eq!(self.buffered_bytes, 0);
+        }
+        // only the pushes at the start of the rwnd are taken beyond the cap
+        let out_of_order_bytes: usize = self
+            .recv_buf
+            .out_of_order_iter()
+            .map(|x| x.body.len())
+            .sum();
+        assert!(out_of_order_bytes <= self.recv_buf_bytes);
     }
 
     #[must_use]
     pub fn stat(&self) -> Stat {
         Stat {
+            in_orders: self.stat.in_orders,
             early_pushes: self.stat.early_pushes,
             late_pushes: self.stat.late_pushes,
+            duplicate_pushes: self.stat.duplicate_pushes,
             out_of_orders: self.stat.out_of_orders,
             decoding_errors: self.stat.decoding_errors,
+            corrupted_frags: self.stat.corrupted_frags,
+            unknown_frags: self.stat.unknown_frags,
+            oversized_pushes: self.stat.oversized_pushes,
+            over_budget_pushes: self.stat.over_budget_pushes,
+            evicted_pushes: self.stat.evicted_pushes,
+            truncated_packets: self.stat.truncated_packets,
+            legacy_packets: self.stat.legacy_packets,
+            too_long_messages: self.stat.too_long_messages,
+            timed_out_messages: self.stat.timed_out_messages,
             next_seq_to_receive: self.recv_buf.next_seq_to_receive(),
             packets: self.stat.packets,
             pushes: self.stat.pushes,
@@ -73,24 +405,196 @@ impl Downloader {
         }
     }
 
+    /// Zero the counters in `stat`
+    pub fn reset_stat(&mut self) {
+        self.stat = LocalStat::default();
+    }
+
+    /// Resize the receive buffer, e.g. under memory pressure
+    ///
+    /// Fragments already buffered are kept even if the buffer shrinks below them; the rwnd reported to the peer bottoms out at `0` until they are emitted.
+    ///
+    /// The length is bounded as in `DownloaderBuilder::build`; on `Err` the buffer is left as it was.
+    ///
+    /// Unit: sequence
+    pub fn set_recv_buf_len(&mut self, len: usize) -> Result<(), SetRecvBufLenError> {
+        if !(len <= MAX_SCALED_RWND) {
+            return Err(SetRecvBufLenError::RecvBufTooLarge);
+        }
+        if len == 0 {
+            return Err(SetRecvBufLenError::RecvBufEmpty);
+        }
+        self.recv_buf.set_capacity(len);
+        self.check_rep();
+        Ok(())
+    }
+
+    /// Bytes held in the receive buffer, in order or not, until emitted
+    ///
+    /// Unit: byte
+    #[must_use]
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Bytes left before pushes held out of order are dropped
+    ///
+    /// Unit: byte
+    #[must_use]
+    pub fn recv_buf_bytes_left(&self) -> usize {
+        self.recv_buf_bytes.saturating_sub(self.buffered_bytes)
+    }
+
+    /// Unit: sequence
+    #[must_use]
+    pub fn out_of_order_len(&self) -> usize {
+        self.recv_buf.out_of_order_len()
+    }
+
+    /// Unit: sequence
+    #[must_use]
+    pub fn sorted_len(&self) -> usize {
+        self.recv_buf.sorted_len()
+    }
+
+    /// Contiguous runs of the sequences received but not yet deliverable
+    #[must_use]
+    pub fn sack_ranges(&self) -> Vec<Range<Seq32>> {
+        self.recv_buf.out_of_order_ranges()
+    }
+
+    #[must_use]
+    pub fn next_seq_to_receive(&self) -> Seq32 {
+        self.recv_buf.next_seq_to_receive()
+    }
+
+    /// The cumulative nack for the peer: the lowest seq not yet received
+    ///
+    /// Every seq below is received, so this is the first hole ahead of `sack_ranges`, or the end of them all if there is no hole.
+    #[must_use]
+    pub fn nack(&self) -> Seq32 {
+        self.recv_buf.next_seq_to_receive()
+    }
+
+    /// No more pushes are accepted until some are emitted
+    #[must_use]
+    pub fn is_rwnd_full(&self) -> bool {
+        self.recv_buf.rwnd_size() == 0
+    }
+
+    pub fn set_on_delivered(
+        &mut self,
+        observer: Option<Weak<dyn IDeliveryObserver + Send + Sync + 'static>>,
+    ) {
+        self.on_delivered = observer;
+    }
+
+    pub fn set_on_download_event(
+        &mut self,
+        observer: Option<Weak<dyn IDownloadObserver + Send + Sync + 'static>>,
+    ) {
+        self.on_download_event = observer;
+    }
+
     #[must_use]
     pub fn emit(&mut self) -> Option<BufSlice> {
-        let received = self.recv_buf.pop_front();
+        self.emit_with_seq().map(|(_, slice)| slice)
+    }
+
+    /// Like `emit`, along with the seq the push was sent at
+    #[must_use]
+    pub fn emit_with_seq(&mut self) -> Option<(Seq32, BufSlice)> {
+        let received = self.pop_ready();
         self.check_rep();
         received
     }
 
+    /// The next push in order, or the next arrived when unordered
+    fn pop_ready(&mut self) -> Option<(Seq32, BufSlice)> {
+        if self.ordered {
+            return self.pop_sorted();
+        }
+        let arrived = self.arrived.pop_front()?;
+        self.consumed_ahead += 1;
+        self.release_consumed();
+        Some(arrived)
+    }
+
+    fn pop_sorted(&mut self) -> Option<(Seq32, BufSlice)> {
+        let frag = self.recv_buf.pop_front()?;
+        self.buffered_bytes -= frag.body.len();
+        Some((frag.seq, frag.body))
+    }
+
+    fn pop_sorted_up_to(&mut self, max: usize) -> Vec<RecvFrag> {
+        let frags = self.recv_buf.pop_front_up_to(max);
+        self.buffered_bytes -= frags.iter().map(|x| x.body.len()).sum::<usize>();
+        frags
+    }
+
+    /// Discard whatever is received but not yet emitted and expect `seq` next
+    ///
+    /// Done on a `Reset` from the peer, or to reuse the downloader for another peer without rebuilding it. `stat` is kept; see `reset_stat`.
+    pub fn reset(&mut self, seq: Seq32) {
+        self.recv_buf.reset(seq);
+        self.buffered_bytes = 0;
+        self.leftover = None;
+        self.arrived.clear();
+        self.consumed_ahead = 0;
+        self.pending_acks.clear();
+        self.pending_acks_since = None;
+        self.last_reset_epoch = None;
+        self.skipping_message = false;
+        self.delivered_ahead.clear();
+        self.timed_out_message = None;
+        self.check_rep();
+    }
+
+    /// Free the rwnd taken by the pushes already emitted ahead of their order
+    fn release_consumed(&mut self) {
+        while self.consumed_ahead > 0 && self.pop_sorted().is_some() {
+            self.consumed_ahead -= 1;
+        }
+    }
+
+    /// Emit all the ready slices in order
+    #[must_use]
+    pub fn emit_all(&mut self) -> Vec<BufSlice> {
+        self.emit_up_to(usize::MAX)
+    }
+
+    /// Emit at most `max` ready slices in order
+    #[must_use]
+    pub fn emit_up_to(&mut self, max: usize) -> Vec<BufSlice> {
+        let mut slices = Vec::new();
+        if max == 0 {
+            return slices;
+        }
+        if let Some(leftover) = self.leftover.take() {
+            slices.push(leftover);
+        }
+        if self.ordered {
+            let frags = self.pop_sorted_up_to(max - slices.len());
+            slices.extend(frags.into_iter().map(|x| x.body));
+        } else {
+            while slices.len() < max {
+                match self.pop_ready() {
+                    Some((_, slice)) => slices.push(slice),
+                    None => break,
+                }
+            }
+        }
+        self.check_rep();
+        slices
+    }
+
     #[must_use]
     pub fn emit_max(&mut self, max_len: usize) -> Option<BufSlice> {
         let leftover = self.leftover.take();
         let slice = if let Some(slice) = leftover {
             slice
         } else {
-            if let Some(slice) = self.recv_buf.pop_front() {
-                slice
-            } else {
-                return None;
-            }
+            self.pop_ready()?.1
         };
 
         let final_slice = if slice.len() > max_len {
@@ -105,34 +609,513 @@ impl Downloader {
         final_slice
     }
 
+    /// Copy at most `max_len` ready bytes into one contiguous buffer
+    ///
+    /// The bytes keep their order and carry no fragment boundaries. The rest of a partially copied fragment stays for the next emit.
+    #[must_use]
+    pub fn emit_coalesced(&mut self, max_len: usize) -> Vec<u8> {
+        let mut coalesced = Vec::new();
+        while coalesced.len() < max_len {
+            match self.emit_max(max_len - coalesced.len()) {
+                Some(slice) => {
+                    coalesced.extend_from_slice(slice.data());
+                    self.recycle(slice);
+                }
+                None => break,
+            }
+        }
+        self.check_rep();
+        coalesced
+    }
+
+    /// Copy exactly `n` ready bytes into one contiguous buffer, or none if fewer are ready
+    ///
+    /// As with `emit_coalesced`, the rest of a partially copied fragment stays for the next emit. Nothing is emitted on `None`.
+    #[must_use]
+    pub fn emit_exact(&mut self, n: usize) -> Option<Vec<u8>> {
+        if self.ready_bytes() < n {
+            return None;
+        }
+        let exact = self.emit_coalesced(n);
+        assert_eq!(exact.len(), n);
+        Some(exact)
+    }
+
+    /// Unit: byte
+    #[must_use]
+    pub fn ready_bytes(&self) -> usize {
+        let leftover = self.leftover.as_ref().map_or(0, |x| x.len());
+        let rest: usize = if self.ordered {
+            self.recv_buf.sorted_iter().map(|x| x.body.len()).sum()
+        } else {
+            self.arrived.iter().map(|(_, x)| x.len()).sum()
+        };
+        leftover + rest
+    }
+
+    /// Emit the pushes of the next whole message in order, or when unordered, of the first message held whose pushes have all arrived
+    ///
+    /// A message ends at the first push with its `more` flag cleared, so a peer not framing messages sends one-push messages. See `Uploader::write_message`. `None` until the last push of the message is ready.
+    ///
+    /// A message of more pushes than `recv_buf_len` can never be ready. Once its start fills the rwnd, all its pushes are dropped as `DropReason::MessageTooLong` and counted in `Stat::too_long_messages`.
+    ///
+    /// Partial emits like `emit_max` cut through message boundaries, so do not mix them with this.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not built with `messages`.
+    pub fn emit_message(&mut self) -> Result<Option<Vec<BufSlice>>, MessageError> {
+        assert!(self.messages, "Not built with `messages`");
+        if let Some(seq) = self.timed_out_message {
+            return Err(MessageError::ReassemblyTimedOut { seq });
+        }
+        self.skip_message();
+        self.release_delivered();
+        let message = if self.ordered {
+            self.pop_sorted_message()
+        } else {
+            self.pop_complete_message()
+        };
+        // the start of a message fills the rwnd, so the message can never end in it
+        if message.is_none() && self.recv_buf.sorted_len() >= self.recv_buf.capacity() {
+            self.stat.too_long_messages += 1;
+            self.skipping_message = true;
+            self.skip_message();
+        }
+        self.release_delivered();
+        self.check_rep();
+        Ok(message)
+    }
+
+    fn pop_sorted_message(&mut self) -> Option<Vec<BufSlice>> {
+        let last = self.recv_buf.sorted_iter().position(|frag| !frag.more)?;
+        let frags = self.pop_sorted_up_to(last + 1);
+        Some(frags.into_iter().map(|x| x.body).collect())
+    }
+
+    /// Unordered only: the first message held whose pushes have all arrived and that is yet to be emitted
+    ///
+    /// Its pushes stay in `recv_buf` until the gaps ahead of them fill.
+    fn pop_complete_message(&mut self) -> Option<Vec<BufSlice>> {
+        // the first sorted push, or else the start of the rwnd
+        let mut expected = match self.recv_buf.sorted_iter().next() {
+            Some(frag) => frag.seq,
+            None => self.recv_buf.next_seq_to_receive(),
+        };
+        // whether a message starts at `expected`
+        let mut at_boundary = !self.skipping_message;
+        let mut start = None;
+        let mut found = None;
+        for frag in self
+            .recv_buf
+            .sorted_iter()
+            .chain(self.recv_buf.out_of_order_iter())
+        {
+            if frag.seq != expected {
+                start = None;
+                at_boundary = false;
+            }
+            if at_boundary {
+                start = Some(frag.seq);
+            }
+            at_boundary = !frag.more;
+            expected = frag.seq;
+            expected.increment();
+            match start {
+                Some(start) if !frag.more && !self.delivered_ahead.contains(&start) => {
+                    found = Some(start..=frag.seq);
+                    break;
+                }
+                _ => (),
+            }
+        }
+        let range = found?;
+        let frags: Vec<(Seq32, BufSlice)> = self
+            .recv_buf
+            .sorted_iter()
+            .chain(self.recv_buf.out_of_order_iter())
+            .filter(|frag| range.contains(&frag.seq))
+            .map(|frag| (frag.seq, BufSlice::clone(&frag.body)))
+            .collect();
+        self.delivered_ahead
+            .extend(frags.iter().map(|(seq, _)| *seq));
+        Some(frags.into_iter().map(|(_, body)| body).collect())
+    }
+
+    /// The message at the start of the rwnd if held incomplete
+    ///
+    /// Returns the seq past its last push held, whether that is its last push, and when its first push held arrived.
+    fn incomplete_message(&self) -> Option<(Seq32, bool, Instant)> {
+        let mut first_received: Option<Instant> = None;
+        let mut past = None;
+        let mut ended = false;
+        for frag in self.recv_buf.sorted_iter() {
+            // a whole message is ready at the start
+            if !frag.more {
+                return None;
+            }
+            first_received =
+                Some(first_received.map_or(frag.first_received, |x| x.min(frag.first_received)));
+            let mut seq = frag.seq;
+            seq.increment();
+            past = Some(seq);
+        }
+        for frag in self.recv_buf.out_of_order_iter() {
+            first_received =
+                Some(first_received.map_or(frag.first_received, |x| x.min(frag.first_received)));
+            let mut seq = frag.seq;
+            seq.increment();
+            past = Some(seq);
+            if !frag.more {
+                ended = true;
+                break;
+            }
+        }
+        Some((past?, ended, first_received?))
+    }
+
+    /// When `on_timer` should be called next to give up on the message held incomplete under `reassembly_timeout`
+    #[must_use]
+    pub fn reassembly_deadline(&self) -> Option<Instant> {
+        let timeout = self.reassembly_timeout?;
+        if self.timed_out_message.is_some() {
+            return None;
+        }
+        let (_, _, first_received) = self.incomplete_message()?;
+        Some(first_received + timeout)
+    }
+
+    fn expire_message(&mut self, now: &Instant) {
+        match self.reassembly_deadline() {
+            Some(deadline) if deadline <= *now => (),
+            _ => return,
+        }
+        let (past, ended, _) = self.incomplete_message().unwrap();
+        match self.on_reassembly_timeout {
+            OnReassemblyTimeout::Skip => (),
+            OnReassemblyTimeout::Error => {
+                let start = match self.recv_buf.sorted_iter().next() {
+                    Some(frag) => frag.seq,
+                    None => self.recv_buf.next_seq_to_receive(),
+                };
+                self.timed_out_message = Some(start);
+                return;
+            }
+        }
+        let observer = self.download_observer();
+        for frag in self.recv_buf.skip_to(past) {
+            self.buffered_bytes -= frag.body.len();
+            if let Some(observer) = &observer {
+                observer.on_push_dropped(frag.seq, DropReason::ReassemblyTimeout);
+            }
+            self.recycle(frag.body);
+        }
+        self.skipping_message = !ended;
+        self.stat.timed_out_messages += 1;
+    }
+
+    /// Free the rwnd taken by the pushes of the messages already emitted ahead of their order
+    fn release_delivered(&mut self) {
+        loop {
+            let seq = match self.recv_buf.sorted_iter().next() {
+                Some(frag) => frag.seq,
+                None => break,
+            };
+            if !self.delivered_ahead.remove(&seq) {
+                break;
+            }
+            self.pop_sorted();
+        }
+    }
+
+    /// Drop the sorted pushes of a message too long to be ready, up to its last one
+    fn skip_message(&mut self) {
+        let observer = self.download_observer();
+        while self.skipping_message {
+            let frag = match self.recv_buf.pop_front() {
+                Some(x) => x,
+                None => break,
+            };
+            self.buffered_bytes -= frag.body.len();
+            if let Some(observer) = &observer {
+                observer.on_push_dropped(frag.seq, DropReason::MessageTooLong);
+            }
+            self.skipping_message = frag.more;
+            self.recycle(frag.body);
+        }
+    }
+
+    /// Borrow all the bytes ready to be emitted, in order, without copying
+    ///
+    /// Call `consume` to advance past the bytes that have been used.
+    #[must_use]
+    pub fn ready_iovecs(&self) -> Vec<IoSlice<'_>> {
+        let mut iovecs = Vec::with_capacity(self.recv_buf.sorted_len() + 1);
+        if let Some(leftover) = &self.leftover {
+            iovecs.push(IoSlice::new(leftover.data()));
+        }
+        if self.ordered {
+            for frag in self.recv_buf.sorted_iter() {
+                iovecs.push(IoSlice::new(frag.body.data()));
+            }
+        } else {
+            for (_, slice) in &self.arrived {
+                iovecs.push(IoSlice::new(slice.data()));
+            }
+        }
+        iovecs
+    }
+
+    /// Drop the first `len` bytes of those returned by `ready_iovecs`
+    ///
+    /// # Panics
+    ///
+    /// Panics if fewer than `len` bytes are ready.
+    pub fn consume(&mut self, mut len: usize) {
+        while len > 0 {
+            let slice = match self.leftover.take() {
+                Some(x) => x,
+                None => match self.pop_ready() {
+                    Some((_, x)) => x,
+                    None => panic!("Not enough bytes to consume"),
+                },
+            };
+            if len < slice.len() {
+                let (_, tail) = slice.split(len).unwrap();
+                self.leftover = Some(tail);
+                len = 0;
+            } else {
+                len -= slice.len();
+                self.recycle(slice);
+            }
+        }
+        self.check_rep();
+    }
+
     #[must_use]
-    pub fn write(&mut self, mut slice: buf::BufSlice) -> Result<SetUploadState, Error> {
-        let packet = Packet::from_slice(&mut slice).map_err(|_| {
-            self.stat.decoding_errors += 1;
-            self.check_rep();
-            Error::Decoding
-        })?;
-        let packet_state = self.write_packet(packet);
+    pub fn write(&mut self, slice: buf::BufSlice, now: &Instant) -> Result<SetUploadState, Error> {
+        self.write_datagram(slice, false, now)
+    }
+
+    /// Write a packet borrowed from a buffer to be reused, such as the one of a socket
+    ///
+    /// Unlike `write`, the bodies of the pushes are copied out of `datagram`.
+    pub fn write_slice(&mut self, datagram: &[u8], now: &Instant) -> Result<SetUploadState, Error> {
+        let allocator = Arc::clone(&self.frag_allocator);
+        self.write_datagram(
+            buf::BorrowedBufSlice::with_allocator(datagram, allocator.as_ref()),
+            false,
+            now,
+        )
+    }
+
+    /// Write several delimited packets concatenated in one buffer, as from a stream transport
+    ///
+    /// A packet not delimited takes the rest of the buffer. The buffer is split up before any packet is written, so on `Err` nothing is written. A packet failing to decode is dropped as if written alone, counted in `Stat::decoding_errors`.
+    ///
+    /// See `UploaderBuilder::delimited` for the sending side.
+    pub fn write_stream(
+        &mut self,
+        mut rdr: impl buf::BufRdr,
+        now: &Instant,
+    ) -> Result<Vec<SetUploadState>, Error> {
+        let mut packets = Vec::new();
+        while !rdr.is_empty() {
+            let len = PacketHeader::peek_packet_len(rdr.data())
+                .map_err(|e| self.decoding_error(Error::from(e)))?;
+            packets.push(rdr.split_front(len).unwrap());
+        }
+        let mut states = Vec::with_capacity(packets.len());
+        for packet in packets {
+            if let Ok(state) = self.write_datagram(packet, false, now) {
+                states.push(state);
+            }
+        }
+        self.check_rep();
+        Ok(states)
+    }
+
+    /// Hand an emitted slice back for its buffer to be freed to `frag_allocator`
+    ///
+    /// The buffer is dropped as usual while other slices still share it.
+    pub fn recycle(&self, slice: BufSlice) {
+        if let Some(buf) = slice.into_buf() {
+            self.frag_allocator.free(buf);
+        }
+    }
+
+    /// Write a packet whose datagram was marked congestion-experienced by ECN
+    pub fn write_ce_marked(
+        &mut self,
+        slice: buf::BufSlice,
+        now: &Instant,
+    ) -> Result<SetUploadState, Error> {
+        self.write_datagram(slice, true, now)
+    }
+
+    fn write_datagram(
+        &mut self,
+        mut slice: impl buf::BufRdr,
+        ce_marked: bool,
+        now: &Instant,
+    ) -> Result<SetUploadState, Error> {
+        let datagram_len = slice.len();
+        let packet =
+            Packet::from_slice_with(&mut slice, self.max_frags_per_packet, self.legacy_mode)
+                .map_err(|e| self.decoding_error(Error::from(e)))?;
+        let truncated_push = match packet.truncated_push() {
+            Some(push) => {
+                if self.strict_framing {
+                    return Err(self.decoding_error(Error::PushBodyTruncated {
+                        seq: push.seq,
+                        declared_len: push.declared_len,
+                        available: push.available,
+                    }));
+                }
+                // best efforts
+                self.stat.truncated_packets += 1;
+                Some(push)
+            }
+            None => None,
+        };
+        // the fragments beyond the limit are left undecoded
+        if truncated_push.is_none() && !slice.is_empty() {
+            if self.strict_framing {
+                return Err(self.decoding_error(Error::TooManyFrags {
+                    max: self.max_frags_per_packet,
+                }));
+            }
+            // best efforts
+            self.stat.truncated_packets += 1;
+        }
+        if self.strict_framing {
+            if let Some(seq) = push_ack_same_seq(&packet) {
+                return Err(self.decoding_error(Error::PushAckSameSeq { seq }));
+            }
+            if let Some((seq, len)) = oversized_push(&packet, self.max_frag_body_len) {
+                return Err(self.decoding_error(Error::PushBodyTooLong { seq, len }));
+            }
+        }
+        // nothing from a clipped push on is read
+        let bytes_consumed = match truncated_push {
+            Some(push) => push.offset,
+            None => datagram_len - slice.len(),
+        };
+        let truncated_push = truncated_push.map(|push| push.seq);
+        let packet_state = self.write_packet(packet, now);
+        let remote_seqs_to_ack = self.delay_acks(
+            packet_state.frags.remote_seqs_to_ack,
+            packet_state.frags.has_urgent_acks,
+            now,
+        );
         let state = SetUploadState {
             remote_rwnd_size: packet_state.remote_rwnd,
             remote_nack: packet_state.remote_nack,
             local_next_seq_to_receive: self.recv_buf.next_seq_to_receive(),
-            remote_seqs_to_ack: packet_state.frags.remote_seqs_to_ack,
+            remote_seqs_to_ack,
             acked_local_seqs: packet_state.frags.acked_local_seqs,
             local_rwnd_size: self.recv_buf.rwnd_size(),
+            local_recv_buf_bytes_left: self.recv_buf_bytes_left(),
+            local_rwnd_probed: packet_state.frags.local_rwnd_probed,
+            sack_ranges: self.sack_ranges(),
+            ce_marked,
+            remote_ece: packet_state.remote_ece,
+            remote_ts_val: packet_state.remote_timestamps.map(|x| x.ts_val),
+            remote_ts_ecr: packet_state.remote_timestamps.map(|x| x.ts_ecr),
+            remote_pings: packet_state.frags.remote_pings,
+            pongs: packet_state.frags.pongs,
+            reset: packet_state.frags.reset,
+            remote_reset_epoch: packet_state.frags.remote_reset_epoch,
+            reset_acks: packet_state.frags.reset_acks,
+            bytes_consumed,
+            in_order_pushes: packet_state.frags.in_order_pushes,
+            reordered_pushes: packet_state.frags.reordered_pushes,
+            truncated_push,
         };
         self.check_rep();
         Ok(state)
     }
 
+    /// Count and report a packet rejected as a whole
+    fn decoding_error(&mut self, e: Error) -> Error {
+        self.stat.decoding_errors += 1;
+        if let Some(observer) = self.download_observer() {
+            observer.on_decoding_error(&e);
+        }
+        self.check_rep();
+        e
+    }
+
+    /// Return the acks due now, holding back the rest under `delayed_ack`
+    ///
+    /// With `urgent`, some of `seqs` ack pushes not in order, so all go out at once.
+    fn delay_acks(&mut self, seqs: Vec<Seq32>, urgent: bool, now: &Instant) -> Vec<Seq32> {
+        let policy = match &self.delayed_ack {
+            Some(policy) => policy,
+            None => return seqs,
+        };
+        if self.pending_acks.is_empty() && !seqs.is_empty() {
+            self.pending_acks_since = Some(*now);
+        }
+        self.pending_acks.extend(seqs);
+        if urgent || self.pending_acks.len() >= policy.max_unacked {
+            return self.take_pending_acks();
+        }
+        self.on_timer(now)
+    }
+
+    /// Number of acks held back by `delayed_ack`
+    #[must_use]
+    pub fn pending_acks(&self) -> usize {
+        self.pending_acks.len()
+    }
+
+    /// When `on_timer` should be called next for the pending acks to go out in time
     #[must_use]
-    fn write_packet(&mut self, packet: Packet) -> PacketState {
+    pub fn ack_deadline(&self) -> Option<Instant> {
+        let policy = self.delayed_ack.as_ref()?;
+        self.pending_acks_since
+            .map(|since| since + policy.max_delay)
+    }
+
+    /// Return the pending acks if the oldest of them has been held for `max_delay`
+    ///
+    /// The returned seqs should be scheduled by `Uploader::queue_ack`. A message held past `reassembly_timeout` is given up on as well.
+    #[must_use]
+    pub fn on_timer(&mut self, now: &Instant) -> Vec<Seq32> {
+        self.expire_message(now);
+        let acks = match self.ack_deadline() {
+            Some(deadline) if deadline <= *now => self.take_pending_acks(),
+            _ => Vec::new(),
+        };
+        self.check_rep();
+        acks
+    }
+
+    fn take_pending_acks(&mut self) -> Vec<Seq32> {
+        self.pending_acks_since = None;
+        std::mem::take(&mut self.pending_acks)
+    }
+
+    #[must_use]
+    fn write_packet(&mut self, packet: Packet, now: &Instant) -> PacketState {
+        self.stat.corrupted_frags += packet.corrupted_frags() as u64;
+        self.stat.unknown_frags += packet.unknown_frags() as u64;
+        let format = packet.hdr().format();
+        if format == PacketFormat::Legacy {
+            self.stat.legacy_packets += 1;
+        }
+        if let Some(observer) = self.download_observer() {
+            observer.on_packet(format);
+        }
         let packet = packet.into_builder();
-        let frags_state = self.write_frags(packet.frags);
+        let frags_state = self.write_frags(packet.frags, now);
         let state = PacketState {
             frags: frags_state,
-            remote_rwnd: packet.hdr.rwnd(),
+            remote_rwnd: packet.hdr.scaled_rwnd(),
             remote_nack: packet.hdr.nack(),
+            remote_ece: packet.hdr.ece(),
+            remote_timestamps: packet.hdr.timestamps(),
         };
         self.stat.packets += 1;
         self.check_rep();
@@ -140,9 +1123,29 @@ impl Downloader {
     }
 
     #[must_use]
-    fn write_frags(&mut self, frags: Vec<Frag>) -> FragsState {
-        let mut remote_seqs_to_ack = Vec::new();
-        let mut acked_local_seqs = Vec::new();
+    fn write_frags(&mut self, frags: Vec<Frag>, now: &Instant) -> FragsState {
+        // sized exactly, so a packet without acks, as in a bulk transfer, allocates nothing for them
+        let pushes = frags
+            .iter()
+            .filter(|frag| matches!(frag.cmd(), FragCommand::Push { .. }))
+            .count();
+        let acks = frags
+            .iter()
+            .filter(|frag| matches!(frag.cmd(), FragCommand::Ack))
+            .count();
+        let mut remote_seqs_to_ack = Vec::with_capacity(pushes);
+        let mut has_urgent_acks = false;
+        let mut acked_local_seqs = Vec::with_capacity(acks);
+        let mut local_rwnd_probed = false;
+        let mut remote_pings = Vec::new();
+        let mut pongs = Vec::new();
+        let mut reset = false;
+        let mut remote_reset_epoch = None;
+        let mut reset_acks = Vec::new();
+        let mut in_order_pushes = 0;
+        let mut reordered_pushes = 0;
+        // upgraded once per packet; nothing is reported without an observer
+        let observer = self.download_observer();
         for frag in frags {
             let frag = frag.into_builder();
             match frag.cmd {
@@ -151,27 +1154,134 @@ impl Downloader {
                         Body::Slice(x) => x,
                         Body::Pasta(_) => panic!(),
                     };
+                    if body.len() > self.max_frag_body_len {
+                        if let Some(observer) = &observer {
+                            observer.on_push_dropped(frag.seq, DropReason::Oversized);
+                        }
+                        self.stat.oversized_pushes += 1;
+                        self.stat.pushes += 1;
+                        // drop the fragment
+                        continue;
+                    }
+                    let len = body.len();
+                    let replaced_len = self
+                        .recv_buf
+                        .get_out_of_order(frag.seq)
+                        .map(|x| x.body.len());
+                    let is_over_budget = match self.recv_buf.location(frag.seq) {
+                        SeqLocationToRwnd::InRecvWindow => {
+                            self.buffered_bytes - replaced_len.unwrap_or(0) + len
+                                > self.recv_buf_bytes
+                        }
+                        SeqLocationToRwnd::AtRecvWindowStart
+                        | SeqLocationToRwnd::TooLate
+                        | SeqLocationToRwnd::TooEarly => false,
+                    };
+                    if is_over_budget
+                        && !(self.is_evictable()
+                            && self.evict_after(frag.seq, len, replaced_len.unwrap_or(0)))
+                    {
+                        // like a push beyond the rwnd, the peer should learn the current rwnd at once
+                        local_rwnd_probed = true;
+                        if let Some(observer) = &observer {
+                            observer.on_push_dropped(frag.seq, DropReason::OverBudget);
+                        }
+                        self.stat.over_budget_pushes += 1;
+                        self.stat.pushes += 1;
+                        // drop the fragment
+                        continue;
+                    }
+                    let body = RecvFrag {
+                        seq: frag.seq,
+                        first_received: *now,
+                        body,
+                        more: frag.more,
+                    };
+                    let sorted_len = self.recv_buf.sorted_len();
+                    let out_of_order_len = self.recv_buf.out_of_order_len();
+                    let arrived = (!self.ordered && !self.messages)
+                        .then(|| (frag.seq, BufSlice::clone(&body.body)));
                     // if out of rwnd
                     let location = self.recv_buf.insert(frag.seq, body);
+                    self.notify_delivered(sorted_len);
+                    let is_duplicate = match location {
+                        // a duplicate replaces the one in the rwnd
+                        SeqLocationToRwnd::InRecvWindow => {
+                            self.recv_buf.out_of_order_len() == out_of_order_len
+                        }
+                        // already delivered
+                        SeqLocationToRwnd::TooLate => true,
+                        SeqLocationToRwnd::AtRecvWindowStart | SeqLocationToRwnd::TooEarly => false,
+                    };
+                    if let Some(arrived) = arrived {
+                        let is_in_rwnd = matches!(
+                            location,
+                            SeqLocationToRwnd::AtRecvWindowStart | SeqLocationToRwnd::InRecvWindow
+                        );
+                        if is_in_rwnd && !is_duplicate {
+                            self.arrived.push_back(arrived);
+                        }
+                        self.release_consumed();
+                    }
+                    if let SeqLocationToRwnd::AtRecvWindowStart | SeqLocationToRwnd::InRecvWindow =
+                        location
+                    {
+                        self.buffered_bytes = self.buffered_bytes - replaced_len.unwrap_or(0) + len;
+                    }
+                    if is_duplicate {
+                        self.stat.duplicate_pushes += 1;
+                    }
+                    if let Some(observer) = &observer {
+                        match location {
+                            SeqLocationToRwnd::TooLate => {
+                                observer.on_push_dropped(frag.seq, DropReason::TooLate)
+                            }
+                            SeqLocationToRwnd::TooEarly => {
+                                observer.on_push_dropped(frag.seq, DropReason::TooEarly)
+                            }
+                            SeqLocationToRwnd::InRecvWindow if is_duplicate => {
+                                observer.on_push_dropped(frag.seq, DropReason::Duplicate)
+                            }
+                            SeqLocationToRwnd::InRecvWindow
+                            | SeqLocationToRwnd::AtRecvWindowStart => {
+                                observer.on_push_accepted(frag.seq)
+                            }
+                        }
+                    }
                     match location {
                         SeqLocationToRwnd::InRecvWindow => {
-                            // schedule uploader to ack this seq
-                            remote_seqs_to_ack.push(frag.seq);
+                            // schedule uploader to ack this seq, unless it may yet be evicted
+                            if !self.is_evictable() {
+                                remote_seqs_to_ack.push(frag.seq);
+                                has_urgent_acks = true;
+                            }
+
+                            reordered_pushes += 1;
 
-                            self.stat.out_of_orders += 1;
+                            if !is_duplicate {
+                                self.stat.out_of_orders += 1;
+                            }
                         }
                         SeqLocationToRwnd::AtRecvWindowStart => {
                             // schedule uploader to ack this seq
                             remote_seqs_to_ack.push(frag.seq);
+
+                            in_order_pushes += 1;
+
+                            self.stat.in_orders += 1;
                         }
                         SeqLocationToRwnd::TooLate => {
                             // schedule uploader to ack this seq
                             remote_seqs_to_ack.push(frag.seq);
+                            has_urgent_acks = true;
 
                             self.stat.late_pushes += 1;
                             // drop the fragment
                         }
                         SeqLocationToRwnd::TooEarly => {
+                            // the peer is probing a rwnd it believes to be larger
+                            local_rwnd_probed = true;
+
                             self.stat.early_pushes += 1;
                             // drop the fragment
                         }
@@ -179,35 +1289,168 @@ impl Downloader {
                     self.stat.pushes += 1;
                 }
                 FragCommand::Ack => {
+                    if let Some(observer) = &observer {
+                        observer.on_ack(frag.seq);
+                    }
                     acked_local_seqs.push(frag.seq);
                     self.stat.acks += 1;
                 }
+                FragCommand::Ping => remote_pings.push(frag.seq),
+                FragCommand::Pong => pongs.push(frag.seq),
+                FragCommand::Reset { epoch } => {
+                    // a replay or a stale reset must not discard what has arrived since
+                    let is_newer = match self.last_reset_epoch {
+                        Some(last) => epoch > last,
+                        None => true,
+                    };
+                    if is_newer {
+                        self.reset(frag.seq);
+                        self.last_reset_epoch = Some(epoch);
+                        // the acks so far belong to the old sequence space
+                        remote_seqs_to_ack.clear();
+                        reset = true;
+                    }
+                    // acknowledged even if not taken, for the `ResetAck` before may be lost
+                    remote_reset_epoch = Some(epoch);
+                }
+                FragCommand::ResetAck => reset_acks.push(frag.seq),
             }
         }
         self.check_rep();
         FragsState {
             remote_seqs_to_ack,
+            has_urgent_acks,
             acked_local_seqs,
+            local_rwnd_probed,
+            remote_pings,
+            pongs,
+            reset,
+            remote_reset_epoch,
+            reset_acks,
+            in_order_pushes,
+            reordered_pushes,
+        }
+    }
+}
+
+impl Downloader {
+    /// Evict the pushes held out of order beyond `seq`, furthest first, until a push of `len` bytes replacing `replaced_len` ones fits in `recv_buf_bytes`
+    ///
+    /// Nothing is evicted and `false` is returned if evicting them all would not make room.
+    #[must_use]
+    fn evict_after(&mut self, seq: Seq32, len: usize, replaced_len: usize) -> bool {
+        let evictable_bytes: usize = self
+            .recv_buf
+            .out_of_order_iter_after(seq)
+            .map(|x| x.body.len())
+            .sum();
+        if self.buffered_bytes - evictable_bytes - replaced_len + len > self.recv_buf_bytes {
+            return false;
+        }
+        let observer = self.download_observer();
+        while self.buffered_bytes - replaced_len + len > self.recv_buf_bytes {
+            let frag = self.recv_buf.pop_out_of_order_after(seq).unwrap();
+            if let Some(observer) = &observer {
+                observer.on_push_dropped(frag.seq, DropReason::Evicted);
+            }
+            self.buffered_bytes -= frag.body.len();
+            self.stat.evicted_pushes += 1;
+        }
+        true
+    }
+
+    /// Pushes held out of order may give way under `OnFull::DropFurthest`
+    fn is_evictable(&self) -> bool {
+        self.ordered && self.on_full == OnFull::DropFurthest
+    }
+
+    fn download_observer(&self) -> Option<Arc<dyn IDownloadObserver + Send + Sync + 'static>> {
+        self.on_download_event.as_ref().and_then(|x| x.upgrade())
+    }
+
+    /// Notify the observer of the fragments sorted after the first `sorted_len` ones
+    fn notify_delivered(&self, sorted_len: usize) {
+        let observer = match self.on_delivered.as_ref().and_then(|x| x.upgrade()) {
+            Some(x) => x,
+            None => return,
+        };
+        for frag in self.recv_buf.sorted_iter().skip(sorted_len) {
+            observer.notify(frag.seq, frag.first_received);
         }
     }
 }
 
+/// The first sequence both pushed and acked in `packet`
+fn push_ack_same_seq(packet: &Packet) -> Option<Seq32> {
+    let acked: HashSet<Seq32> = packet
+        .frags()
+        .iter()
+        .filter(|frag| matches!(frag.cmd(), FragCommand::Ack))
+        .map(|frag| frag.seq())
+        .collect();
+    packet
+        .frags()
+        .iter()
+        .filter(|frag| matches!(frag.cmd(), FragCommand::Push { .. }))
+        .map(|frag| frag.seq())
+        .find(|seq| acked.contains(seq))
+}
+
+/// The first push in `packet` whose body is longer than `max_len`
+fn oversized_push(packet: &Packet, max_len: usize) -> Option<(Seq32, usize)> {
+    packet.frags().iter().find_map(|frag| match frag.cmd() {
+        FragCommand::Push { body } if body.len() > max_len => Some((frag.seq(), body.len())),
+        _ => None,
+    })
+}
+
+struct RecvFrag {
+    seq: Seq32,
+    first_received: Instant,
+    body: BufSlice,
+    more: bool,
+}
+
 struct FragsState {
     remote_seqs_to_ack: Vec<Seq32>,
+    /// Some of `remote_seqs_to_ack` ack pushes not in order
+    has_urgent_acks: bool,
     acked_local_seqs: Vec<Seq32>,
+    local_rwnd_probed: bool,
+    remote_pings: Vec<Seq32>,
+    pongs: Vec<Seq32>,
+    reset: bool,
+    remote_reset_epoch: Option<Seq32>,
+    reset_acks: Vec<Seq32>,
+    in_order_pushes: usize,
+    reordered_pushes: usize,
 }
 
 struct PacketState {
     frags: FragsState,
-    remote_rwnd: u16,
+    remote_rwnd: u32,
     remote_nack: Seq32,
+    remote_ece: bool,
+    remote_timestamps: Option<Timestamps>,
 }
 
+#[derive(Default)]
 struct LocalStat {
+    in_orders: u64,
     late_pushes: u64,
+    duplicate_pushes: u64,
     early_pushes: u64,
     out_of_orders: u64,
     decoding_errors: u64,
+    corrupted_frags: u64,
+    unknown_frags: u64,
+    oversized_pushes: u64,
+    over_budget_pushes: u64,
+    evicted_pushes: u64,
+    truncated_packets: u64,
+    legacy_packets: u64,
+    too_long_messages: u64,
+    timed_out_messages: u64,
     packets: u64,
     acks: u64,
     pushes: u64,
@@ -215,10 +1458,33 @@ struct LocalStat {
 
 #[derive(Debug, PartialEq)]
 pub struct Stat {
+    /// Pushes landing at the start of the rwnd
+    pub in_orders: u64,
     pub late_pushes: u64,
+    /// Pushes of a seq already received, whether delivered or held out of order
+    ///
+    /// They are still acked for the peer to stop retransmitting them.
+    pub duplicate_pushes: u64,
     pub early_pushes: u64,
     pub out_of_orders: u64,
     pub decoding_errors: u64,
+    pub corrupted_frags: u64,
+    /// Fragments of commands unknown to this version, skipped
+    pub unknown_frags: u64,
+    /// Pushes dropped for exceeding `max_frag_body_len`
+    pub oversized_pushes: u64,
+    /// Pushes held out of order dropped for exceeding `recv_buf_bytes`
+    pub over_budget_pushes: u64,
+    /// Pushes held out of order evicted under `OnFull::DropFurthest` to make room for one nearer
+    pub evicted_pushes: u64,
+    /// Packets whose fragments beyond `max_frags_per_packet` were left undecoded, or whose last push was clipped
+    pub truncated_packets: u64,
+    /// Packets decoded as `PacketFormat::Legacy` under `legacy_mode`
+    pub legacy_packets: u64,
+    /// Messages dropped for having more pushes than `recv_buf_len`; see `Downloader::emit_message`
+    pub too_long_messages: u64,
+    /// Messages skipped under `OnReassemblyTimeout::Skip`
+    pub timed_out_messages: u64,
     pub next_seq_to_receive: Seq32,
     pub packets: u64,
     pub acks: u64,
@@ -227,37 +1493,496 @@ pub struct Stat {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use proptest::prelude::*;
+
     use crate::{
+        layer::{DropReason, IDeliveryObserver, IDownloadObserver},
         protocol::{
-            frag::{Body, FragBuilder, FragCommand},
+            frag::{Body, Frag, FragBuilder, FragCommand, ACK_HDR_LEN, PUSH_HDR_LEN},
             packet::PacketBuilder,
-            packet_hdr::PacketHeaderBuilder,
+            packet_hdr::{PacketHeaderBuilder, CURRENT_VERSION, PACKET_HDR_LEN},
         },
         utils::{
-            buf::{BufSlice, OwnedBufWtr},
+            buf::{BorrowedBufSlice, BufSlice, BufWtr, OwnedBufWtr},
             Seq32,
         },
     };
 
-    use super::DownloaderBuilder;
+    use super::{
+        BuildError, DecodingError, DelayedAckPolicy, Downloader, DownloaderBuilder, Error,
+        FragAllocator, MessageError, OnFull, OnReassemblyTimeout, SetRecvBufLenError,
+        MAX_SCALED_RWND,
+    };
+
+    fn push_packet(frags: Vec<(u32, Vec<u8>)>) -> BufSlice {
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: frags
+                .into_iter()
+                .map(|(seq, body)| {
+                    FragBuilder {
+                        seq: Seq32::from_u32(seq),
+                        cmd: FragCommand::Push {
+                            body: Body::Slice(BufSlice::from_bytes(body)),
+                        },
+                        checksum: false,
+                        more: false,
+                    }
+                    .build()
+                    .unwrap()
+                })
+                .collect(),
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet.append_to(&mut wtr).unwrap();
+        wtr.into_slice()
+    }
+
+    fn push_frag(seq: u32, body: Vec<u8>) -> Frag {
+        FragBuilder {
+            seq: Seq32::from_u32(seq),
+            cmd: FragCommand::Push {
+                body: Body::Slice(BufSlice::from_bytes(body)),
+            },
+            checksum: false,
+            more: false,
+        }
+        .build()
+        .unwrap()
+    }
+
+    /// Bodies of `1..=max_len` bytes each, and the order to write them in, duplicates included, covering every one of them
+    fn pushes_and_schedule(
+        max_pushes: usize,
+        max_len: usize,
+    ) -> impl Strategy<Value = (Vec<Vec<u8>>, Vec<usize>)> {
+        prop::collection::vec(
+            prop::collection::vec(any::<u8>(), 1..=max_len),
+            1..=max_pushes,
+        )
+        .prop_flat_map(|bodies| {
+            let n = bodies.len();
+            let all = Just((0..n).collect::<Vec<_>>()).prop_shuffle();
+            let duplicates = prop::collection::vec(0..n, 0..n);
+            (Just(bodies), all, duplicates)
+        })
+        .prop_flat_map(|(bodies, all, duplicates)| {
+            let mut schedule = all;
+            schedule.extend(duplicates);
+            (Just(bodies), Just(schedule).prop_shuffle())
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_reassembly(
+            (bodies, schedule) in pushes_and_schedule(32, 8),
+            emit_between in prop::collection::vec(any::<bool>(), 64),
+        ) {
+            let mut downloader = DownloaderBuilder {
+                recv_buf_len: bodies.len(),
+                ..Default::default()
+            }
+            .build()
+            .unwrap();
+            let now = Instant::now();
+
+            let mut delivered = Vec::new();
+            for (i, &index) in schedule.iter().enumerate() {
+                let frag = push_frag(index as u32, bodies[index].clone());
+                let _ = downloader.write_frags(vec![frag], &now);
+                if emit_between[i % emit_between.len()] {
+                    for slice in downloader.emit_all() {
+                        delivered.extend_from_slice(slice.data());
+                    }
+                }
+            }
+            for slice in downloader.emit_all() {
+                delivered.extend_from_slice(slice.data());
+            }
+
+            prop_assert_eq!(delivered, bodies.concat());
+            prop_assert_eq!(downloader.next_seq_to_receive(), Seq32::from_u32(bodies.len() as u32));
+            prop_assert_eq!(downloader.buffered_bytes(), 0);
+        }
+    }
+
+    struct DeliveryRecorder {
+        delivered: Mutex<Vec<(Seq32, Instant)>>,
+    }
+
+    impl IDeliveryObserver for DeliveryRecorder {
+        fn notify(&self, seq: Seq32, first_received: Instant) {
+            self.delivered.lock().unwrap().push((seq, first_received));
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum DownloadEvent {
+        PushAccepted(Seq32),
+        PushDropped(Seq32, DropReason),
+        Ack(Seq32),
+        DecodingError(Error),
+    }
+
+    struct DownloadEventRecorder {
+        events: Mutex<Vec<DownloadEvent>>,
+    }
+
+    impl IDownloadObserver for DownloadEventRecorder {
+        fn on_push_accepted(&self, seq: Seq32) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(DownloadEvent::PushAccepted(seq));
+        }
+
+        fn on_push_dropped(&self, seq: Seq32, reason: DropReason) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(DownloadEvent::PushDropped(seq, reason));
+        }
+
+        fn on_ack(&self, seq: Seq32) {
+            self.events.lock().unwrap().push(DownloadEvent::Ack(seq));
+        }
+
+        fn on_decoding_error(&self, error: &Error) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(DownloadEvent::DecodingError(error.clone()));
+        }
+    }
 
     #[test]
     fn test_empty() {
-        let mut download = DownloaderBuilder { recv_buf_len: 3 }.build().unwrap();
+        let mut download = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
 
         let origin1 = vec![];
         let slice = BufSlice::from_bytes(origin1);
-        let changes = download.write(slice);
+        let changes = download.write(slice, &Instant::now());
         assert!(changes.is_err());
     }
 
+    #[test]
+    fn test_occupancy() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        downloader
+            .write(push_packet(vec![(2, vec![2])]), &Instant::now())
+            .unwrap();
+        assert_eq!(downloader.out_of_order_len(), 1);
+        assert_eq!(downloader.sorted_len(), 0);
+        assert_eq!(downloader.next_seq_to_receive(), Seq32::from_u32(0));
+        assert!(!downloader.is_rwnd_full());
+
+        downloader
+            .write(
+                push_packet(vec![(0, vec![0]), (1, vec![1])]),
+                &Instant::now(),
+            )
+            .unwrap();
+        assert_eq!(downloader.out_of_order_len(), 0);
+        assert_eq!(downloader.sorted_len(), 3);
+        assert_eq!(downloader.next_seq_to_receive(), Seq32::from_u32(3));
+        assert!(downloader.is_rwnd_full());
+
+        assert_eq!(downloader.emit().unwrap().data(), vec![0]);
+        assert_eq!(downloader.sorted_len(), 2);
+        assert!(!downloader.is_rwnd_full());
+    }
+
+    #[test]
+    fn test_emit_all() {
+        let frags = vec![(0, vec![0, 1]), (1, vec![2]), (2, vec![3, 4])];
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        downloader
+            .write(push_packet(frags), &Instant::now())
+            .unwrap();
+        assert!(downloader.is_rwnd_full());
+
+        assert_eq!(downloader.emit_max(1).unwrap().data(), vec![0]);
+        let slices = downloader.emit_up_to(2);
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[0].data(), vec![1]);
+        assert_eq!(slices[1].data(), vec![2]);
+        assert_eq!(downloader.sorted_len(), 1);
+
+        let slices = downloader.emit_all();
+        assert_eq!(slices.len(), 1);
+        assert_eq!(slices[0].data(), vec![3, 4]);
+        assert!(downloader.emit_all().is_empty());
+        assert_eq!(downloader.stat().next_seq_to_receive, Seq32::from_u32(3));
+        assert!(!downloader.is_rwnd_full());
+    }
+
+    #[test]
+    fn test_emit_coalesced() {
+        let frags = vec![(0, vec![0, 1]), (1, vec![2]), (2, vec![3, 4])];
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        downloader
+            .write(push_packet(frags), &Instant::now())
+            .unwrap();
+
+        assert_eq!(downloader.emit_coalesced(4), vec![0, 1, 2, 3]);
+        assert_eq!(downloader.emit_coalesced(0), vec![]);
+        assert_eq!(downloader.emit_coalesced(10), vec![4]);
+        assert_eq!(downloader.emit_coalesced(10), vec![]);
+    }
+
+    /// `cargo test --release -- --ignored bench_write_emit --nocapture`, with and without `--features paranoid`
+    #[test]
+    #[ignore]
+    fn bench_write_emit() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 32,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let rounds = 10_000;
+        let start = Instant::now();
+        let mut seq = 0;
+        for _ in 0..rounds {
+            let frags = (seq..seq + 32)
+                .map(|seq| (seq, vec![seq as u8; 16]))
+                .collect();
+            downloader.write(push_packet(frags), &start).unwrap();
+            seq += 32;
+            while let Some(slice) = downloader.emit() {
+                assert_eq!(slice.len(), 16);
+            }
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{} pushes in {:?}, {:?} per push",
+            rounds * 32,
+            elapsed,
+            elapsed / (rounds * 32)
+        );
+        assert_eq!(downloader.nack(), Seq32::from_u32(seq));
+    }
+
+    #[test]
+    fn test_emit_exact() {
+        let frags = vec![(0, vec![0, 1]), (1, vec![2]), (2, vec![3, 4])];
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 4,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        downloader
+            .write(push_packet(frags), &Instant::now())
+            .unwrap();
+        assert_eq!(downloader.ready_bytes(), 5);
+
+        // more than `n` ready: the rest of the boundary fragment stays
+        assert_eq!(downloader.emit_exact(4), Some(vec![0, 1, 2, 3]));
+        assert_eq!(downloader.ready_bytes(), 1);
+
+        // fewer than `n` ready: nothing is emitted
+        assert_eq!(downloader.emit_exact(2), None);
+        assert_eq!(downloader.ready_bytes(), 1);
+        assert_eq!(downloader.sorted_len(), 0);
+
+        downloader
+            .write(push_packet(vec![(3, vec![5, 6])]), &Instant::now())
+            .unwrap();
+        // exactly `n` ready
+        assert_eq!(downloader.emit_exact(3), Some(vec![4, 5, 6]));
+        assert_eq!(downloader.ready_bytes(), 0);
+        assert_eq!(downloader.emit_exact(0), Some(vec![]));
+        assert_eq!(downloader.emit_exact(1), None);
+    }
+
+    #[test]
+    fn test_shrink_recv_buf() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 4,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        downloader
+            .write(
+                push_packet(vec![(0, vec![0]), (2, vec![2])]),
+                &Instant::now(),
+            )
+            .unwrap();
+
+        downloader.set_recv_buf_len(1).unwrap();
+        assert_eq!(downloader.out_of_order_len(), 1);
+        assert!(downloader.is_rwnd_full());
+
+        let state = downloader
+            .write(push_packet(vec![(1, vec![1])]), &Instant::now())
+            .unwrap();
+        assert_eq!(state.local_rwnd_size, 0);
+        assert!(state.local_rwnd_probed);
+
+        assert_eq!(downloader.emit().unwrap().data(), vec![0]);
+        let state = downloader
+            .write(push_packet(vec![(1, vec![1])]), &Instant::now())
+            .unwrap();
+        // seq(2) held out of order is not evicted
+        assert_eq!(state.local_next_seq_to_receive.to_u32(), 3);
+        assert_eq!(state.local_rwnd_size, 0);
+        assert_eq!(downloader.emit_coalesced(10), vec![1, 2]);
+        assert_eq!(downloader.recv_buf.rwnd_size(), 1);
+    }
+
+    #[test]
+    fn test_grow_recv_buf() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 1,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        downloader
+            .write(push_packet(vec![(0, vec![0])]), &Instant::now())
+            .unwrap();
+        assert!(downloader.is_rwnd_full());
+
+        downloader.set_recv_buf_len(3).unwrap();
+        let state = downloader
+            .write(push_packet(vec![(2, vec![2])]), &Instant::now())
+            .unwrap();
+        assert_eq!(state.local_rwnd_size, 2);
+        assert_eq!(downloader.out_of_order_len(), 1);
+    }
+
+    #[test]
+    fn test_set_recv_buf_len_bounds() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 1,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            downloader.set_recv_buf_len(0),
+            Err(SetRecvBufLenError::RecvBufEmpty)
+        );
+        assert_eq!(
+            downloader.set_recv_buf_len(MAX_SCALED_RWND + 1),
+            Err(SetRecvBufLenError::RecvBufTooLarge)
+        );
+        assert_eq!(downloader.recv_buf.capacity(), 1);
+
+        downloader.set_recv_buf_len(MAX_SCALED_RWND).unwrap();
+        assert_eq!(downloader.recv_buf.capacity(), MAX_SCALED_RWND);
+        assert_eq!(downloader.recv_buf.rwnd_size(), MAX_SCALED_RWND);
+    }
+
+    #[test]
+    fn test_decoding_errors() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        let slice = BufSlice::from_bytes(vec![CURRENT_VERSION, 0, 2, 0]);
+        let e = downloader.write(slice, &Instant::now()).err().unwrap();
+        assert_eq!(e, Error::PacketHeaderTooShort);
+
+        let slice = BufSlice::from_bytes(vec![CURRENT_VERSION + 1, 0, 2, 0, 0, 0, 0, 0]);
+        let e = downloader.write(slice, &Instant::now()).err().unwrap();
+        assert_eq!(
+            e,
+            Error::VersionMismatch {
+                version: CURRENT_VERSION + 1
+            }
+        );
+
+        // [packet_header] [push_hdr seq(0)] [3] [seq(1)] [cmd(9)]
+        // an unknown command without its len
+        let mut data = push_packet(vec![(0, vec![0, 1, 2])]).data().to_vec();
+        data.extend_from_slice(&[0, 0, 0, 1, 9]);
+        let e = downloader
+            .write(BufSlice::from_bytes(data), &Instant::now())
+            .err()
+            .unwrap();
+        assert_eq!(
+            e,
+            Error::FragHeaderInvalid {
+                offset: PACKET_HDR_LEN + 12 + 5
+            }
+        );
+
+        // [packet_header] [push_hdr seq(0)] [2 of 3]
+        // best efforts: the clipped push is dropped, not the packet
+        let mut data = push_packet(vec![(0, vec![0, 1, 2])]).data().to_vec();
+        data.pop();
+        let changes = downloader
+            .write(BufSlice::from_bytes(data), &Instant::now())
+            .unwrap();
+        assert_eq!(changes.truncated_push, Some(Seq32::from_u32(0)));
+        assert_eq!(downloader.stat().decoding_errors, 3);
+    }
+
     #[test]
     fn test_few_1() {
-        let mut downloader = DownloaderBuilder { recv_buf_len: 3 }.build().unwrap();
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
 
         let packet = PacketBuilder {
             hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
                 rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
                 nack: Seq32::from_u32(0),
             }
             .build()
@@ -267,6 +1992,8 @@ mod tests {
                 cmd: FragCommand::Push {
                     body: Body::Slice(BufSlice::from_bytes(vec![4; 11])),
                 },
+                checksum: false,
+                more: false,
             }
             .build()
             .unwrap()],
@@ -279,7 +2006,7 @@ mod tests {
         let mut wtr = OwnedBufWtr::new(1024, 0);
         packet.append_to(&mut wtr).unwrap();
         let slice = wtr.into_slice();
-        let state = downloader.write(slice).unwrap();
+        let state = downloader.write(slice, &Instant::now()).unwrap();
         assert_eq!(state.local_next_seq_to_receive.to_u32(), 1);
         assert_eq!(state.local_rwnd_size, 2);
         assert_eq!(state.remote_nack.to_u32(), 0);
@@ -292,11 +2019,22 @@ mod tests {
 
     #[test]
     fn test_out_of_order() {
-        let mut downloader = DownloaderBuilder { recv_buf_len: 3 }.build().unwrap();
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
 
         let packet = PacketBuilder {
             hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
                 rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
                 nack: Seq32::from_u32(0),
             }
             .build()
@@ -306,6 +2044,8 @@ mod tests {
                 cmd: FragCommand::Push {
                     body: Body::Slice(BufSlice::from_bytes(vec![4; 11])),
                 },
+                checksum: false,
+                more: false,
             }
             .build()
             .unwrap()],
@@ -318,7 +2058,7 @@ mod tests {
         let mut wtr = OwnedBufWtr::new(1024, 0);
         packet.append_to(&mut wtr).unwrap();
         let slice = wtr.into_slice();
-        let state = downloader.write(slice).unwrap();
+        let state = downloader.write(slice, &Instant::now()).unwrap();
         assert_eq!(state.local_next_seq_to_receive.to_u32(), 0);
         assert_eq!(state.local_rwnd_size, 3);
         assert_eq!(state.remote_nack.to_u32(), 0);
@@ -330,82 +2070,571 @@ mod tests {
     }
 
     #[test]
-    fn test_out_of_window1() {
-        let mut downloader = DownloaderBuilder { recv_buf_len: 3 }.build().unwrap();
+    fn test_no_allocation_for_absent_acks() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let state = downloader
+            .write(
+                push_packet(vec![(0, vec![0]), (1, vec![1])]),
+                &Instant::now(),
+            )
+            .unwrap();
+        assert_eq!(state.remote_seqs_to_ack.capacity(), 2);
+        assert_eq!(state.acked_local_seqs.capacity(), 0);
+        assert_eq!(state.sack_ranges.capacity(), 0);
+        assert_eq!(state.remote_pings.capacity(), 0);
+        assert_eq!(state.pongs.capacity(), 0);
+    }
 
-        let packet = PacketBuilder {
-            hdr: PacketHeaderBuilder {
-                rwnd: 2,
-                nack: Seq32::from_u32(0),
-            }
-            .build()
-            .unwrap(),
-            frags: vec![FragBuilder {
-                seq: Seq32::from_u32(99),
-                cmd: FragCommand::Push {
-                    body: Body::Slice(BufSlice::from_bytes(vec![4; 11])),
-                },
-            }
-            .build()
-            .unwrap()],
+    #[test]
+    fn test_duplicate_pushes() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
         }
         .build()
         .unwrap();
+        let now = Instant::now();
 
-        let mut wtr = OwnedBufWtr::new(1024, 0);
-        packet.append_to(&mut wtr).unwrap();
-        let slice = wtr.into_slice();
-        let state = downloader.write(slice).unwrap();
-        assert_eq!(state.local_next_seq_to_receive.to_u32(), 0);
-        assert_eq!(state.local_rwnd_size, 3);
-        assert_eq!(state.remote_nack.to_u32(), 0);
-        assert_eq!(state.remote_rwnd_size, 2);
-        assert_eq!(state.remote_seqs_to_ack, vec![]);
-        assert_eq!(state.acked_local_seqs, vec![]);
+        downloader
+            .write(push_packet(vec![(0, vec![0]), (2, vec![2])]), &now)
+            .unwrap();
+        assert_eq!(downloader.emit().unwrap().data(), vec![0]);
+
+        // seq(0) delivered and seq(2) held out of order
+        let state = downloader
+            .write(push_packet(vec![(0, vec![0]), (2, vec![2])]), &now)
+            .unwrap();
+        assert_eq!(
+            state.remote_seqs_to_ack,
+            vec![Seq32::from_u32(0), Seq32::from_u32(2)]
+        );
+        let stat = downloader.stat();
+        assert_eq!(stat.duplicate_pushes, 2);
+        assert_eq!(stat.late_pushes, 1);
+        assert_eq!(stat.out_of_orders, 1);
+        assert_eq!(stat.pushes, 4);
         assert!(downloader.emit().is_none());
     }
 
     #[test]
-    fn test_ack() {
-        let mut download = DownloaderBuilder { recv_buf_len: 3 }.build().unwrap();
-
-        let packet = PacketBuilder {
-            hdr: PacketHeaderBuilder {
-                rwnd: 2,
-                nack: Seq32::from_u32(0),
-            }
-            .build()
-            .unwrap(),
-            frags: vec![
-                FragBuilder {
-                    seq: Seq32::from_u32(1),
-                    cmd: FragCommand::Ack,
-                }
-                .build()
-                .unwrap(),
-                FragBuilder {
-                    seq: Seq32::from_u32(3),
-                    cmd: FragCommand::Ack,
-                }
-                .build()
-                .unwrap(),
-                FragBuilder {
-                    seq: Seq32::from_u32(99),
-                    cmd: FragCommand::Push {
-                        body: Body::Slice(BufSlice::from_bytes(vec![4; 11])),
-                    },
-                }
-                .build()
-                .unwrap(),
-            ],
+    fn test_nack() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 8,
+            ..Default::default()
         }
         .build()
         .unwrap();
+        let now = Instant::now();
+        assert_eq!(downloader.nack(), Seq32::from_u32(0));
 
-        let mut wtr = OwnedBufWtr::new(1024, 0);
-        packet.append_to(&mut wtr).unwrap();
+        //         0  1  2  3  4  5
+        // rwnd         [   3     5 ]
+        // sorted [0  1]
+        let state = downloader
+            .write(
+                push_packet(vec![(0, vec![0]), (1, vec![1]), (3, vec![3]), (5, vec![5])]),
+                &now,
+            )
+            .unwrap();
+        assert_eq!(downloader.nack(), Seq32::from_u32(2));
+        assert_eq!(state.local_next_seq_to_receive, downloader.nack());
+
+        // filling the first hole moves the nack to the next one
+        let state = downloader
+            .write(push_packet(vec![(2, vec![2])]), &now)
+            .unwrap();
+        assert_eq!(downloader.nack(), Seq32::from_u32(4));
+        assert_eq!(state.local_next_seq_to_receive, downloader.nack());
+        assert_eq!(
+            downloader.sack_ranges(),
+            vec![Seq32::from_u32(5)..Seq32::from_u32(6)]
+        );
+
+        // emitting leaves the nack be
+        assert_eq!(downloader.emit_all().len(), 4);
+        assert_eq!(downloader.nack(), Seq32::from_u32(4));
+    }
+
+    #[derive(Default)]
+    struct CountingPool {
+        free: Mutex<Vec<Vec<u8>>>,
+        fresh: Mutex<usize>,
+        reused: Mutex<usize>,
+    }
+
+    impl FragAllocator for CountingPool {
+        fn alloc(&self, len: usize) -> Vec<u8> {
+            match self.free.lock().unwrap().pop() {
+                Some(buf) => {
+                    *self.reused.lock().unwrap() += 1;
+                    buf
+                }
+                None => {
+                    *self.fresh.lock().unwrap() += 1;
+                    Vec::with_capacity(len)
+                }
+            }
+        }
+
+        fn free(&self, buf: Vec<u8>) {
+            self.free.lock().unwrap().push(buf);
+        }
+    }
+
+    #[test]
+    fn test_frag_allocator() {
+        let pool = Arc::new(CountingPool::default());
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            frag_allocator: Some(Arc::clone(&pool) as _),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let now = Instant::now();
+
+        for seq in 0..4 {
+            let datagram = push_packet(vec![(seq, vec![seq as u8; 5])]);
+            downloader.write_slice(datagram.data(), &now).unwrap();
+            let slice = downloader.emit().unwrap();
+            assert_eq!(slice.data(), vec![seq as u8; 5]);
+            downloader.recycle(slice);
+        }
+        assert_eq!(*pool.fresh.lock().unwrap(), 1);
+        assert_eq!(*pool.reused.lock().unwrap(), 3);
+
+        // freed on `consume` without a hand back
+        let datagram = push_packet(vec![(4, vec![4; 5]), (5, vec![5; 5])]);
+        downloader.write_slice(datagram.data(), &now).unwrap();
+        downloader.consume(10);
+        assert_eq!(pool.free.lock().unwrap().len(), 2);
+        assert_eq!(*pool.fresh.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_write_stream() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let now = Instant::now();
+        let packet = |seq: u32, checksum: bool, delimited: bool| {
+            PacketBuilder {
+                hdr: PacketHeaderBuilder {
+                    version: CURRENT_VERSION,
+                    rwnd: 2,
+                    rwnd_shift: 0,
+                    checksum,
+                    ece: false,
+                    timestamps: None,
+                    delimited,
+                    nack: Seq32::from_u32(0),
+                }
+                .build()
+                .unwrap(),
+                frags: vec![push_frag(seq, vec![seq as u8; 3])],
+            }
+            .build()
+            .unwrap()
+        };
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet(0, true, true).append_to(&mut wtr).unwrap();
+        packet(1, false, true).append_to(&mut wtr).unwrap();
+        // the last one needs no delimiting
+        packet(2, true, false).append_to(&mut wtr).unwrap();
+
+        let states = downloader.write_stream(wtr.into_slice(), &now).unwrap();
+        assert_eq!(states.len(), 3);
+        for (seq, state) in states.iter().enumerate() {
+            assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(seq as u32)]);
+        }
+        assert_eq!(downloader.emit().unwrap().data(), vec![0; 3]);
+        assert_eq!(downloader.emit().unwrap().data(), vec![1; 3]);
+        assert_eq!(downloader.emit().unwrap().data(), vec![2; 3]);
+        assert_eq!(downloader.stat().packets, 3);
+
+        // a corrupted packet is dropped alone
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet(3, true, true).append_to(&mut wtr).unwrap();
+        packet(4, true, true).append_to(&mut wtr).unwrap();
+        let mut data = wtr.data().to_vec();
+        *data.last_mut().unwrap() ^= 1;
+        let states = downloader.write_stream(BorrowedBufSlice::from_slice(&data), &now);
+        assert_eq!(states.unwrap().len(), 1);
+        assert_eq!(downloader.stat().decoding_errors, 1);
+
+        // a packet cut short fails the whole buffer
+        let states =
+            downloader.write_stream(BufSlice::from_bytes(data[..data.len() - 1].to_vec()), &now);
+        assert_eq!(
+            states.err(),
+            Some(Error::PacketLenMismatch {
+                declared_len: data.len() / 2,
+                available: data.len() / 2 - 1,
+            })
+        );
+        assert_eq!(downloader.next_seq_to_receive(), Seq32::from_u32(4));
+    }
+
+    #[test]
+    fn test_reuse() {
+        let builder = || DownloaderBuilder {
+            recv_buf_len: 8,
+            ..Default::default()
+        };
+        let mut reused = builder().build().unwrap();
+        let mut fresh = builder().build().unwrap();
+        let now = Instant::now();
+
+        // the old peer leaves sorted, out-of-order and partially emitted pushes
+        reused
+            .write(
+                push_packet(vec![(0, vec![0; 4]), (1, vec![1]), (2, vec![2])]),
+                &now,
+            )
+            .unwrap();
+        reused.write(push_packet(vec![(4, vec![4])]), &now).unwrap();
+        assert_eq!(reused.emit_max(2).unwrap().data(), vec![0; 2]);
+        assert_eq!(reused.out_of_order_len(), 1);
+
+        reused.reset(Seq32::from_u32(0));
+        assert_eq!(reused.next_seq_to_receive(), Seq32::from_u32(0));
+        assert_eq!(reused.buffered_bytes(), 0);
+        assert!(reused.emit().is_none());
+
+        // the new peer sees a fresh downloader
+        for downloader in [&mut reused, &mut fresh] {
+            let state = downloader
+                .write(push_packet(vec![(0, vec![7, 8]), (2, vec![9])]), &now)
+                .unwrap();
+            assert_eq!(
+                state.remote_seqs_to_ack,
+                vec![Seq32::from_u32(0), Seq32::from_u32(2)]
+            );
+            assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(1));
+            assert_eq!(state.local_rwnd_size, 7);
+            assert_eq!(downloader.emit_all().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_recv_buf_bytes() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 64,
+            recv_buf_bytes: 4,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let now = Instant::now();
+
+        let state = downloader
+            .write(push_packet(vec![(1, vec![1; 3])]), &now)
+            .unwrap();
+        assert_eq!(state.local_recv_buf_bytes_left, 1);
+        assert!(!state.local_rwnd_probed);
+
+        // plenty of rwnd but no bytes left
+        let state = downloader
+            .write(push_packet(vec![(2, vec![2; 3])]), &now)
+            .unwrap();
+        assert!(state.remote_seqs_to_ack.is_empty());
+        assert!(state.local_rwnd_probed);
+        assert_eq!(state.local_recv_buf_bytes_left, 1);
+        assert_eq!(downloader.out_of_order_len(), 1);
+
+        // the push filling the gap is taken beyond the cap
+        let state = downloader
+            .write(push_packet(vec![(0, vec![0; 3])]), &now)
+            .unwrap();
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        assert_eq!(state.local_recv_buf_bytes_left, 0);
+        assert_eq!(downloader.buffered_bytes(), 6);
+
+        assert_eq!(downloader.emit().unwrap().data(), vec![0; 3]);
+        assert_eq!(downloader.emit().unwrap().data(), vec![1; 3]);
+        assert_eq!(downloader.recv_buf_bytes_left(), 4);
+
+        // the retransmission now fits
+        let state = downloader
+            .write(push_packet(vec![(2, vec![2; 3])]), &now)
+            .unwrap();
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(2)]);
+        assert_eq!(downloader.emit().unwrap().data(), vec![2; 3]);
+        assert_eq!(downloader.buffered_bytes(), 0);
+        assert_eq!(downloader.stat().over_budget_pushes, 1);
+    }
+
+    fn on_full_downloader(on_full: OnFull, delayed_ack: Option<DelayedAckPolicy>) -> Downloader {
+        DownloaderBuilder {
+            recv_buf_len: 64,
+            delayed_ack,
+            recv_buf_bytes: 6,
+            on_full,
+            ..Default::default()
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_on_full_drop_new() {
+        let mut downloader = on_full_downloader(OnFull::DropNew, None);
+        let now = Instant::now();
+        let seqs = |a: u32, b: u32| Seq32::from_u32(a)..Seq32::from_u32(b);
+
+        let state = downloader
+            .write(push_packet(vec![(1, vec![1; 3]), (3, vec![3; 3])]), &now)
+            .unwrap();
+        assert_eq!(state.local_recv_buf_bytes_left, 0);
+
+        // nearer than those held, but still dropped
+        let state = downloader
+            .write(push_packet(vec![(2, vec![2; 3])]), &now)
+            .unwrap();
+        assert!(state.remote_seqs_to_ack.is_empty());
+        assert!(state.local_rwnd_probed);
+        assert_eq!(state.sack_ranges, vec![seqs(1, 2), seqs(3, 4)]);
+        assert_eq!(downloader.buffered_bytes(), 6);
+        assert_eq!(downloader.stat().over_budget_pushes, 1);
+        assert_eq!(downloader.stat().evicted_pushes, 0);
+    }
+
+    #[test]
+    fn test_on_full_drop_furthest() {
+        let mut downloader = on_full_downloader(OnFull::DropFurthest, None);
+        let now = Instant::now();
+        let seqs = |a: u32, b: u32| Seq32::from_u32(a)..Seq32::from_u32(b);
+
+        // held out of order, so not acked
+        let state = downloader
+            .write(push_packet(vec![(1, vec![1; 3]), (3, vec![3; 3])]), &now)
+            .unwrap();
+        assert!(state.remote_seqs_to_ack.is_empty());
+        assert_eq!(state.local_recv_buf_bytes_left, 0);
+
+        // 3 gives way to 2
+        let state = downloader
+            .write(push_packet(vec![(2, vec![2; 3])]), &now)
+            .unwrap();
+        assert!(state.remote_seqs_to_ack.is_empty());
+        assert!(!state.local_rwnd_probed);
+        assert_eq!(state.sack_ranges, vec![seqs(1, 3)]);
+        assert_eq!(downloader.buffered_bytes(), 6);
+        assert_eq!(downloader.stat().evicted_pushes, 1);
+
+        // nothing beyond 4 to evict
+        let state = downloader
+            .write(push_packet(vec![(4, vec![4; 3])]), &now)
+            .unwrap();
+        assert!(state.remote_seqs_to_ack.is_empty());
+        assert!(state.local_rwnd_probed);
+        assert_eq!(downloader.stat().over_budget_pushes, 1);
+
+        // the nack covers those sorted behind the gap
+        let state = downloader
+            .write(push_packet(vec![(3, vec![3; 1]), (0, vec![0; 1])]), &now)
+            .unwrap();
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(3));
+        let state = downloader
+            .write(push_packet(vec![(3, vec![3; 3])]), &now)
+            .unwrap();
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(3)]);
+        let emitted: Vec<Vec<u8>> = downloader
+            .emit_all()
+            .into_iter()
+            .map(|x| x.data().to_vec())
+            .collect();
+        assert_eq!(
+            emitted,
+            vec![vec![0; 1], vec![1; 3], vec![2; 3], vec![3; 3]]
+        );
+    }
+
+    #[test]
+    fn test_on_full_drop_furthest_delayed_ack() {
+        let policy = DelayedAckPolicy {
+            max_delay: Duration::from_secs(1),
+            max_unacked: 64,
+        };
+        let mut downloader = on_full_downloader(OnFull::DropFurthest, Some(policy));
+        let now = Instant::now();
+
+        // nothing evictable is left pending
+        let state = downloader
+            .write(push_packet(vec![(1, vec![1; 3]), (3, vec![3; 3])]), &now)
+            .unwrap();
+        assert!(state.remote_seqs_to_ack.is_empty());
+        assert_eq!(downloader.pending_acks(), 0);
+
+        let _ = downloader
+            .write(push_packet(vec![(2, vec![2; 3]), (0, vec![0; 1])]), &now)
+            .unwrap();
+        assert_eq!(downloader.stat().evicted_pushes, 1);
+        assert_eq!(
+            downloader.on_timer(&(now + Duration::from_secs(1))),
+            vec![Seq32::from_u32(0)]
+        );
+    }
+
+    #[test]
+    fn test_unordered() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ordered: false,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let now = Instant::now();
+
+        let state = downloader
+            .write(push_packet(vec![(1, vec![4; 11])]), &now)
+            .unwrap();
+        assert_eq!(state.local_next_seq_to_receive.to_u32(), 0);
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(1)]);
+        assert_eq!(downloader.emit().unwrap().data(), vec![4; 11]);
+        assert!(downloader.emit().is_none());
+
+        // a duplicate is not delivered twice
+        downloader
+            .write(push_packet(vec![(1, vec![4; 11])]), &now)
+            .unwrap();
+        assert!(downloader.emit().is_none());
+
+        // seq 1 still takes up the rwnd until seq 0 fills the gap
+        let state = downloader
+            .write(push_packet(vec![(0, vec![3; 2])]), &now)
+            .unwrap();
+        assert_eq!(state.local_next_seq_to_receive.to_u32(), 2);
+        let (seq, slice) = downloader.emit_with_seq().unwrap();
+        assert_eq!((seq.to_u32(), slice.data()), (0, &[3; 2][..]));
+        assert!(downloader.emit().is_none());
+        assert_eq!(downloader.recv_buf.sorted_len(), 0);
+        assert_eq!(downloader.recv_buf.rwnd_size(), 3);
+    }
+
+    #[test]
+    fn test_out_of_window1() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: vec![FragBuilder {
+                seq: Seq32::from_u32(99),
+                cmd: FragCommand::Push {
+                    body: Body::Slice(BufSlice::from_bytes(vec![4; 11])),
+                },
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap()],
+        }
+        .build()
+        .unwrap();
+
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet.append_to(&mut wtr).unwrap();
+        let slice = wtr.into_slice();
+        let state = downloader.write(slice, &Instant::now()).unwrap();
+        assert_eq!(state.local_next_seq_to_receive.to_u32(), 0);
+        assert_eq!(state.local_rwnd_size, 3);
+        assert_eq!(state.remote_nack.to_u32(), 0);
+        assert_eq!(state.remote_rwnd_size, 2);
+        assert_eq!(state.remote_seqs_to_ack, vec![]);
+        assert_eq!(state.acked_local_seqs, vec![]);
+        assert!(downloader.emit().is_none());
+    }
+
+    #[test]
+    fn test_ack() {
+        let mut download = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: vec![
+                FragBuilder {
+                    seq: Seq32::from_u32(1),
+                    cmd: FragCommand::Ack,
+                    checksum: false,
+                    more: false,
+                }
+                .build()
+                .unwrap(),
+                FragBuilder {
+                    seq: Seq32::from_u32(3),
+                    cmd: FragCommand::Ack,
+                    checksum: false,
+                    more: false,
+                }
+                .build()
+                .unwrap(),
+                FragBuilder {
+                    seq: Seq32::from_u32(99),
+                    cmd: FragCommand::Push {
+                        body: Body::Slice(BufSlice::from_bytes(vec![4; 11])),
+                    },
+                    checksum: false,
+                    more: false,
+                }
+                .build()
+                .unwrap(),
+            ],
+        }
+        .build()
+        .unwrap();
+
+        let recorder = Arc::new(DownloadEventRecorder {
+            events: Mutex::new(Vec::new()),
+        });
+        let observer: Arc<dyn IDownloadObserver + Send + Sync> = recorder.clone();
+        download.set_on_download_event(Some(Arc::downgrade(&observer)));
+
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet.append_to(&mut wtr).unwrap();
         let slice = wtr.into_slice();
-        let state = download.write(slice).unwrap();
+        let state = download.write(slice, &Instant::now()).unwrap();
         assert_eq!(state.local_next_seq_to_receive.to_u32(), 0);
         assert_eq!(state.local_rwnd_size, 3);
         assert_eq!(state.remote_nack.to_u32(), 0);
@@ -414,16 +2643,45 @@ mod tests {
         let tmp: Vec<Seq32> = vec![1, 3].iter().map(|&x| Seq32::from_u32(x)).collect();
         assert_eq!(state.acked_local_seqs, tmp);
         assert!(download.emit().is_none());
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                DownloadEvent::Ack(Seq32::from_u32(1)),
+                DownloadEvent::Ack(Seq32::from_u32(3)),
+                DownloadEvent::PushDropped(Seq32::from_u32(99), DropReason::TooEarly),
+            ]
+        );
+
+        // a packet too short for its header
+        let e = match download.write(BufSlice::from_bytes(vec![0; 1]), &Instant::now()) {
+            Err(e) => e,
+            Ok(_) => panic!(),
+        };
+        assert_eq!(
+            recorder.events.lock().unwrap().last(),
+            Some(&DownloadEvent::DecodingError(e))
+        );
     }
 
     #[test]
     fn test_rwnd_proceeding() {
-        let mut downloader = DownloaderBuilder { recv_buf_len: 2 }.build().unwrap();
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 2,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
 
         {
             let packet = PacketBuilder {
                 hdr: PacketHeaderBuilder {
+                    version: CURRENT_VERSION,
                     rwnd: 2,
+                    rwnd_shift: 0,
+                    checksum: false,
+                    ece: false,
+                    timestamps: None,
+                    delimited: false,
                     nack: Seq32::from_u32(0),
                 }
                 .build()
@@ -434,6 +2692,8 @@ mod tests {
                         cmd: FragCommand::Push {
                             body: Body::Slice(BufSlice::from_bytes(vec![1; 1])),
                         },
+                        checksum: false,
+                        more: false,
                     }
                     .build()
                     .unwrap(),
@@ -442,6 +2702,8 @@ mod tests {
                         cmd: FragCommand::Push {
                             body: Body::Slice(BufSlice::from_bytes(vec![2; 2])),
                         },
+                        checksum: false,
+                        more: false,
                     }
                     .build()
                     .unwrap(),
@@ -455,7 +2717,7 @@ mod tests {
             let mut wtr = OwnedBufWtr::new(1024, 0);
             packet.append_to(&mut wtr).unwrap();
             let slice = wtr.into_slice();
-            let changes = downloader.write(slice).unwrap();
+            let changes = downloader.write(slice, &Instant::now()).unwrap();
             assert_eq!(changes.local_next_seq_to_receive.to_u32(), 0);
             assert_eq!(changes.local_rwnd_size, 2);
             assert_eq!(changes.remote_nack.to_u32(), 0);
@@ -463,12 +2725,20 @@ mod tests {
             let tmp: Vec<Seq32> = vec![1].iter().map(|&x| Seq32::from_u32(x)).collect();
             assert_eq!(changes.remote_seqs_to_ack, tmp);
             assert_eq!(changes.acked_local_seqs, vec![]);
+            assert_eq!(changes.in_order_pushes, 0);
+            assert_eq!(changes.reordered_pushes, 1);
             assert!(downloader.emit().is_none());
         }
         {
             let packet = PacketBuilder {
                 hdr: PacketHeaderBuilder {
+                    version: CURRENT_VERSION,
                     rwnd: 2,
+                    rwnd_shift: 0,
+                    checksum: false,
+                    ece: false,
+                    timestamps: None,
+                    delimited: false,
                     nack: Seq32::from_u32(0),
                 }
                 .build()
@@ -479,6 +2749,8 @@ mod tests {
                         cmd: FragCommand::Push {
                             body: Body::Slice(BufSlice::from_bytes(vec![0; 1])),
                         },
+                        checksum: false,
+                        more: false,
                     }
                     .build()
                     .unwrap(),
@@ -487,6 +2759,8 @@ mod tests {
                         cmd: FragCommand::Push {
                             body: Body::Slice(BufSlice::from_bytes(vec![3; 3])),
                         },
+                        checksum: false,
+                        more: false,
                     }
                     .build()
                     .unwrap(),
@@ -500,7 +2774,7 @@ mod tests {
             let mut wtr = OwnedBufWtr::new(1024, 0);
             packet.append_to(&mut wtr).unwrap();
             let slice = wtr.into_slice();
-            let state = downloader.write(slice).unwrap();
+            let state = downloader.write(slice, &Instant::now()).unwrap();
             assert_eq!(state.local_next_seq_to_receive.to_u32(), 2);
             assert_eq!(state.local_rwnd_size, 0);
             assert_eq!(state.remote_nack.to_u32(), 0);
@@ -508,13 +2782,24 @@ mod tests {
             let tmp: Vec<Seq32> = vec![0].iter().map(|&x| Seq32::from_u32(x)).collect();
             assert_eq!(state.remote_seqs_to_ack, tmp);
             assert_eq!(state.acked_local_seqs, vec![]);
-            assert_eq!(downloader.emit().unwrap().data(), vec![0; 1]);
-            assert_eq!(downloader.emit().unwrap().data(), vec![1; 1]);
+            // seq(3) is beyond the rwnd, so on neither path
+            assert_eq!(state.in_order_pushes, 1);
+            assert_eq!(state.reordered_pushes, 0);
+            let (seq, slice) = downloader.emit_with_seq().unwrap();
+            assert_eq!((seq.to_u32(), slice.data()), (0, &[0; 1][..]));
+            let (seq, slice) = downloader.emit_with_seq().unwrap();
+            assert_eq!((seq.to_u32(), slice.data()), (1, &[1; 1][..]));
         }
         {
             let packet = PacketBuilder {
                 hdr: PacketHeaderBuilder {
+                    version: CURRENT_VERSION,
                     rwnd: 2,
+                    rwnd_shift: 0,
+                    checksum: false,
+                    ece: false,
+                    timestamps: None,
+                    delimited: false,
                     nack: Seq32::from_u32(0),
                 }
                 .build()
@@ -524,6 +2809,8 @@ mod tests {
                     cmd: FragCommand::Push {
                         body: Body::Slice(BufSlice::from_bytes(vec![2; 2])),
                     },
+                    checksum: false,
+                    more: false,
                 }
                 .build()
                 .unwrap()],
@@ -536,7 +2823,7 @@ mod tests {
             let mut wtr = OwnedBufWtr::new(1024, 0);
             packet.append_to(&mut wtr).unwrap();
             let slice = wtr.into_slice();
-            let changes = downloader.write(slice).unwrap();
+            let changes = downloader.write(slice, &Instant::now()).unwrap();
             assert_eq!(changes.local_next_seq_to_receive.to_u32(), 3);
             assert_eq!(changes.local_rwnd_size, 1);
             assert_eq!(changes.remote_nack.to_u32(), 0);
@@ -544,14 +2831,21 @@ mod tests {
             let tmp: Vec<Seq32> = vec![2].iter().map(|&x| Seq32::from_u32(x)).collect();
             assert_eq!(changes.remote_seqs_to_ack, tmp);
             assert_eq!(changes.acked_local_seqs, vec![]);
-            assert_eq!(downloader.emit().unwrap().data(), vec![2; 2]);
-            assert!(downloader.emit().is_none());
+            let (seq, slice) = downloader.emit_with_seq().unwrap();
+            assert_eq!((seq.to_u32(), slice.data()), (2, &[2; 2][..]));
+            assert!(downloader.emit_with_seq().is_none());
         }
         // test out of window2
         {
             let packet = PacketBuilder {
                 hdr: PacketHeaderBuilder {
+                    version: CURRENT_VERSION,
                     rwnd: 2,
+                    rwnd_shift: 0,
+                    checksum: false,
+                    ece: false,
+                    timestamps: None,
+                    delimited: false,
                     nack: Seq32::from_u32(0),
                 }
                 .build()
@@ -561,6 +2855,8 @@ mod tests {
                     cmd: FragCommand::Push {
                         body: Body::Slice(BufSlice::from_bytes(vec![2; 2])),
                     },
+                    checksum: false,
+                    more: false,
                 }
                 .build()
                 .unwrap()],
@@ -573,7 +2869,7 @@ mod tests {
             let mut wtr = OwnedBufWtr::new(1024, 0);
             packet.append_to(&mut wtr).unwrap();
             let slice = wtr.into_slice();
-            let changes = downloader.write(slice).unwrap();
+            let changes = downloader.write(slice, &Instant::now()).unwrap();
             assert_eq!(changes.local_next_seq_to_receive.to_u32(), 3);
             assert_eq!(changes.local_rwnd_size, 2);
             assert_eq!(changes.remote_nack.to_u32(), 0);
@@ -582,15 +2878,41 @@ mod tests {
             assert_eq!(changes.acked_local_seqs, vec![]);
             assert!(downloader.emit().is_none());
         }
+        let stat = downloader.stat();
+        assert_eq!(stat.packets, 4);
+        assert_eq!(stat.pushes, 6);
+        assert_eq!(stat.in_orders, 2);
+        assert_eq!(stat.out_of_orders, 1);
+        assert_eq!(stat.early_pushes, 2);
+        assert_eq!(stat.late_pushes, 1);
+        assert_eq!(stat.duplicate_pushes, 1);
+        assert_eq!(stat.acks, 0);
+        assert_eq!(stat.decoding_errors, 0);
+
+        downloader.reset_stat();
+        let stat = downloader.stat();
+        assert_eq!((stat.packets, stat.pushes, stat.in_orders), (0, 0, 0));
+        assert_eq!(stat.next_seq_to_receive, Seq32::from_u32(3));
     }
 
     #[test]
     fn test_recv_max() {
-        let mut download = DownloaderBuilder { recv_buf_len: 3 }.build().unwrap();
+        let mut download = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
 
         let packet = PacketBuilder {
             hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
                 rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
                 nack: Seq32::from_u32(0),
             }
             .build()
@@ -600,6 +2922,8 @@ mod tests {
                 cmd: FragCommand::Push {
                     body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2, 3])),
                 },
+                checksum: false,
+                more: false,
             }
             .build()
             .unwrap()],
@@ -613,7 +2937,7 @@ mod tests {
             let mut wtr = OwnedBufWtr::new(1024, 0);
             packet.append_to(&mut wtr).unwrap();
             let slice = wtr.into_slice();
-            let changes = download.write(slice).unwrap();
+            let changes = download.write(slice, &Instant::now()).unwrap();
             assert_eq!(changes.local_next_seq_to_receive.to_u32(), 1);
             assert_eq!(changes.local_rwnd_size, 2);
             assert_eq!(changes.remote_nack.to_u32(), 0);
@@ -629,11 +2953,1136 @@ mod tests {
 
     #[test]
     fn test_large_rwnd() {
-        let recv_buf_len = (u16::MAX as usize) + 1;
-        let result = DownloaderBuilder { recv_buf_len }.build();
+        let recv_buf_len = MAX_SCALED_RWND + 1;
+        let result = DownloaderBuilder {
+            recv_buf_len,
+            ..Default::default()
+        }
+        .build();
         match result {
             Ok(_) => panic!(),
             Err(_) => (),
         }
+        // scaled in the packet header
+        let recv_buf_len = (u16::MAX as usize) + 1;
+        DownloaderBuilder {
+            recv_buf_len,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_on_delivered() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let recorder = Arc::new(DeliveryRecorder {
+            delivered: Mutex::new(Vec::new()),
+        });
+        let observer: Arc<dyn IDeliveryObserver + Send + Sync> = recorder.clone();
+        downloader.set_on_delivered(Some(Arc::downgrade(&observer)));
+
+        let t0 = Instant::now();
+        let t1 = t0 + Duration::from_millis(30);
+
+        // seq(1) is buffered behind the gap of seq(0)
+        downloader
+            .write(push_packet(vec![(1, vec![1])]), &t0)
+            .unwrap();
+        assert!(recorder.delivered.lock().unwrap().is_empty());
+
+        // seq(0) fills the gap and drains seq(1)
+        downloader
+            .write(push_packet(vec![(0, vec![0])]), &t1)
+            .unwrap();
+        let delivered = recorder.delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 2);
+        assert_eq!(delivered[0], (Seq32::from_u32(0), t1));
+        assert_eq!(delivered[1], (Seq32::from_u32(1), t0));
+        assert_eq!(t1.duration_since(delivered[1].1), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_ready_iovecs() {
+        let frags = vec![(0, vec![0, 1, 2]), (1, vec![3]), (2, vec![4, 5])];
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        downloader
+            .write(push_packet(frags.clone()), &Instant::now())
+            .unwrap();
+        let mut twin = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        twin.write(push_packet(frags), &Instant::now()).unwrap();
+
+        let mut drained = Vec::new();
+        while let Some(slice) = twin.emit() {
+            drained.extend_from_slice(slice.data());
+        }
+        let ready: Vec<u8> = downloader
+            .ready_iovecs()
+            .iter()
+            .flat_map(|x| x.iter().copied())
+            .collect();
+        assert_eq!(ready, drained);
+        assert_eq!(downloader.ready_iovecs().len(), 3);
+
+        // cross the boundary between seq(0) and seq(1)
+        downloader.consume(4);
+        let ready = downloader.ready_iovecs();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(&*ready[0], &[4, 5]);
+
+        // split seq(2)
+        downloader.consume(1);
+        assert_eq!(&*downloader.ready_iovecs()[0], &[5]);
+        assert_eq!(downloader.emit_max(10).unwrap().data(), vec![5]);
+        assert!(downloader.ready_iovecs().is_empty());
+    }
+
+    #[test]
+    fn test_corrupted_frag() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: (0..3)
+                .map(|seq| {
+                    FragBuilder {
+                        seq: Seq32::from_u32(seq),
+                        cmd: FragCommand::Push {
+                            body: Body::Slice(BufSlice::from_bytes(vec![seq as u8; 2])),
+                        },
+                        checksum: true,
+                        more: false,
+                    }
+                    .build()
+                    .unwrap()
+                })
+                .collect(),
+        }
+        .build()
+        .unwrap();
+
+        // [packet_header] [push_hdr seq(0)] [crc] [2] [push_hdr seq(1)] [crc] [2] ...
+
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet.append_to(&mut wtr).unwrap();
+        let mut data = wtr.data().to_vec();
+        // corrupt the body of seq(1)
+        data[PACKET_HDR_LEN + 15 + 13] ^= 0xff;
+        let state = downloader
+            .write(BufSlice::from_bytes(data), &Instant::now())
+            .unwrap();
+        let tmp: Vec<Seq32> = vec![0, 2].iter().map(|&x| Seq32::from_u32(x)).collect();
+        assert_eq!(state.remote_seqs_to_ack, tmp);
+        assert_eq!(state.local_next_seq_to_receive.to_u32(), 1);
+        assert_eq!(downloader.stat().corrupted_frags, 1);
+        assert_eq!(downloader.emit().unwrap().data(), vec![0; 2]);
+        assert!(downloader.emit().is_none());
+    }
+
+    #[test]
+    fn test_error_from_decoding_error() {
+        assert_eq!(Error::from(DecodingError::Checksum), Error::FragChecksum);
+        assert_eq!(
+            Error::from(DecodingError::UnknownCommand { cmd: 9 }),
+            Error::UnknownCommand { cmd: 9 }
+        );
+    }
+
+    #[test]
+    fn test_unknown_command_skipped() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: vec![FragBuilder {
+                seq: Seq32::from_u32(7),
+                cmd: FragCommand::Ack,
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap()],
+        }
+        .build()
+        .unwrap();
+
+        // [packet_header] [ack_hdr seq(7)] [seq(1)] [cmd(9)] [len(3)] [3] [push_hdr seq(0)] [2]
+
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet.append_to(&mut wtr).unwrap();
+        wtr.append(&[0, 0, 0, 1, 9, 0, 0, 0, 3, 9, 9, 9]).unwrap();
+        push_frag(0, vec![0; 2]).append_to(&mut wtr).unwrap();
+        let state = downloader.write(wtr.into_slice(), &Instant::now()).unwrap();
+        assert_eq!(state.acked_local_seqs, vec![Seq32::from_u32(7)]);
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        assert_eq!(downloader.stat().unknown_frags, 1);
+        assert_eq!(downloader.stat().decoding_errors, 0);
+        assert_eq!(downloader.emit().unwrap().data(), vec![0; 2]);
+    }
+
+    #[test]
+    fn test_duplicate_of_just_delivered_in_same_packet() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        // [packet_header] [push_hdr seq(1)] [1] [push_hdr seq(0)] [0] [push_hdr seq(1)] [2]
+        let slice = push_packet(vec![(1, vec![1]), (0, vec![0]), (1, vec![2])]);
+        let state = downloader.write(slice, &Instant::now()).unwrap();
+        assert_eq!(state.local_next_seq_to_receive.to_u32(), 2);
+        let tmp: Vec<Seq32> = vec![1, 0, 1].iter().map(|&x| Seq32::from_u32(x)).collect();
+        assert_eq!(state.remote_seqs_to_ack, tmp);
+        assert_eq!(downloader.stat().late_pushes, 1);
+        assert_eq!(downloader.emit().unwrap().data(), vec![0]);
+        assert_eq!(downloader.emit().unwrap().data(), vec![1]);
+        assert!(downloader.emit().is_none());
+    }
+
+    #[test]
+    fn test_push_ack_same_seq() {
+        let packet = || {
+            let packet = PacketBuilder {
+                hdr: PacketHeaderBuilder {
+                    version: CURRENT_VERSION,
+                    rwnd: 2,
+                    rwnd_shift: 0,
+                    checksum: false,
+                    ece: false,
+                    timestamps: None,
+                    delimited: false,
+                    nack: Seq32::from_u32(0),
+                }
+                .build()
+                .unwrap(),
+                frags: vec![
+                    FragBuilder {
+                        seq: Seq32::from_u32(0),
+                        cmd: FragCommand::Push {
+                            body: Body::Slice(BufSlice::from_bytes(vec![0])),
+                        },
+                        checksum: false,
+                        more: false,
+                    }
+                    .build()
+                    .unwrap(),
+                    FragBuilder {
+                        seq: Seq32::from_u32(0),
+                        cmd: FragCommand::Ack,
+                        checksum: false,
+                        more: false,
+                    }
+                    .build()
+                    .unwrap(),
+                ],
+            }
+            .build()
+            .unwrap();
+            let mut wtr = OwnedBufWtr::new(1024, 0);
+            packet.append_to(&mut wtr).unwrap();
+            wtr.into_slice()
+        };
+
+        // each frag is processed per its command
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let state = downloader.write(packet(), &Instant::now()).unwrap();
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        assert_eq!(state.acked_local_seqs, vec![Seq32::from_u32(0)]);
+        assert_eq!(downloader.emit().unwrap().data(), vec![0]);
+
+        // the whole packet is rejected before touching any state
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            strict_framing: true,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        assert_eq!(
+            downloader.write(packet(), &Instant::now()).err(),
+            Some(Error::PushAckSameSeq {
+                seq: Seq32::from_u32(0)
+            })
+        );
+        assert_eq!(downloader.next_seq_to_receive(), Seq32::from_u32(0));
+        assert!(downloader.emit().is_none());
+        assert_eq!(downloader.stat().pushes, 0);
+        assert_eq!(downloader.stat().acks, 0);
+        assert_eq!(downloader.stat().packets, 0);
+        assert_eq!(downloader.stat().decoding_errors, 1);
+
+        let state = downloader
+            .write(push_packet(vec![(0, vec![0])]), &Instant::now())
+            .unwrap();
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(1));
+        assert_eq!(downloader.emit().unwrap().data(), vec![0]);
+    }
+
+    #[test]
+    fn test_zero_copy_write() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 64,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let frags = (0..64).map(|seq| (seq, vec![seq as u8; 4])).collect();
+        let slice = push_packet(frags);
+        let packet = slice.data().as_ptr_range();
+        downloader.write(slice, &Instant::now()).unwrap();
+
+        // every body borrows from the buffer of the packet
+        let bodies = downloader.emit_all();
+        assert_eq!(bodies.len(), 64);
+        for (seq, body) in bodies.iter().enumerate() {
+            assert_eq!(body.data(), vec![seq as u8; 4]);
+            assert!(packet.contains(&body.data().as_ptr()));
+        }
+    }
+
+    #[test]
+    fn test_sack_ranges_single_gap() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 8,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let seqs = |a: u32, b: u32| Seq32::from_u32(a)..Seq32::from_u32(b);
+
+        let state = downloader
+            .write(
+                push_packet(vec![(1, vec![1]), (3, vec![3]), (2, vec![2])]),
+                &Instant::now(),
+            )
+            .unwrap();
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(0));
+        assert_eq!(state.sack_ranges, vec![seqs(1, 4)]);
+
+        let state = downloader
+            .write(push_packet(vec![(0, vec![0])]), &Instant::now())
+            .unwrap();
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(4));
+        assert!(state.sack_ranges.is_empty());
+    }
+
+    #[test]
+    fn test_sack_ranges_multi_gap() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 8,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let seqs = |a: u32, b: u32| Seq32::from_u32(a)..Seq32::from_u32(b);
+
+        let state = downloader
+            .write(
+                push_packet(vec![(6, vec![6]), (2, vec![2]), (5, vec![5]), (3, vec![3])]),
+                &Instant::now(),
+            )
+            .unwrap();
+        assert_eq!(state.sack_ranges, vec![seqs(2, 4), seqs(5, 7)]);
+
+        let state = downloader
+            .write(push_packet(vec![(0, vec![0])]), &Instant::now())
+            .unwrap();
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(1));
+        assert_eq!(state.sack_ranges, vec![seqs(2, 4), seqs(5, 7)]);
+
+        let state = downloader
+            .write(push_packet(vec![(1, vec![1])]), &Instant::now())
+            .unwrap();
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(4));
+        assert_eq!(state.sack_ranges, vec![seqs(5, 7)]);
+
+        let state = downloader
+            .write(push_packet(vec![(4, vec![4])]), &Instant::now())
+            .unwrap();
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(7));
+        assert!(state.sack_ranges.is_empty());
+        assert_eq!(
+            downloader.emit_coalesced(usize::MAX),
+            vec![0, 1, 2, 3, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn test_max_frag_body_len() {
+        let packet = || push_packet(vec![(0, vec![0, 1, 2]), (1, vec![1])]);
+
+        // the oversized push is dropped alone
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            max_frag_body_len: 2,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let state = downloader.write(packet(), &Instant::now()).unwrap();
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(1)]);
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(0));
+        assert_eq!(downloader.out_of_order_len(), 1);
+        assert_eq!(downloader.stat().oversized_pushes, 1);
+
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 3,
+            strict_framing: true,
+            max_frag_body_len: 2,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        assert_eq!(
+            downloader.write(packet(), &Instant::now()).err(),
+            Some(Error::PushBodyTooLong {
+                seq: Seq32::from_u32(0),
+                len: 3
+            })
+        );
+        assert_eq!(downloader.out_of_order_len(), 0);
+        assert_eq!(downloader.stat().pushes, 0);
+    }
+
+    /// (seq, body, more)
+    fn message_packet(frags: Vec<(u32, Vec<u8>, bool)>) -> BufSlice {
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: frags
+                .into_iter()
+                .map(|(seq, body, more)| {
+                    FragBuilder {
+                        seq: Seq32::from_u32(seq),
+                        cmd: FragCommand::Push {
+                            body: Body::Slice(BufSlice::from_bytes(body)),
+                        },
+                        checksum: false,
+                        more,
+                    }
+                    .build()
+                    .unwrap()
+                })
+                .collect(),
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet.append_to(&mut wtr).unwrap();
+        wtr.into_slice()
+    }
+
+    #[test]
+    fn test_emit_message() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 8,
+            messages: true,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let packet = message_packet;
+
+        downloader
+            .write(
+                packet(vec![(2, vec![2], false), (0, vec![0], true)]),
+                &Instant::now(),
+            )
+            .unwrap();
+        // the middle fragment is missing
+        assert!(downloader.emit_message().unwrap().is_none());
+
+        downloader
+            .write(
+                packet(vec![(1, vec![1], true), (3, vec![3], false)]),
+                &Instant::now(),
+            )
+            .unwrap();
+        let message = downloader.emit_message().unwrap().unwrap();
+        assert_eq!(
+            message
+                .iter()
+                .map(|x| x.data().to_vec())
+                .collect::<Vec<_>>(),
+            vec![vec![0], vec![1], vec![2]]
+        );
+        let message = downloader.emit_message().unwrap().unwrap();
+        assert_eq!(message.len(), 1);
+        assert_eq!(message[0].data(), vec![3]);
+        assert!(downloader.emit_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_emit_message_too_long() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 2,
+            messages: true,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let recorder = Arc::new(DownloadEventRecorder {
+            events: Mutex::new(Vec::new()),
+        });
+        let observer: Arc<dyn IDownloadObserver + Send + Sync> = recorder.clone();
+        downloader.set_on_download_event(Some(Arc::downgrade(&observer)));
+
+        // a message of 3 pushes followed by one of 1
+        downloader
+            .write(
+                message_packet(vec![(0, vec![0], true), (1, vec![1], true)]),
+                &Instant::now(),
+            )
+            .unwrap();
+        // no room for the last push
+        assert!(downloader.emit_message().unwrap().is_none());
+        assert_eq!(downloader.stat().too_long_messages, 1);
+        downloader
+            .write(
+                message_packet(vec![(2, vec![2], false), (3, vec![3], false)]),
+                &Instant::now(),
+            )
+            .unwrap();
+        let message = downloader.emit_message().unwrap().unwrap();
+        assert_eq!(message.len(), 1);
+        assert_eq!(message[0].data(), vec![3]);
+        assert!(downloader.emit_message().unwrap().is_none());
+        assert_eq!(downloader.stat().too_long_messages, 1);
+        let dropped: Vec<_> = recorder
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|x| match x {
+                DownloadEvent::PushDropped(seq, DropReason::MessageTooLong) => Some(seq.to_u32()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(dropped, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_reassembly_timeout_skip() {
+        let timeout = Duration::from_millis(100);
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 8,
+            messages: true,
+            reassembly_timeout: Some(timeout),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let start = Instant::now();
+
+        // message A of 0..=2 missing its middle 1, then message B of 3
+        downloader
+            .write(
+                message_packet(vec![
+                    (0, vec![0], true),
+                    (2, vec![2], false),
+                    (3, vec![3], false),
+                ]),
+                &start,
+            )
+            .unwrap();
+        assert!(downloader.emit_message().unwrap().is_none());
+        assert_eq!(downloader.reassembly_deadline(), Some(start + timeout));
+        assert!(downloader.on_timer(&(start + timeout / 2)).is_empty());
+        assert!(downloader.emit_message().unwrap().is_none());
+        assert!(downloader.on_timer(&(start + timeout)).is_empty());
+        assert_eq!(downloader.stat().timed_out_messages, 1);
+        let message = downloader.emit_message().unwrap().unwrap();
+        assert_eq!(message.len(), 1);
+        assert_eq!(message[0].data(), vec![3]);
+        assert_eq!(downloader.reassembly_deadline(), None);
+
+        // the missing push arriving late is not emitted
+        downloader
+            .write(message_packet(vec![(1, vec![1], true)]), &start)
+            .unwrap();
+        assert!(downloader.emit_message().unwrap().is_none());
+        assert_eq!(downloader.stat().late_pushes, 1);
+
+        // a message whose last push has not arrived either
+        let now = start + timeout;
+        downloader
+            .write(message_packet(vec![(4, vec![4], true)]), &now)
+            .unwrap();
+        assert!(downloader.on_timer(&(now + timeout)).is_empty());
+        assert_eq!(downloader.stat().timed_out_messages, 2);
+        downloader
+            .write(
+                message_packet(vec![
+                    (5, vec![5], true),
+                    (6, vec![6], false),
+                    (7, vec![7], false),
+                ]),
+                &now,
+            )
+            .unwrap();
+        let message = downloader.emit_message().unwrap().unwrap();
+        assert_eq!(message.len(), 1);
+        assert_eq!(message[0].data(), vec![7]);
+        assert!(downloader.emit_message().unwrap().is_none());
+        assert_eq!(downloader.stat().next_seq_to_receive, Seq32::from_u32(8));
+    }
+
+    #[test]
+    fn test_reassembly_timeout_error() {
+        let timeout = Duration::from_millis(100);
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 8,
+            messages: true,
+            reassembly_timeout: Some(timeout),
+            on_reassembly_timeout: OnReassemblyTimeout::Error,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let start = Instant::now();
+
+        downloader
+            .write(
+                message_packet(vec![(0, vec![0], true), (2, vec![2], false)]),
+                &start,
+            )
+            .unwrap();
+        assert!(downloader.on_timer(&(start + timeout)).is_empty());
+        assert_eq!(
+            downloader.emit_message().err(),
+            Some(MessageError::ReassemblyTimedOut {
+                seq: Seq32::from_u32(0)
+            })
+        );
+        assert_eq!(downloader.stat().timed_out_messages, 0);
+        assert_eq!(downloader.reassembly_deadline(), None);
+
+        downloader.reset(Seq32::from_u32(3));
+        assert!(downloader.emit_message().unwrap().is_none());
+
+        let e = DownloaderBuilder {
+            reassembly_timeout: Some(timeout),
+            ..Default::default()
+        }
+        .build()
+        .err()
+        .unwrap();
+        assert!(matches!(e, BuildError::ReassemblyTimeoutWithoutMessages));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_emit_message_without_messages() {
+        let mut downloader = DownloaderBuilder::default().build().unwrap();
+        let _ = downloader.emit_message();
+    }
+
+    #[test]
+    fn test_emit_message_unordered() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 8,
+            ordered: false,
+            messages: true,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let data = |message: Vec<BufSlice>| {
+            message
+                .iter()
+                .map(|x| x.data().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        // message A of 0..=2 missing 1, then message B of 3..=4
+        downloader
+            .write(
+                message_packet(vec![(0, vec![0], true), (2, vec![2], false)]),
+                &Instant::now(),
+            )
+            .unwrap();
+        assert!(downloader.emit_message().unwrap().is_none());
+        downloader
+            .write(
+                message_packet(vec![(4, vec![4], false), (3, vec![3], true)]),
+                &Instant::now(),
+            )
+            .unwrap();
+        let message = downloader.emit_message().unwrap().unwrap();
+        assert_eq!(data(message), vec![vec![3], vec![4]]);
+        assert!(downloader.emit_message().unwrap().is_none());
+        // B is still held behind the gap
+        assert_eq!(downloader.stat().next_seq_to_receive, Seq32::from_u32(1));
+
+        downloader
+            .write(message_packet(vec![(1, vec![1], true)]), &Instant::now())
+            .unwrap();
+        let message = downloader.emit_message().unwrap().unwrap();
+        assert_eq!(data(message), vec![vec![0], vec![1], vec![2]]);
+        assert!(downloader.emit_message().unwrap().is_none());
+
+        // a one-push message right away
+        downloader
+            .write(message_packet(vec![(5, vec![5], false)]), &Instant::now())
+            .unwrap();
+        let message = downloader.emit_message().unwrap().unwrap();
+        assert_eq!(data(message), vec![vec![5]]);
+        assert!(downloader.emit_message().unwrap().is_none());
+        assert_eq!(downloader.stat().next_seq_to_receive, Seq32::from_u32(6));
+        assert_eq!(downloader.stat().too_long_messages, 0);
+    }
+
+    fn delayed_ack_downloader() -> Downloader {
+        DownloaderBuilder {
+            recv_buf_len: 8,
+            delayed_ack: Some(DelayedAckPolicy {
+                max_delay: Duration::from_millis(40),
+                max_unacked: 3,
+            }),
+            ..Default::default()
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_delayed_ack_threshold() {
+        let mut downloader = delayed_ack_downloader();
+        let now = Instant::now();
+        let changes = downloader
+            .write(push_packet(vec![(0, vec![0])]), &now)
+            .unwrap();
+        assert_eq!(changes.remote_seqs_to_ack, vec![]);
+        let changes = downloader
+            .write(push_packet(vec![(1, vec![1])]), &now)
+            .unwrap();
+        assert_eq!(changes.remote_seqs_to_ack, vec![]);
+        assert_eq!(downloader.pending_acks(), 2);
+        // the data is delivered regardless
+        assert_eq!(downloader.emit_all().len(), 2);
+
+        let changes = downloader
+            .write(push_packet(vec![(2, vec![2]), (3, vec![3])]), &now)
+            .unwrap();
+        let tmp: Vec<Seq32> = (0..4).map(Seq32::from_u32).collect();
+        assert_eq!(changes.remote_seqs_to_ack, tmp);
+        assert_eq!(downloader.pending_acks(), 0);
+        assert_eq!(downloader.ack_deadline(), None);
+
+        assert!(DownloaderBuilder {
+            recv_buf_len: 8,
+            delayed_ack: Some(DelayedAckPolicy {
+                max_delay: Duration::ZERO,
+                max_unacked: 0,
+            }),
+            ..Default::default()
+        }
+        .build()
+        .is_err());
+    }
+
+    #[test]
+    fn test_delayed_ack_timer() {
+        let mut downloader = delayed_ack_downloader();
+        let start = Instant::now();
+        assert_eq!(downloader.on_timer(&start), vec![]);
+
+        let changes = downloader
+            .write(push_packet(vec![(0, vec![0])]), &start)
+            .unwrap();
+        assert_eq!(changes.remote_seqs_to_ack, vec![]);
+        let deadline = start + Duration::from_millis(40);
+        assert_eq!(downloader.ack_deadline(), Some(deadline));
+
+        // a later push does not postpone the deadline
+        let now = start + Duration::from_millis(30);
+        let changes = downloader
+            .write(push_packet(vec![(1, vec![1])]), &now)
+            .unwrap();
+        assert_eq!(changes.remote_seqs_to_ack, vec![]);
+        assert_eq!(downloader.ack_deadline(), Some(deadline));
+        assert_eq!(downloader.on_timer(&now), vec![]);
+        assert_eq!(downloader.pending_acks(), 2);
+
+        let tmp: Vec<Seq32> = (0..2).map(Seq32::from_u32).collect();
+        assert_eq!(downloader.on_timer(&deadline), tmp);
+        assert_eq!(downloader.pending_acks(), 0);
+
+        // overdue acks also go out with the next written packet
+        let changes = downloader
+            .write(push_packet(vec![(2, vec![2])]), &deadline)
+            .unwrap();
+        assert_eq!(changes.remote_seqs_to_ack, vec![]);
+        let later = deadline + Duration::from_millis(50);
+        let changes = downloader
+            .write(push_packet(vec![(3, vec![3])]), &later)
+            .unwrap();
+        let tmp: Vec<Seq32> = (2..4).map(Seq32::from_u32).collect();
+        assert_eq!(changes.remote_seqs_to_ack, tmp);
+    }
+
+    #[test]
+    fn test_delayed_ack_bypassed() {
+        let mut downloader = delayed_ack_downloader();
+        let now = Instant::now();
+        let changes = downloader
+            .write(push_packet(vec![(0, vec![0])]), &now)
+            .unwrap();
+        assert_eq!(changes.remote_seqs_to_ack, vec![]);
+
+        // out of order, taking the pending ack along
+        let changes = downloader
+            .write(push_packet(vec![(2, vec![2])]), &now)
+            .unwrap();
+        let tmp: Vec<Seq32> = [0, 2].into_iter().map(Seq32::from_u32).collect();
+        assert_eq!(changes.remote_seqs_to_ack, tmp);
+        assert_eq!(downloader.pending_acks(), 0);
+
+        // duplicate
+        let changes = downloader
+            .write(push_packet(vec![(2, vec![2])]), &now)
+            .unwrap();
+        assert_eq!(changes.remote_seqs_to_ack, vec![Seq32::from_u32(2)]);
+
+        // late
+        let changes = downloader
+            .write(push_packet(vec![(0, vec![0])]), &now)
+            .unwrap();
+        assert_eq!(changes.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        assert_eq!(downloader.pending_acks(), 0);
+    }
+
+    #[test]
+    fn test_ping() {
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 2,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: vec![FragBuilder {
+                seq: Seq32::from_u32(0),
+                cmd: FragCommand::Ping,
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap()],
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet.append_to(&mut wtr).unwrap();
+        let changes = downloader.write(wtr.into_slice(), &Instant::now()).unwrap();
+        assert_eq!(changes.remote_pings, vec![Seq32::from_u32(0)]);
+        assert_eq!(changes.pongs, vec![]);
+        // the id is not a push seq
+        assert_eq!(changes.local_next_seq_to_receive, Seq32::from_u32(0));
+        assert_eq!(changes.local_rwnd_size, 2);
+        assert_eq!(changes.remote_seqs_to_ack, vec![]);
+        assert!(downloader.emit().is_none());
+        assert_eq!(downloader.stat().pushes, 0);
+    }
+
+    #[test]
+    fn test_max_frags_per_packet() {
+        // 10_000 one-byte pushes
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: (0..10_000)
+                .map(|seq| {
+                    FragBuilder {
+                        seq: Seq32::from_u32(seq),
+                        cmd: FragCommand::Push {
+                            body: Body::Slice(BufSlice::from_bytes(vec![seq as u8])),
+                        },
+                        checksum: false,
+                        more: false,
+                    }
+                    .build()
+                    .unwrap()
+                })
+                .collect(),
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024 * 1024, 0);
+        packet.append_to(&mut wtr).unwrap();
+        let data = wtr.data().to_vec();
+        let downloader = |strict_framing| {
+            DownloaderBuilder {
+                recv_buf_len: 16,
+                strict_framing,
+                max_frags_per_packet: 64,
+                ..Default::default()
+            }
+            .build()
+            .unwrap()
+        };
+
+        let mut downloader1 = downloader(false);
+        let changes = downloader1
+            .write(BufSlice::from_bytes(data.clone()), &Instant::now())
+            .unwrap();
+        // the pushes beyond the rwnd are not acked
+        assert_eq!(changes.remote_seqs_to_ack.len(), 16);
+        assert!(changes.remote_seqs_to_ack.capacity() <= 64);
+        assert_eq!(changes.local_next_seq_to_receive, Seq32::from_u32(16));
+        let stat = downloader1.stat();
+        assert_eq!(stat.pushes, 64);
+        assert_eq!(stat.early_pushes, 48);
+        assert_eq!(stat.truncated_packets, 1);
+        // up to the end of the last fragment decoded
+        assert_eq!(
+            changes.bytes_consumed,
+            PACKET_HDR_LEN + 64 * (PUSH_HDR_LEN + 1)
+        );
+        assert!(changes.bytes_consumed < data.len());
+
+        // a packet within the limit is read to its end
+        let mut downloader3 = downloader(false);
+        let few = push_packet(vec![(0, vec![0; 3]), (1, vec![1])]);
+        let few_len = few.len();
+        let changes = downloader3.write(few, &Instant::now()).unwrap();
+        assert_eq!(changes.bytes_consumed, few_len);
+
+        let mut downloader2 = downloader(true);
+        assert_eq!(
+            downloader2
+                .write(BufSlice::from_bytes(data), &Instant::now())
+                .err(),
+            Some(Error::TooManyFrags { max: 64 })
+        );
+        assert_eq!(downloader2.stat().pushes, 0);
+    }
+
+    #[test]
+    fn test_truncated_push() {
+        // [packet_header] [ack seq(5)] [push_hdr seq(0)] [3] [push_hdr seq(1)] [2 of 3]
+        let packet = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: vec![
+                FragBuilder {
+                    seq: Seq32::from_u32(5),
+                    cmd: FragCommand::Ack,
+                    checksum: false,
+                    more: false,
+                }
+                .build()
+                .unwrap(),
+                FragBuilder {
+                    seq: Seq32::from_u32(0),
+                    cmd: FragCommand::Push {
+                        body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2])),
+                    },
+                    checksum: false,
+                    more: false,
+                }
+                .build()
+                .unwrap(),
+                FragBuilder {
+                    seq: Seq32::from_u32(1),
+                    cmd: FragCommand::Push {
+                        body: Body::Slice(BufSlice::from_bytes(vec![3, 4, 5])),
+                    },
+                    checksum: false,
+                    more: false,
+                }
+                .build()
+                .unwrap(),
+            ],
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        packet.append_to(&mut wtr).unwrap();
+        let mut data = wtr.data().to_vec();
+        data.pop();
+        let downloader = |strict_framing| {
+            DownloaderBuilder {
+                recv_buf_len: 16,
+                strict_framing,
+                ..Default::default()
+            }
+            .build()
+            .unwrap()
+        };
+
+        let mut downloader1 = downloader(false);
+        let changes = downloader1
+            .write(BufSlice::from_bytes(data.clone()), &Instant::now())
+            .unwrap();
+        // the fragments before the clipped push are kept
+        assert_eq!(changes.truncated_push, Some(Seq32::from_u32(1)));
+        assert_eq!(changes.acked_local_seqs, vec![Seq32::from_u32(5)]);
+        assert_eq!(changes.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        assert_eq!(changes.local_next_seq_to_receive, Seq32::from_u32(1));
+        // up to the clipped push
+        assert_eq!(
+            changes.bytes_consumed,
+            PACKET_HDR_LEN + ACK_HDR_LEN + PUSH_HDR_LEN + 3
+        );
+        assert_eq!(downloader1.emit().unwrap().data(), vec![0, 1, 2]);
+        let stat = downloader1.stat();
+        assert_eq!(stat.truncated_packets, 1);
+        assert_eq!(stat.decoding_errors, 0);
+        assert_eq!(stat.pushes, 1);
+
+        // a packet read through carries no clipped push
+        let changes = downloader1
+            .write(push_packet(vec![(1, vec![3, 4, 5])]), &Instant::now())
+            .unwrap();
+        assert_eq!(changes.truncated_push, None);
+
+        let mut downloader2 = downloader(true);
+        assert_eq!(
+            downloader2
+                .write(BufSlice::from_bytes(data), &Instant::now())
+                .err(),
+            Some(Error::PushBodyTruncated {
+                seq: Seq32::from_u32(1),
+                declared_len: 3,
+                available: 2,
+            })
+        );
+        let stat = downloader2.stat();
+        assert_eq!(stat.decoding_errors, 1);
+        assert_eq!(stat.pushes, 0);
+    }
+
+    #[test]
+    fn test_legacy_mode() {
+        let current = push_packet(vec![(0, vec![0, 1, 2])]);
+        // the same but for the version byte, with no flags in the shift
+        let legacy = push_packet(vec![(1, vec![3, 4])]).data()[1..].to_vec();
+
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 16,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let e = downloader
+            .write(BufSlice::from_bytes(legacy.clone()), &Instant::now())
+            .err()
+            .unwrap();
+        assert_eq!(e, Error::VersionMismatch { version: 0 });
+
+        let mut downloader = DownloaderBuilder {
+            recv_buf_len: 16,
+            legacy_mode: true,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        downloader
+            .write(BufSlice::from_bytes(legacy), &Instant::now())
+            .unwrap();
+        downloader.write(current, &Instant::now()).unwrap();
+        assert_eq!(downloader.emit().unwrap().data(), &[0, 1, 2]);
+        assert_eq!(downloader.emit().unwrap().data(), &[3, 4]);
+        let stat = downloader.stat();
+        assert_eq!(stat.packets, 2);
+        assert_eq!(stat.legacy_packets, 1);
+        assert_eq!(stat.decoding_errors, 0);
     }
 }
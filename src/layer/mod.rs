@@ -1,36 +1,97 @@
+mod connection;
 mod downloader;
 mod observer;
+mod session;
 mod uploader;
 
-use crate::utils::Seq32;
+use crate::{protocol::frag::ACK_HDR_LEN, utils::Seq32};
+pub use connection::*;
 pub use downloader::*;
 pub use observer::*;
+pub use session::*;
+use std::{ops::Range, time::Duration};
 pub use uploader::*;
 
 pub struct Builder {
     pub local_recv_buf_len: usize,
     pub nack_duplicate_threshold_to_activate_fast_retransmit: usize,
-    pub ratio_rto_to_one_rtt: f64,
+    /// See `UploaderBuilder::min_rto`
+    pub min_rto: Duration,
+    pub max_rto: Duration,
     pub to_send_queue_len_cap: usize,
     pub swnd_size_cap: usize,
+    pub max_inflight_bytes: usize,
+    pub frag_checksum: bool,
+    pub packet_checksum: bool,
+    /// See `UploaderBuilder::timestamps`
+    pub timestamps: bool,
+    /// See `UploaderBuilder::delimited`
+    pub delimited: bool,
+    /// Reject what a well-behaved peer would never send instead of processing what makes sense of it
+    pub strict_framing: bool,
+    /// Hold back acks on the downloader; see `Downloader::on_timer`
+    ///
+    /// `None` acks every push in the next `Uploader::emit` after the packet carrying it is written, even when corked or paced.
+    pub delayed_ack: Option<DelayedAckPolicy>,
+    /// See `DownloaderBuilder::ordered`
+    pub ordered: bool,
+    /// See `DownloaderBuilder::recv_buf_bytes`
+    pub local_recv_buf_bytes: usize,
+    /// See `DownloaderBuilder::on_full`
+    pub local_on_full: OnFull,
+    /// See `DownloaderBuilder::legacy_mode`
+    pub legacy_mode: bool,
+    /// See `DownloaderBuilder::messages`
+    pub messages: bool,
+    /// See `DownloaderBuilder::reassembly_timeout`
+    pub reassembly_timeout: Option<Duration>,
+    /// See `DownloaderBuilder::on_reassembly_timeout`
+    pub on_reassembly_timeout: OnReassemblyTimeout,
     pub mtu: usize,
 }
 
 impl Builder {
     pub fn build(self) -> Result<(Uploader, Downloader), BuildError> {
+        // the peer, if built alike, would retransmit pushes whose acks are only held back
+        if let Some(policy) = &self.delayed_ack {
+            if policy.max_delay >= self.min_rto {
+                return Err(BuildError::AckDelayNotBelowMinRto);
+            }
+        }
         let uploader = UploaderBuilder {
             local_recv_buf_len: self.local_recv_buf_len,
             nack_duplicate_threshold_to_activate_fast_retransmit: self
                 .nack_duplicate_threshold_to_activate_fast_retransmit,
-            ratio_rto_to_one_rtt: self.ratio_rto_to_one_rtt,
+            min_rto: self.min_rto,
+            max_rto: self.max_rto,
             to_send_queue_len_cap: self.to_send_queue_len_cap,
             swnd_size_cap: self.swnd_size_cap,
+            max_inflight_bytes: self.max_inflight_bytes,
+            frag_checksum: self.frag_checksum,
+            packet_checksum: self.packet_checksum,
+            timestamps: self.timestamps,
+            delimited: self.delimited,
+            strict_framing: self.strict_framing,
             mtu: self.mtu,
         }
         .build()
         .map_err(|e| BuildError::Uploader(e))?;
         let downloader = DownloaderBuilder {
             recv_buf_len: self.local_recv_buf_len,
+            strict_framing: self.strict_framing,
+            // a push body never fills a whole packet
+            max_frag_body_len: self.mtu,
+            // every fragment takes at least an ack header
+            max_frags_per_packet: self.mtu / ACK_HDR_LEN,
+            delayed_ack: self.delayed_ack,
+            ordered: self.ordered,
+            recv_buf_bytes: self.local_recv_buf_bytes,
+            on_full: self.local_on_full,
+            frag_allocator: None,
+            legacy_mode: self.legacy_mode,
+            messages: self.messages,
+            reassembly_timeout: self.reassembly_timeout,
+            on_reassembly_timeout: self.on_reassembly_timeout,
         }
         .build()
         .map_err(|e| BuildError::Downloader(e))?;
@@ -41,36 +102,231 @@ impl Builder {
         Builder {
             local_recv_buf_len: 1024,
             nack_duplicate_threshold_to_activate_fast_retransmit: 1024 * 1 / 2,
-            ratio_rto_to_one_rtt: 1.5,
+            min_rto: MIN_RTO,
+            max_rto: MAX_RTO,
             to_send_queue_len_cap: 1024,
             swnd_size_cap: 1024,
+            max_inflight_bytes: usize::MAX,
+            frag_checksum: false,
+            packet_checksum: false,
+            timestamps: false,
+            delimited: false,
+            strict_framing: false,
+            delayed_ack: None,
+            ordered: true,
+            local_recv_buf_bytes: usize::MAX,
+            local_on_full: OnFull::DropNew,
+            legacy_mode: false,
+            messages: false,
+            reassembly_timeout: None,
+            on_reassembly_timeout: OnReassemblyTimeout::Skip,
             mtu: 1300,
         }
     }
+
+    /// A starting point for the given profile, to be tweaked by the caller
+    pub fn preset(profile: Profile) -> Self {
+        let default = Self::default();
+        match profile {
+            Profile::Lan => Builder {
+                nack_duplicate_threshold_to_activate_fast_retransmit: 2,
+                mtu: 1400,
+                ..default
+            },
+            Profile::Wan => Builder {
+                local_recv_buf_len: u16::MAX as usize,
+                nack_duplicate_threshold_to_activate_fast_retransmit: 3,
+                to_send_queue_len_cap: 1024 * 64,
+                swnd_size_cap: u16::MAX as usize,
+                mtu: 1300,
+                ..default
+            },
+            Profile::LossyWireless => Builder {
+                nack_duplicate_threshold_to_activate_fast_retransmit: 1,
+                frag_checksum: true,
+                mtu: 1200,
+                ..default
+            },
+            Profile::RealTime => Builder {
+                local_recv_buf_len: 64,
+                nack_duplicate_threshold_to_activate_fast_retransmit: 1,
+                to_send_queue_len_cap: 64,
+                swnd_size_cap: 64,
+                min_rto: Duration::from_millis(20),
+                max_rto: Duration::from_secs(1),
+                delayed_ack: None,
+                ordered: false,
+                ..default
+            },
+        }
+    }
+}
+
+pub enum Profile {
+    /// Low latency and little loss
+    ///
+    /// - fast retransmit after 2 duplicate nacks
+    /// - MTU of 1400
+    Lan,
+    /// Long fat pipes
+    ///
+    /// - receive buffer and send window of `u16::MAX` frags
+    /// - to-send queue of 64 Ki slices
+    /// - fast retransmit after 3 duplicate nacks
+    /// - MTU of 1300
+    Wan,
+    /// Frequent loss and corruption
+    ///
+    /// - fast retransmit after 1 duplicate nack
+    /// - per-fragment checksum
+    /// - MTU of 1200
+    LossyWireless,
+    /// Latency over throughput
+    ///
+    /// - receive buffer, send window and to-send queue of 64
+    /// - fast retransmit after 1 duplicate nack
+    /// - RTO between 20 ms and 1 s
+    /// - no delayed acks
+    /// - pushes emitted as they arrive, without head-of-line blocking
+    RealTime,
 }
 
 #[derive(Debug)]
 pub enum BuildError {
     Downloader(downloader::BuildError),
     Uploader(uploader::BuildError),
+    /// `DelayedAckPolicy::max_delay` is not below `min_rto`
+    AckDelayNotBelowMinRto,
 }
 
 pub struct SetUploadState {
-    pub remote_rwnd_size: u16,
+    pub remote_rwnd_size: u32,
     pub remote_nack: Seq32,
+    /// The cumulative nack to stamp into outgoing packet headers; see `Downloader::nack`
     pub local_next_seq_to_receive: Seq32,
     pub remote_seqs_to_ack: Vec<Seq32>,
     pub acked_local_seqs: Vec<Seq32>,
     pub local_rwnd_size: usize,
+    /// Bytes the local receive buffer takes before dropping pushes held out of order
+    pub local_recv_buf_bytes_left: usize,
+    /// A push landed beyond the local rwnd, so the peer should learn the current rwnd at once
+    pub local_rwnd_probed: bool,
+    /// Contiguous runs of the remote seqs received but not yet deliverable
+    ///
+    /// Every seq below `local_next_seq_to_receive` and between the runs is still missing.
+    pub sack_ranges: Vec<Range<Seq32>>,
+    /// The packet arrived congestion-experienced, so the peer should learn it at once
+    pub ce_marked: bool,
+    /// The peer echoed a congestion-experienced mark
+    pub remote_ece: bool,
+    /// The `ts_val` of the packet, to be echoed back as `ts_ecr`
+    pub remote_ts_val: Option<u32>,
+    /// The `ts_ecr` of the packet, echoing a `ts_val` stamped by the local uploader
+    pub remote_ts_ecr: Option<u32>,
+    /// Ids of the `Ping`s from the peer, each to be answered with a `Pong`
+    pub remote_pings: Vec<Seq32>,
+    /// Ids of the `Pong`s from the peer, answering the local `Ping`s
+    pub pongs: Vec<Seq32>,
+    /// The peer restarted its sequence space, so whatever was received but not yet emitted is discarded
+    pub reset: bool,
+    /// The epoch of a `Reset` from the peer, taken or replayed, to be acknowledged with a `ResetAck`
+    pub remote_reset_epoch: Option<Seq32>,
+    /// Epochs of the `ResetAck`s from the peer, acknowledging the local `Reset`s
+    pub reset_acks: Vec<Seq32>,
+    /// Bytes read from the datagram, short of its end if fragments beyond `DownloaderBuilder::max_frags_per_packet` were left undecoded or up to the start of a `truncated_push`
+    pub bytes_consumed: usize,
+    /// Pushes of the packet landing at the start of the rwnd, sorted at once
+    pub in_order_pushes: usize,
+    /// Pushes of the packet held out of order in the rwnd, duplicates included
+    pub reordered_pushes: usize,
+    /// The seq of a push clipped by the end of the datagram, dropped while the fragments before it are kept
+    ///
+    /// Always `None` under `strict_framing`, which fails the whole packet instead.
+    pub truncated_push: Option<Seq32>,
+}
+
+impl SetUploadState {
+    /// Fold the state of a `later` packet into this one, as if both came in one packet
+    ///
+    /// The seqs to act on are concatenated and the flags and counts are accumulated. Of the sequence fields, the furthest across any wraparound wins, along with the `remote_rwnd_size` advertised with the winning `remote_nack`; `later` wins ties. The local rwnd and buffer bytes take the smaller of the two, and the timestamps come from `later` where it has any. `sack_ranges` always come from `later`, for an empty list there means the gaps have been filled.
+    ///
+    /// On a `reset` in `later`, nothing received before it is acked or taken as the local nack.
+    #[must_use]
+    pub fn merge(self, later: SetUploadState) -> SetUploadState {
+        let (remote_rwnd_size, remote_nack) = match later.remote_nack < self.remote_nack {
+            true => (self.remote_rwnd_size, self.remote_nack),
+            false => (later.remote_rwnd_size, later.remote_nack),
+        };
+        let (local_next_seq_to_receive, mut remote_seqs_to_ack) = match later.reset {
+            // the acks so far belong to the old sequence space
+            true => (later.local_next_seq_to_receive, Vec::new()),
+            false => (
+                Seq32::max(
+                    self.local_next_seq_to_receive,
+                    later.local_next_seq_to_receive,
+                ),
+                self.remote_seqs_to_ack,
+            ),
+        };
+        remote_seqs_to_ack.extend(later.remote_seqs_to_ack);
+        let mut acked_local_seqs = self.acked_local_seqs;
+        acked_local_seqs.extend(later.acked_local_seqs);
+        let mut remote_pings = self.remote_pings;
+        remote_pings.extend(later.remote_pings);
+        let mut pongs = self.pongs;
+        pongs.extend(later.pongs);
+        let mut reset_acks = self.reset_acks;
+        reset_acks.extend(later.reset_acks);
+        SetUploadState {
+            remote_rwnd_size,
+            remote_nack,
+            local_next_seq_to_receive,
+            remote_seqs_to_ack,
+            acked_local_seqs,
+            local_rwnd_size: usize::min(self.local_rwnd_size, later.local_rwnd_size),
+            local_recv_buf_bytes_left: usize::min(
+                self.local_recv_buf_bytes_left,
+                later.local_recv_buf_bytes_left,
+            ),
+            local_rwnd_probed: self.local_rwnd_probed || later.local_rwnd_probed,
+            sack_ranges: later.sack_ranges,
+            ce_marked: self.ce_marked || later.ce_marked,
+            remote_ece: self.remote_ece || later.remote_ece,
+            remote_ts_val: later.remote_ts_val.or(self.remote_ts_val),
+            remote_ts_ecr: later.remote_ts_ecr.or(self.remote_ts_ecr),
+            remote_pings,
+            pongs,
+            reset: self.reset || later.reset,
+            remote_reset_epoch: later.remote_reset_epoch.or(self.remote_reset_epoch),
+            reset_acks,
+            bytes_consumed: self.bytes_consumed + later.bytes_consumed,
+            in_order_pushes: self.in_order_pushes + later.in_order_pushes,
+            reordered_pushes: self.reordered_pushes + later.reordered_pushes,
+            truncated_push: later.truncated_push.or(self.truncated_push),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Instant;
+    use std::time::{Duration, Instant};
 
-    use crate::utils::buf::{BufSlice, BufWtr, OwnedBufWtr};
+    use crate::{
+        protocol::{
+            frag::{FragCommand, PUSH_HDR_LEN},
+            packet::Packet,
+            packet_hdr::{PACKET_HDR_LEN, PACKET_LEN_LEN},
+        },
+        utils::{
+            buf::{BufSlice, BufWtr, OwnedBufWtr},
+            Seq32,
+        },
+    };
 
-    use super::Builder;
+    use super::{
+        BuildError, Builder, DelayedAckPolicy, Downloader, NewReno, Profile, SetUploadState,
+        Uploader,
+    };
 
     const MTU: usize = 1024;
 
@@ -80,20 +336,20 @@ mod tests {
         let (mut upload1, mut download1) = Builder {
             local_recv_buf_len: 2,
             nack_duplicate_threshold_to_activate_fast_retransmit: 0,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: MTU,
+            ..Builder::default()
         }
         .build()
         .unwrap();
         let (mut upload2, mut download2) = Builder {
             local_recv_buf_len: 2,
             nack_duplicate_threshold_to_activate_fast_retransmit: 0,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: MTU,
+            ..Builder::default()
         }
         .build()
         .unwrap();
@@ -114,7 +370,9 @@ mod tests {
             assert_eq!(
                 inflight.data(),
                 vec![
+                    2, // ver
                     0, 2, // rwnd
+                    0, // rwnd shift
                     0, 0, 0, 0, // nack
                     0, 0, 0, 0, // seq
                     0, // cmd (Push)
@@ -124,7 +382,7 @@ mod tests {
             );
 
             let inflight = inflight.into_slice();
-            let upload2_changes = download2.write(inflight).unwrap();
+            let upload2_changes = download2.write(inflight, &now).unwrap();
             upload2.set_state(upload2_changes, &now).unwrap();
 
             let recv2 = download2.emit().unwrap();
@@ -139,35 +397,73 @@ mod tests {
 
             packets[0].append_to(&mut inflight).unwrap();
 
-            //                               rwnd] [     nack] [      seq] [cmd
-            assert_eq!(inflight.data(), vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 1]);
+            //                            ver]  rwnd] [sft [     nack] [      seq] [cmd
+            assert_eq!(inflight.data(), vec![2, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]);
 
             let inflight = inflight.into_slice();
-            let upload1_changes = download1.write(inflight).unwrap();
+            let upload1_changes = download1.write(inflight, &now).unwrap();
             upload1.set_state(upload1_changes, &now).unwrap();
         }
     }
 
+    #[test]
+    fn test_few_1_borrowed() {
+        let now = Instant::now();
+        let (_, mut download2) = Builder {
+            local_recv_buf_len: 2,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+
+        // push: 1 -> 2
+        let mut recv_buf = vec![
+            2, // ver
+            0, 2, // rwnd
+            0, // rwnd shift
+            0, 0, 0, 0, // nack
+            0, 0, 0, 0, // seq
+            0, // cmd (Push)
+            0, 0, 0, 3, // len
+            0, 1, 2, // data
+        ];
+        let upload2_changes = download2.write_slice(&recv_buf, &now).unwrap();
+        assert_eq!(upload2_changes.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        assert_eq!(upload2_changes.local_next_seq_to_receive.to_u32(), 1);
+
+        // the body outlives the reuse of the buffer
+        recv_buf.fill(0);
+        let recv2 = download2.emit().unwrap();
+        assert_eq!(recv2.data(), vec![0, 1, 2]);
+
+        // ack: 1 <- 2
+        //                 ver]  rwnd] [sft [     nack] [      seq] [cmd
+        let recv_buf = vec![2, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1];
+        let upload2_changes = download2.write_slice(&recv_buf, &now).unwrap();
+        assert_eq!(upload2_changes.acked_local_seqs, vec![Seq32::from_u32(0)]);
+        assert_eq!(upload2_changes.remote_rwnd_size, 1);
+    }
+
     #[test]
     fn test_rto() {
         let mut now = Instant::now();
         let (mut upload1, mut _download1) = Builder {
             local_recv_buf_len: 2,
             nack_duplicate_threshold_to_activate_fast_retransmit: 0,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: MTU,
+            ..Builder::default()
         }
         .build()
         .unwrap();
         let (mut upload2, mut download2) = Builder {
             local_recv_buf_len: 2,
             nack_duplicate_threshold_to_activate_fast_retransmit: 0,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: MTU,
+            ..Builder::default()
         }
         .build()
         .unwrap();
@@ -188,7 +484,9 @@ mod tests {
             assert_eq!(
                 inflight.data(),
                 vec![
+                    2, // ver
                     0, 2, // rwnd
+                    0, // rwnd shift
                     0, 0, 0, 0, // nack
                     0, 0, 0, 0, // seq
                     0, // cmd (Push)
@@ -198,7 +496,7 @@ mod tests {
             );
 
             let inflight = inflight.into_slice();
-            let upload2_changes = download2.write(inflight).unwrap();
+            let upload2_changes = download2.write(inflight, &now).unwrap();
             upload2.set_state(upload2_changes, &now).unwrap();
 
             let recv2 = download2.emit().unwrap();
@@ -213,8 +511,8 @@ mod tests {
 
             packets[0].append_to(&mut inflight).unwrap();
 
-            //                               rwnd] [     nack] [      seq] [cmd
-            assert_eq!(inflight.data(), vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 1]);
+            //                            ver]  rwnd] [sft [     nack] [      seq] [cmd
+            assert_eq!(inflight.data(), vec![2, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]);
 
             // dropped
         }
@@ -231,7 +529,9 @@ mod tests {
             assert_eq!(
                 inflight.data(),
                 vec![
+                    2, // ver
                     0, 2, // rwnd
+                    0, // rwnd shift
                     0, 0, 0, 0, // nack
                     0, 0, 0, 0, // seq
                     0, // cmd (Push)
@@ -241,4 +541,1023 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_ack_retransmitted_delivered_push() {
+        let mut now = Instant::now();
+        let (mut upload1, mut download1) = Builder {
+            local_recv_buf_len: 2,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+        let (mut upload2, mut download2) = Builder {
+            local_recv_buf_len: 2,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+
+        // push: 1 -> 2
+        {
+            upload1
+                .write(BufSlice::from_bytes(vec![0, 1, 2]))
+                .map_err(|_| ())
+                .unwrap();
+            let packets = upload1.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload2_changes = download2.write(inflight.into_slice(), &now).unwrap();
+            upload2.set_state(upload2_changes, &now).unwrap();
+            assert_eq!(download2.emit().unwrap().data(), vec![0, 1, 2]);
+        }
+        // ack: 1 <- 2
+        {
+            let packets = upload2.emit(&now);
+            assert_eq!(packets.len(), 1);
+
+            // dropped
+        }
+        now += upload1.rto();
+        // retransmit: 1 -> 2
+        {
+            let packets = upload1.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload2_changes = download2.write(inflight.into_slice(), &now).unwrap();
+
+            // the push has been delivered, but it is still acked
+            assert_eq!(upload2_changes.remote_seqs_to_ack.len(), 1);
+            upload2.set_state(upload2_changes, &now).unwrap();
+            assert!(download2.emit().is_none());
+        }
+        // ack again without waiting: 1 <- 2
+        {
+            let packets = upload2.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+
+            //                            ver]  rwnd] [sft [     nack] [      seq] [cmd
+            assert_eq!(inflight.data(), vec![2, 0, 2, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]);
+
+            let upload1_changes = download1.write(inflight.into_slice(), &now).unwrap();
+            upload1.set_state(upload1_changes, &now).unwrap();
+        }
+        now += upload1.rto();
+        assert_eq!(upload1.emit(&now).len(), 0);
+    }
+
+    #[test]
+    fn test_presets() {
+        let lan = Builder::preset(Profile::Lan);
+        assert_eq!(lan.nack_duplicate_threshold_to_activate_fast_retransmit, 2);
+        assert_eq!(lan.mtu, 1400);
+
+        let wan = Builder::preset(Profile::Wan);
+        assert_eq!(wan.local_recv_buf_len, u16::MAX as usize);
+        assert_eq!(wan.swnd_size_cap, u16::MAX as usize);
+        assert_eq!(wan.to_send_queue_len_cap, 1024 * 64);
+        assert_eq!(wan.nack_duplicate_threshold_to_activate_fast_retransmit, 3);
+        assert_eq!(wan.mtu, 1300);
+
+        let lossy = Builder::preset(Profile::LossyWireless);
+        assert_eq!(
+            lossy.nack_duplicate_threshold_to_activate_fast_retransmit,
+            1
+        );
+        assert!(lossy.frag_checksum);
+        assert_eq!(lossy.mtu, 1200);
+
+        let real_time = Builder::preset(Profile::RealTime);
+        assert_eq!(real_time.local_recv_buf_len, 64);
+        assert_eq!(real_time.swnd_size_cap, 64);
+        assert_eq!(real_time.to_send_queue_len_cap, 64);
+        assert_eq!(
+            real_time.nack_duplicate_threshold_to_activate_fast_retransmit,
+            1
+        );
+        assert_eq!(real_time.min_rto, Duration::from_millis(20));
+        assert_eq!(real_time.max_rto, Duration::from_secs(1));
+        assert!(real_time.delayed_ack.is_none());
+        assert!(!real_time.ordered);
+
+        for profile in [
+            Profile::Lan,
+            Profile::Wan,
+            Profile::LossyWireless,
+            Profile::RealTime,
+        ] {
+            Builder::preset(profile).build().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ack_delay_below_min_rto() {
+        let builder = |max_delay| Builder {
+            delayed_ack: Some(DelayedAckPolicy {
+                max_delay,
+                max_unacked: 2,
+            }),
+            min_rto: Duration::from_millis(100),
+            ..Builder::default()
+        };
+        assert!(builder(Duration::from_millis(40)).build().is_ok());
+        assert!(matches!(
+            builder(Duration::from_millis(100)).build(),
+            Err(BuildError::AckDelayNotBelowMinRto)
+        ));
+    }
+
+    #[test]
+    fn test_acks_accumulate_across_writes() {
+        let now = Instant::now();
+        let (mut upload1, _download1) = Builder {
+            local_recv_buf_len: 2,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+        let (mut upload2, mut download2) = Builder {
+            local_recv_buf_len: 2,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+        // 2 has advertised room for both pushes
+        upload1
+            .set_state(
+                SetUploadState {
+                    remote_rwnd_size: 2,
+                    remote_nack: Seq32::from_u32(0),
+                    local_next_seq_to_receive: Seq32::from_u32(0),
+                    remote_seqs_to_ack: vec![],
+                    acked_local_seqs: vec![],
+                    local_rwnd_size: 2,
+                    local_recv_buf_bytes_left: usize::MAX,
+                    local_rwnd_probed: false,
+                    sack_ranges: vec![],
+                    ce_marked: false,
+                    remote_ece: false,
+                    remote_ts_val: None,
+                    remote_ts_ecr: None,
+                    remote_pings: vec![],
+                    pongs: vec![],
+                    reset: false,
+                    remote_reset_epoch: None,
+                    reset_acks: vec![],
+                    bytes_consumed: 0,
+                    in_order_pushes: 0,
+                    reordered_pushes: 0,
+                    truncated_push: None,
+                },
+                &now,
+            )
+            .unwrap();
+
+        // two packets: 1 -> 2
+        for data in [vec![0, 1, 2], vec![3, 4]] {
+            upload1
+                .write(BufSlice::from_bytes(data))
+                .map_err(|_| ())
+                .unwrap();
+            let packets = upload1.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload2_changes = download2.write(inflight.into_slice(), &now).unwrap();
+            upload2.set_state(upload2_changes, &now).unwrap();
+        }
+        // both acks in one packet: 1 <- 2
+        {
+            let packets = upload2.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+
+            assert_eq!(
+                inflight.data(),
+                vec![
+                    2, // ver
+                    0, 0, // rwnd
+                    0, // rwnd shift
+                    0, 0, 0, 2, // nack
+                    0, 0, 0, 0, 1, // ack seq(0)
+                    0, 0, 0, 1, 1, // ack seq(1)
+                ]
+            );
+        }
+        assert_eq!(upload2.emit(&now).len(), 0);
+    }
+
+    #[test]
+    fn test_no_delayed_ack() {
+        let now = Instant::now();
+        let (mut upload1, _download1) = Builder::default().build().unwrap();
+        let (mut upload2, mut download2) = Builder::default().build().unwrap();
+        // acks are not held back along with the pushes
+        upload2.cork();
+        upload2.set_pacing(true);
+        // 2 has advertised room for all the pushes
+        upload1
+            .set_state(merge_state(0, 4, 0, vec![]), &now)
+            .unwrap();
+
+        for seq in 0..4 {
+            upload1
+                .write(BufSlice::from_bytes(vec![seq as u8]))
+                .map_err(|_| ())
+                .unwrap();
+            let packets = upload1.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let changes = download2.write(inflight.into_slice(), &now).unwrap();
+            upload2.set_state(changes, &now).unwrap();
+
+            let acks: Vec<Seq32> = upload2
+                .emit(&now)
+                .iter()
+                .flat_map(|packet| packet.frags())
+                .filter(|frag| matches!(frag.cmd(), FragCommand::Ack))
+                .map(|frag| frag.seq())
+                .collect();
+            assert_eq!(acks, vec![Seq32::from_u32(seq)]);
+        }
+    }
+
+    #[test]
+    fn test_corrupted_on_egress() {
+        let now = Instant::now();
+        let (mut upload1, _download1) = Builder {
+            frag_checksum: true,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+        let (mut upload2, mut download2) = Builder {
+            frag_checksum: true,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+
+        upload1
+            .write(BufSlice::from_bytes(vec![0, 1, 2]))
+            .map_err(|_| ())
+            .unwrap();
+        let packets = upload1.emit(&now);
+        assert_eq!(packets.len(), 1);
+        let mut inflight = OwnedBufWtr::new(1024, 0);
+        packets[0].append_to(&mut inflight).unwrap();
+
+        // the encoded bytes are the caller's to inspect or rewrite before sending
+        let mut datagram = inflight.data().to_vec();
+        *datagram.last_mut().unwrap() ^= 1;
+
+        let upload2_changes = download2
+            .write(BufSlice::from_bytes(datagram), &now)
+            .unwrap();
+        assert!(upload2_changes.remote_seqs_to_ack.is_empty());
+        upload2.set_state(upload2_changes, &now).unwrap();
+        assert_eq!(download2.stat().corrupted_frags, 1);
+        assert!(download2.emit().is_none());
+    }
+
+    #[test]
+    fn test_advertise_rwnd_on_probe() {
+        let mut now = Instant::now();
+        let (mut upload1, mut download1) = Builder::default().build().unwrap();
+        let (mut upload2, mut download2) = Builder {
+            local_recv_buf_len: 2,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+        // 1 believes 2 has room for three pushes
+        upload1
+            .set_state(
+                SetUploadState {
+                    remote_rwnd_size: 3,
+                    remote_nack: Seq32::from_u32(0),
+                    local_next_seq_to_receive: Seq32::from_u32(0),
+                    remote_seqs_to_ack: vec![],
+                    acked_local_seqs: vec![],
+                    local_rwnd_size: 2,
+                    local_recv_buf_bytes_left: usize::MAX,
+                    local_rwnd_probed: false,
+                    sack_ranges: vec![],
+                    ce_marked: false,
+                    remote_ece: false,
+                    remote_ts_val: None,
+                    remote_ts_ecr: None,
+                    remote_pings: vec![],
+                    pongs: vec![],
+                    reset: false,
+                    remote_reset_epoch: None,
+                    reset_acks: vec![],
+                    bytes_consumed: 0,
+                    in_order_pushes: 0,
+                    reordered_pushes: 0,
+                    truncated_push: None,
+                },
+                &now,
+            )
+            .unwrap();
+
+        // three pushes: 1 -> 2
+        for data in [vec![0], vec![1], vec![2]] {
+            upload1
+                .write(BufSlice::from_bytes(data))
+                .map_err(|_| ())
+                .unwrap();
+            let packets = upload1.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload2_changes = download2.write(inflight.into_slice(), &now).unwrap();
+            upload2.set_state(upload2_changes, &now).unwrap();
+        }
+        // acks and the full rwnd: 1 <- 2
+        {
+            let packets = upload2.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload1_changes = download1.write(inflight.into_slice(), &now).unwrap();
+            assert_eq!(upload1_changes.remote_rwnd_size, 0);
+            upload1.set_state(upload1_changes, &now).unwrap();
+        }
+        // zero window probe while 2 is still full: 1 -> 2
+        now += upload1.rto();
+        {
+            let packets = upload1.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload2_changes = download2.write(inflight.into_slice(), &now).unwrap();
+            assert!(upload2_changes.remote_seqs_to_ack.is_empty());
+            assert!(upload2_changes.local_rwnd_probed);
+            upload2.set_state(upload2_changes, &now).unwrap();
+        }
+        // 2 answers with its current rwnd at once, with no frags: 1 <- 2
+        {
+            let packets = upload2.emit(&now);
+            assert_eq!(packets.len(), 1);
+            assert!(packets[0].frags().is_empty());
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload1_changes = download1.write(inflight.into_slice(), &now).unwrap();
+            assert_eq!(upload1_changes.remote_rwnd_size, 0);
+            upload1.set_state(upload1_changes, &now).unwrap();
+        }
+        assert_eq!(upload2.emit(&now).len(), 0);
+
+        // 2 drains; the next probe is accepted: 1 -> 2
+        assert_eq!(download2.emit_all().len(), 2);
+        now += upload1.rto();
+        {
+            let packets = upload1.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload2_changes = download2.write(inflight.into_slice(), &now).unwrap();
+            assert!(!upload2_changes.local_rwnd_probed);
+            upload2.set_state(upload2_changes, &now).unwrap();
+        }
+        // the ack carries the non-zero rwnd: 1 <- 2
+        {
+            let packets = upload2.emit(&now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload1_changes = download1.write(inflight.into_slice(), &now).unwrap();
+            assert_eq!(upload1_changes.remote_rwnd_size, 1);
+            upload1.set_state(upload1_changes, &now).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ecn_echo() {
+        let now = Instant::now();
+        let (mut upload1, mut download1) = Builder::default().build().unwrap();
+        let (mut upload2, mut download2) = Builder::default().build().unwrap();
+        upload1.set_congestion_control(Some(Box::new(NewReno::new(4))));
+        upload1
+            .set_state(
+                SetUploadState {
+                    remote_rwnd_size: 3,
+                    remote_nack: Seq32::from_u32(0),
+                    local_next_seq_to_receive: Seq32::from_u32(0),
+                    remote_seqs_to_ack: vec![],
+                    acked_local_seqs: vec![],
+                    local_rwnd_size: 2,
+                    local_recv_buf_bytes_left: usize::MAX,
+                    local_rwnd_probed: false,
+                    sack_ranges: vec![],
+                    ce_marked: false,
+                    remote_ece: false,
+                    remote_ts_val: None,
+                    remote_ts_ecr: None,
+                    remote_pings: vec![],
+                    pongs: vec![],
+                    reset: false,
+                    remote_reset_epoch: None,
+                    reset_acks: vec![],
+                    bytes_consumed: 0,
+                    in_order_pushes: 0,
+                    reordered_pushes: 0,
+                    truncated_push: None,
+                },
+                &now,
+            )
+            .unwrap();
+
+        // push marked on the way: 1 -> 2
+        {
+            upload1
+                .write(BufSlice::from_bytes(vec![0]))
+                .map_err(|_| ())
+                .unwrap();
+            let packets = upload1.emit(&now);
+            assert_eq!(packets.len(), 1);
+            assert!(!packets[0].hdr().ece());
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload2_changes = download2
+                .write_ce_marked(inflight.into_slice(), &now)
+                .unwrap();
+            assert!(upload2_changes.ce_marked);
+            upload2.set_state(upload2_changes, &now).unwrap();
+        }
+        // ack with the echo: 1 <- 2
+        {
+            let packets = upload2.emit(&now);
+            assert_eq!(packets.len(), 1);
+            assert!(packets[0].hdr().ece());
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            let upload1_changes = download1.write(inflight.into_slice(), &now).unwrap();
+            assert!(upload1_changes.remote_ece);
+            assert!(!upload1_changes.ce_marked);
+            upload1.set_state(upload1_changes, &now).unwrap();
+        }
+        // one ack grew cwnd to 5 before the echo halved it
+        assert_eq!(upload1.cwnd(), Some(2));
+        // echoed only once
+        assert!(upload2.emit(&now).is_empty());
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let now = Instant::now();
+        let (mut upload1, mut download1) = Builder::default().build().unwrap();
+        let (mut upload2, mut download2) = Builder::default().build().unwrap();
+
+        // ping: 1 -> 2
+        upload1.ping(Seq32::from_u32(7));
+        let packets = upload1.emit(&now);
+        assert_eq!(packets.len(), 1);
+        let mut inflight = OwnedBufWtr::new(1024, 0);
+        packets[0].append_to(&mut inflight).unwrap();
+        let upload2_changes = download2.write(inflight.into_slice(), &now).unwrap();
+        assert_eq!(upload2_changes.remote_pings, vec![Seq32::from_u32(7)]);
+        upload2.set_state(upload2_changes, &now).unwrap();
+
+        // pong: 1 <- 2
+        let packets = upload2.emit(&now);
+        assert_eq!(packets.len(), 1);
+        let mut inflight = OwnedBufWtr::new(1024, 0);
+        packets[0].append_to(&mut inflight).unwrap();
+        let upload1_changes = download1.write(inflight.into_slice(), &now).unwrap();
+        assert_eq!(upload1_changes.pongs, vec![Seq32::from_u32(7)]);
+        assert_eq!(upload1_changes.remote_pings, vec![]);
+        upload1.set_state(upload1_changes, &now).unwrap();
+
+        // answered once
+        assert!(upload2.emit(&now).is_empty());
+        assert!(upload1.emit(&now).is_empty());
+    }
+
+    #[test]
+    fn test_write_message() {
+        let now = Instant::now();
+        // three body bytes per push
+        let mtu = PACKET_HDR_LEN + PUSH_HDR_LEN + 3;
+        let (mut upload1, mut download1) = Builder {
+            mtu,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+        let (mut upload2, mut download2) = Builder {
+            mtu,
+            messages: true,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+
+        for message in [&b"hello"[..], b"ab", b"cd", b"seven!!"] {
+            upload1
+                .write_message(BufSlice::from_bytes(message.to_vec()))
+                .map_err(|_| ())
+                .unwrap();
+        }
+        loop {
+            let packets = upload1.emit(&now);
+            if packets.is_empty() {
+                break;
+            }
+            for packet in packets {
+                let mut inflight = OwnedBufWtr::new(mtu, 0);
+                packet.append_to(&mut inflight).unwrap();
+                let changes = download2.write(inflight.into_slice(), &now).unwrap();
+                upload2.set_state(changes, &now).unwrap();
+            }
+            for packet in upload2.emit(&now) {
+                let mut inflight = OwnedBufWtr::new(mtu, 0);
+                packet.append_to(&mut inflight).unwrap();
+                let changes = download1.write(inflight.into_slice(), &now).unwrap();
+                upload1.set_state(changes, &now).unwrap();
+            }
+        }
+
+        let mut messages = Vec::new();
+        while let Some(frags) = download2.emit_message().unwrap() {
+            let message: Vec<u8> = frags.iter().flat_map(|x| x.data().to_vec()).collect();
+            messages.push((frags.len(), message));
+        }
+        assert_eq!(
+            messages,
+            vec![
+                (2, b"hello".to_vec()),
+                (1, b"ab".to_vec()),
+                (1, b"cd".to_vec()),
+                (3, b"seven!!".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_delimited() {
+        let now = Instant::now();
+        // three body bytes per push
+        let mtu = PACKET_HDR_LEN + PACKET_LEN_LEN + PUSH_HDR_LEN + 3;
+        let (mut upload1, mut download1) = Builder {
+            delimited: true,
+            mtu,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+        let (mut upload2, mut download2) = Builder {
+            delimited: true,
+            mtu,
+            ..Builder::default()
+        }
+        .build()
+        .unwrap();
+
+        // 1 learns the rwnd of 2
+        upload2.ping(Seq32::from_u32(0));
+        let mut inflight = OwnedBufWtr::new(mtu, 0);
+        for packet in upload2.emit(&now) {
+            packet.append_to(&mut inflight).unwrap();
+        }
+        let states = download1.write_stream(inflight.into_slice(), &now).unwrap();
+        assert_eq!(states.len(), 1);
+        for state in states {
+            upload1.set_state(state, &now).unwrap();
+        }
+
+        upload1
+            .write(BufSlice::from_bytes(b"0123456789".to_vec()))
+            .map_err(|_| ())
+            .unwrap();
+        // the pong, then four pushes
+        let packets = upload1.emit(&now);
+        assert_eq!(packets.len(), 5);
+        let mut inflight = OwnedBufWtr::new(mtu * packets.len(), 0);
+        for packet in packets {
+            let start = inflight.data_len();
+            packet.append_to(&mut inflight).unwrap();
+            assert!(inflight.data_len() - start <= mtu);
+        }
+        let states = download2.write_stream(inflight.into_slice(), &now).unwrap();
+        assert_eq!(states.len(), 5);
+        let mut data = Vec::new();
+        while let Some(slice) = download2.emit() {
+            data.extend_from_slice(slice.data());
+        }
+        assert_eq!(data, b"0123456789");
+    }
+
+    #[test]
+    fn test_piggyback() {
+        let now = Instant::now();
+        let (mut upload1, mut download1) = Builder::default().build().unwrap();
+        let (mut upload2, mut download2) = Builder::default().build().unwrap();
+
+        // push: 1 -> 2
+        upload1
+            .write(BufSlice::from_bytes(vec![0, 1, 2]))
+            .map_err(|_| ())
+            .unwrap();
+        let packets = upload1.emit(&now);
+        let mut inflight = OwnedBufWtr::new(1024, 0);
+        packets[0].append_to(&mut inflight).unwrap();
+        let upload2_changes = download2.write(inflight.into_slice(), &now).unwrap();
+        upload2.set_state(upload2_changes, &now).unwrap();
+
+        // ack and push in one packet: 1 <- 2
+        upload2
+            .write(BufSlice::from_bytes(vec![3, 4]))
+            .map_err(|_| ())
+            .unwrap();
+        let packets = upload2.emit(&now);
+        assert_eq!(packets.len(), 1);
+        let mut inflight = OwnedBufWtr::new(1024, 0);
+        packets[0].append_to(&mut inflight).unwrap();
+        assert_eq!(
+            inflight.data(),
+            vec![
+                2, // ver
+                3, 255, // rwnd
+                0,   // rwnd shift
+                0, 0, 0, 1, // nack
+                0, 0, 0, 0, // seq
+                1, // cmd (Ack)
+                0, 0, 0, 0, // seq
+                0, // cmd (Push)
+                0, 0, 0, 2, // len
+                3, 4 // data
+            ]
+        );
+        let upload1_changes = download1.write(inflight.into_slice(), &now).unwrap();
+        assert_eq!(upload1_changes.acked_local_seqs, vec![Seq32::from_u32(0)]);
+        assert_eq!(upload1_changes.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        upload1.set_state(upload1_changes, &now).unwrap();
+        assert_eq!(download1.emit().unwrap().data(), vec![3, 4]);
+        assert_eq!(upload1.stat().bytes_acked, 3);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let t0 = Instant::now();
+        let builder = || Builder {
+            timestamps: true,
+            ..Builder::default()
+        };
+        let (mut upload1, mut download1) = builder().build().unwrap();
+        let (mut upload2, mut download2) = builder().build().unwrap();
+        let transmit = |packets: Vec<Packet>| {
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            inflight.into_slice()
+        };
+
+        // the first push is lost: 1 -> 2
+        upload1
+            .write(BufSlice::from_bytes(vec![0, 1, 2]))
+            .map_err(|_| ())
+            .unwrap();
+        let packets = upload1.emit(&t0);
+        assert_eq!(packets[0].hdr().timestamps().unwrap().ts_val, 0);
+
+        // the retransmission arrives: 1 -> 2
+        let t1 = t0 + upload1.rto();
+        let datagram = transmit(upload1.emit(&t1));
+        let t2 = t1 + Duration::from_millis(10);
+        let changes = download2.write(datagram, &t2).unwrap();
+        let rto_ms = upload1.rto().as_millis() as u32;
+        assert_eq!(changes.remote_ts_val, Some(rto_ms));
+        assert_eq!(changes.remote_ts_ecr, Some(0));
+        upload2.set_state(changes, &t2).unwrap();
+
+        // ack echoing the retransmission: 1 <- 2
+        let t3 = t2 + Duration::from_millis(20);
+        let packets = upload2.emit(&t3);
+        assert_eq!(packets[0].hdr().timestamps().unwrap().ts_ecr, rto_ms);
+        let t4 = t3 + Duration::from_millis(10);
+        let changes = download1.write(transmit(packets), &t4).unwrap();
+        upload1.set_state(changes, &t4).unwrap();
+
+        // the ack of a retransmission alone would sample nothing
+        assert_eq!(upload1.stat().srtt, Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn test_reset() {
+        let now = Instant::now();
+        let (mut upload1, _) = Builder::default().build().unwrap();
+        let (_, mut download2) = Builder::default().build().unwrap();
+        let deliver = |upload: &mut Uploader, download: &mut Downloader, now: &Instant| {
+            let packets = upload.emit(now);
+            assert_eq!(packets.len(), 1);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            download.write(inflight.into_slice(), now).unwrap()
+        };
+
+        // received but not yet emitted
+        upload1
+            .write(BufSlice::from_bytes(b"old".to_vec()))
+            .map_err(|_| ())
+            .unwrap();
+        let changes = deliver(&mut upload1, &mut download2, &now);
+        assert!(!changes.reset);
+        assert_eq!(changes.local_next_seq_to_receive.to_u32(), 1);
+
+        // 1 restarts from seq 0, which is too late for 2
+        let (mut upload1, _) = Builder::default().build().unwrap();
+        upload1
+            .write(BufSlice::from_bytes(b"new".to_vec()))
+            .map_err(|_| ())
+            .unwrap();
+        let changes = deliver(&mut upload1, &mut download2, &now);
+        assert!(!changes.reset);
+        assert_eq!(changes.local_next_seq_to_receive.to_u32(), 1);
+
+        // the reset leads the retransmission
+        upload1.reset(Seq32::from_u32(1));
+        let now = now + upload1.rto();
+        let changes = deliver(&mut upload1, &mut download2, &now);
+        assert!(changes.reset);
+        assert_eq!(changes.remote_reset_epoch, Some(Seq32::from_u32(1)));
+        assert_eq!(changes.local_next_seq_to_receive.to_u32(), 1);
+        assert_eq!(changes.remote_seqs_to_ack, vec![Seq32::from_u32(0)]);
+        assert_eq!(download2.emit().unwrap().data(), b"new");
+        assert!(download2.emit().is_none());
+    }
+
+    fn reset_transmit(packets: Vec<Packet>) -> BufSlice {
+        assert_eq!(packets.len(), 1);
+        let mut inflight = OwnedBufWtr::new(1024, 0);
+        packets[0].append_to(&mut inflight).unwrap();
+        inflight.into_slice()
+    }
+
+    #[test]
+    fn test_reset_lost() {
+        let t0 = Instant::now();
+        let (mut upload1, mut download1) = Builder::default().build().unwrap();
+        let (mut upload2, mut download2) = Builder::default().build().unwrap();
+
+        upload1.reset(Seq32::from_u32(1));
+        let packets = upload1.emit(&t0);
+        assert_eq!(packets.len(), 1);
+        // lost
+        assert!(upload1.emit(&t0).is_empty());
+        assert!(upload1.is_resetting());
+
+        // retransmitted on RTO
+        let t1 = t0 + upload1.rto();
+        let changes = download2
+            .write(reset_transmit(upload1.emit(&t1)), &t1)
+            .unwrap();
+        assert!(changes.reset);
+        upload2.set_state(changes, &t1).unwrap();
+
+        // acknowledged
+        let changes = download1
+            .write(reset_transmit(upload2.emit(&t1)), &t1)
+            .unwrap();
+        assert_eq!(changes.reset_acks, vec![Seq32::from_u32(1)]);
+        upload1.set_state(changes, &t1).unwrap();
+        assert!(!upload1.is_resetting());
+        assert!(upload1.emit(&(t1 + upload1.rto())).is_empty());
+    }
+
+    #[test]
+    fn test_reset_replayed() {
+        let now = Instant::now();
+        let (mut upload1, mut download1) = Builder::default().build().unwrap();
+        let (mut upload2, mut download2) = Builder::default().build().unwrap();
+
+        upload1.reset(Seq32::from_u32(1));
+        upload1
+            .write(BufSlice::from_bytes(b"a".to_vec()))
+            .map_err(|_| ())
+            .unwrap();
+        let reset = reset_transmit(upload1.emit(&now));
+        let replay = BufSlice::from_bytes(reset.data().to_vec());
+        let changes = download2.write(reset, &now).unwrap();
+        assert!(changes.reset);
+        upload2.set_state(changes, &now).unwrap();
+        let changes = download1
+            .write(reset_transmit(upload2.emit(&now)), &now)
+            .unwrap();
+        upload1.set_state(changes, &now).unwrap();
+
+        upload1
+            .write(BufSlice::from_bytes(b"b".to_vec()))
+            .map_err(|_| ())
+            .unwrap();
+        download2
+            .write(reset_transmit(upload1.emit(&now)), &now)
+            .unwrap();
+
+        // the replay is acknowledged again but discards nothing
+        let changes = download2.write(replay, &now).unwrap();
+        assert!(!changes.reset);
+        assert_eq!(changes.remote_reset_epoch, Some(Seq32::from_u32(1)));
+        assert_eq!(changes.local_next_seq_to_receive.to_u32(), 2);
+        assert_eq!(download2.emit().unwrap().data(), b"a");
+        assert_eq!(download2.emit().unwrap().data(), b"b");
+
+        // a new epoch is taken
+        let (mut upload1, _) = Builder::default().build().unwrap();
+        upload1.reset(Seq32::from_u32(2));
+        let changes = download2
+            .write(reset_transmit(upload1.emit(&now)), &now)
+            .unwrap();
+        assert!(changes.reset);
+        assert_eq!(changes.local_next_seq_to_receive.to_u32(), 0);
+    }
+
+    #[test]
+    fn test_reset_stale_epoch() {
+        let now = Instant::now();
+        let (_, mut download2) = Builder::default().build().unwrap();
+        let (mut upload2, _) = Builder::default().build().unwrap();
+
+        let (mut upload1, _) = Builder::default().build().unwrap();
+        upload1.reset(Seq32::from_u32(1));
+        upload1
+            .write(BufSlice::from_bytes(b"a".to_vec()))
+            .map_err(|_| ())
+            .unwrap();
+        let reset = reset_transmit(upload1.emit(&now));
+        let stale = BufSlice::from_bytes(reset.data().to_vec());
+        assert!(download2.write(reset, &now).unwrap().reset);
+        assert_eq!(download2.emit().unwrap().data(), b"a");
+
+        // 1 restarts again
+        let (mut upload1, mut download1) = Builder::default().build().unwrap();
+        upload1.reset(Seq32::from_u32(2));
+        upload1
+            .write(BufSlice::from_bytes(b"b".to_vec()))
+            .map_err(|_| ())
+            .unwrap();
+        let changes = download2
+            .write(reset_transmit(upload1.emit(&now)), &now)
+            .unwrap();
+        assert!(changes.reset);
+        upload2.set_state(changes, &now).unwrap();
+        let changes = download1
+            .write(reset_transmit(upload2.emit(&now)), &now)
+            .unwrap();
+        upload1.set_state(changes, &now).unwrap();
+        upload1
+            .write(BufSlice::from_bytes(b"c".to_vec()))
+            .map_err(|_| ())
+            .unwrap();
+        let changes = download2
+            .write(reset_transmit(upload1.emit(&now)), &now)
+            .unwrap();
+        assert_eq!(changes.local_next_seq_to_receive.to_u32(), 2);
+
+        // the delayed reset of the older epoch is acknowledged but not taken
+        let changes = download2.write(stale, &now).unwrap();
+        assert!(!changes.reset);
+        assert_eq!(changes.remote_reset_epoch, Some(Seq32::from_u32(1)));
+        assert_eq!(changes.local_next_seq_to_receive.to_u32(), 2);
+        assert_eq!(download2.emit().unwrap().data(), b"b");
+        assert_eq!(download2.emit().unwrap().data(), b"c");
+        assert!(download2.emit().is_none());
+    }
+
+    fn merge_state(
+        remote_nack: u32,
+        remote_rwnd_size: u32,
+        local_next_seq_to_receive: u32,
+        remote_seqs_to_ack: Vec<u32>,
+    ) -> SetUploadState {
+        SetUploadState {
+            remote_rwnd_size,
+            remote_nack: Seq32::from_u32(remote_nack),
+            local_next_seq_to_receive: Seq32::from_u32(local_next_seq_to_receive),
+            remote_seqs_to_ack: remote_seqs_to_ack
+                .into_iter()
+                .map(Seq32::from_u32)
+                .collect(),
+            acked_local_seqs: vec![],
+            local_rwnd_size: 8,
+            local_recv_buf_bytes_left: usize::MAX,
+            local_rwnd_probed: false,
+            sack_ranges: vec![],
+            ce_marked: false,
+            remote_ece: false,
+            remote_ts_val: None,
+            remote_ts_ecr: None,
+            remote_pings: vec![],
+            pongs: vec![],
+            reset: false,
+            remote_reset_epoch: None,
+            reset_acks: vec![],
+            bytes_consumed: 10,
+            in_order_pushes: 1,
+            reordered_pushes: 0,
+            truncated_push: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_states() {
+        let now = Instant::now();
+        let (mut upload1, _) = Builder::default().build().unwrap();
+        let (_, mut download2) = Builder::default().build().unwrap();
+        upload1
+            .set_state(merge_state(0, 1024, 0, vec![]), &now)
+            .unwrap();
+        let mut states = Vec::new();
+        for data in [vec![0, 1, 2], vec![3, 4]] {
+            upload1
+                .write(BufSlice::from_bytes(data))
+                .map_err(|_| ())
+                .unwrap();
+            let packets = upload1.emit(&now);
+            let mut inflight = OwnedBufWtr::new(1024, 0);
+            packets[0].append_to(&mut inflight).unwrap();
+            states.push(download2.write(inflight.into_slice(), &now).unwrap());
+        }
+        let later = states.pop().unwrap();
+        let earlier = states.pop().unwrap();
+        let bytes_consumed = earlier.bytes_consumed + later.bytes_consumed;
+        let state = earlier.merge(later);
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(2));
+        assert_eq!(
+            state.remote_seqs_to_ack,
+            vec![Seq32::from_u32(0), Seq32::from_u32(1)]
+        );
+        assert_eq!(state.local_rwnd_size, 1024 - 2);
+        assert_eq!(state.in_order_pushes, 2);
+        assert_eq!(state.bytes_consumed, bytes_consumed);
+    }
+
+    #[test]
+    fn test_merge_states_wraparound() {
+        // `u32::MAX` is behind `1` across the wraparound
+        let earlier = merge_state(1, 3, 1, vec![0]);
+        let later = SetUploadState {
+            local_rwnd_size: 5,
+            ce_marked: true,
+            ..merge_state(u32::MAX, 9, u32::MAX, vec![u32::MAX - 1])
+        };
+        let state = earlier.merge(later);
+        assert_eq!(state.remote_nack, Seq32::from_u32(1));
+        assert_eq!(state.remote_rwnd_size, 3);
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(1));
+        assert_eq!(
+            state.remote_seqs_to_ack,
+            vec![Seq32::from_u32(0), Seq32::from_u32(u32::MAX - 1)]
+        );
+        assert_eq!(state.local_rwnd_size, 5);
+        assert!(state.ce_marked);
+        assert_eq!(state.bytes_consumed, 20);
+
+        // and the other way round
+        let state = merge_state(u32::MAX, 9, u32::MAX, vec![]).merge(merge_state(1, 3, 1, vec![]));
+        assert_eq!(state.remote_nack, Seq32::from_u32(1));
+        assert_eq!(state.remote_rwnd_size, 3);
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(1));
+    }
+
+    #[test]
+    fn test_merge_states_reset() {
+        let earlier = merge_state(0, 3, 7, vec![6]);
+        let later = SetUploadState {
+            reset: true,
+            ..merge_state(0, 3, 2, vec![1])
+        };
+        let state = earlier.merge(later);
+        assert!(state.reset);
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(2));
+        assert_eq!(state.remote_seqs_to_ack, vec![Seq32::from_u32(1)]);
+    }
+
+    #[test]
+    fn test_merge_states_sack_filled() {
+        let earlier = SetUploadState {
+            sack_ranges: vec![Seq32::from_u32(3)..Seq32::from_u32(5)],
+            ..merge_state(0, 3, 2, vec![3, 4])
+        };
+        // the gap at 2 is filled
+        let later = merge_state(0, 3, 5, vec![2]);
+        let state = earlier.merge(later);
+        assert_eq!(state.sack_ranges, vec![]);
+        assert_eq!(state.local_next_seq_to_receive, Seq32::from_u32(5));
+    }
 }
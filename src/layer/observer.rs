@@ -1,3 +1,48 @@
+use super::Error;
+use crate::{protocol::packet_hdr::PacketFormat, utils::Seq32};
+use std::time::Instant;
+
 pub trait IObserver {
     fn notify(&self);
 }
+
+pub trait IDeliveryObserver {
+    /// Called when the fragment of `seq` becomes deliverable
+    fn notify(&self, seq: Seq32, first_received: Instant);
+}
+
+/// Hooks into what the downloader makes of each packet written, e.g. for tracing
+///
+/// Every method does nothing by default.
+pub trait IDownloadObserver {
+    /// Called when the push of `seq` is taken into the rwnd
+    fn on_push_accepted(&self, _seq: Seq32) {}
+    /// Called when the push of `seq` is dropped, or evicted after being taken
+    fn on_push_dropped(&self, _seq: Seq32, _reason: DropReason) {}
+    /// Called when the peer acks `seq`
+    fn on_ack(&self, _seq: Seq32) {}
+    /// Called when a packet is rejected as a whole
+    fn on_decoding_error(&self, _error: &Error) {}
+    /// Called for every packet decoded, with the layout it was in
+    fn on_packet(&self, _format: PacketFormat) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Already delivered; still acked
+    TooLate,
+    /// Beyond the rwnd
+    TooEarly,
+    /// Already held out of order; the new copy replaces it and is still acked
+    Duplicate,
+    /// Longer than `DownloaderBuilder::max_frag_body_len`
+    Oversized,
+    /// Held out of order beyond `DownloaderBuilder::recv_buf_bytes`
+    OverBudget,
+    /// Evicted under `OnFull::DropFurthest`
+    Evicted,
+    /// Part of a message of more pushes than `DownloaderBuilder::recv_buf_len`; see `Downloader::emit_message`
+    MessageTooLong,
+    /// Part of a message held incomplete past `DownloaderBuilder::reassembly_timeout`
+    ReassemblyTimeout,
+}
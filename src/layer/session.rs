@@ -0,0 +1,379 @@
+use super::{BuildError, Builder, Downloader, Uploader};
+use crate::{
+    protocol::packet::Packet,
+    utils::{
+        buf::{BufSlice, BufWtr, OwnedBufWtr},
+        Seq32,
+    },
+};
+use std::time::{Duration, Instant};
+
+/// Unit of `elapsed`: since the start of the session
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    /// Bytes written to the uploader of `peer`
+    Write {
+        peer: usize,
+        elapsed: Duration,
+        data: Vec<u8>,
+    },
+    /// A datagram written to the downloader of `peer`, whose changes are then set on the uploader of `peer`
+    Input {
+        peer: usize,
+        elapsed: Duration,
+        datagram: Vec<u8>,
+    },
+    /// The datagrams emitted by the uploader of `peer`
+    Emit {
+        peer: usize,
+        elapsed: Duration,
+        datagrams: Vec<Vec<u8>>,
+    },
+    /// A slice emitted by the downloader of `peer`
+    Recv {
+        peer: usize,
+        elapsed: Duration,
+        data: Vec<u8>,
+    },
+    /// A message emitted by the downloader of `peer`; see `Downloader::emit_message`
+    RecvMessage {
+        peer: usize,
+        elapsed: Duration,
+        frags: Vec<Vec<u8>>,
+    },
+    /// The acks flushed by the downloader of `peer` on a timer, then queued on the uploader of `peer`
+    Timer {
+        peer: usize,
+        elapsed: Duration,
+        acks: Vec<Seq32>,
+    },
+}
+
+/// Log of what happened to the two peers of a session, for `replay_session`
+///
+/// `peer` is either `0` or `1`.
+pub struct SessionRecorder {
+    start: Instant,
+    events: Vec<SessionEvent>,
+}
+
+impl SessionRecorder {
+    #[must_use]
+    pub fn new(start: Instant) -> Self {
+        SessionRecorder {
+            start,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record_write(&mut self, peer: usize, data: &[u8], now: &Instant) {
+        assert!(peer < 2);
+        self.events.push(SessionEvent::Write {
+            peer,
+            elapsed: now.duration_since(self.start),
+            data: data.to_vec(),
+        });
+    }
+
+    pub fn record_input(&mut self, peer: usize, datagram: &[u8], now: &Instant) {
+        assert!(peer < 2);
+        self.events.push(SessionEvent::Input {
+            peer,
+            elapsed: now.duration_since(self.start),
+            datagram: datagram.to_vec(),
+        });
+    }
+
+    pub fn record_emit(&mut self, peer: usize, packets: &[Packet], now: &Instant) {
+        assert!(peer < 2);
+        self.events.push(SessionEvent::Emit {
+            peer,
+            elapsed: now.duration_since(self.start),
+            datagrams: packets.iter().map(encode).collect(),
+        });
+    }
+
+    pub fn record_recv(&mut self, peer: usize, data: &[u8], now: &Instant) {
+        assert!(peer < 2);
+        self.events.push(SessionEvent::Recv {
+            peer,
+            elapsed: now.duration_since(self.start),
+            data: data.to_vec(),
+        });
+    }
+
+    pub fn record_recv_message(&mut self, peer: usize, frags: &[BufSlice], now: &Instant) {
+        assert!(peer < 2);
+        self.events.push(SessionEvent::RecvMessage {
+            peer,
+            elapsed: now.duration_since(self.start),
+            frags: frags.iter().map(|x| x.data().to_vec()).collect(),
+        });
+    }
+
+    /// Record a `Downloader::on_timer` returning `acks`, even if none
+    pub fn record_timer(&mut self, peer: usize, acks: &[Seq32], now: &Instant) {
+        assert!(peer < 2);
+        self.events.push(SessionEvent::Timer {
+            peer,
+            elapsed: now.duration_since(self.start),
+            acks: acks.to_vec(),
+        });
+    }
+
+    #[must_use]
+    pub fn events(&self) -> &[SessionEvent] {
+        &self.events
+    }
+
+    #[must_use]
+    pub fn into_events(self) -> Vec<SessionEvent> {
+        self.events
+    }
+}
+
+/// Drive two fresh peers from `builder` through `events`, checking every emit and timer against the recorded one
+pub fn replay_session(
+    builder: impl Fn() -> Builder,
+    events: &[SessionEvent],
+) -> Result<(), ReplayError> {
+    let start = Instant::now();
+    let mut peers: Vec<(Uploader, Downloader)> = Vec::new();
+    for _ in 0..2 {
+        peers.push(builder().build().map_err(ReplayError::Build)?);
+    }
+    for (index, event) in events.iter().enumerate() {
+        let peer = match event {
+            SessionEvent::Write { peer, .. }
+            | SessionEvent::Input { peer, .. }
+            | SessionEvent::Emit { peer, .. }
+            | SessionEvent::Recv { peer, .. }
+            | SessionEvent::RecvMessage { peer, .. }
+            | SessionEvent::Timer { peer, .. } => *peer,
+        };
+        let (uploader, downloader) = peers
+            .get_mut(peer)
+            .ok_or(ReplayError::NoSuchPeer { index })?;
+        match event {
+            SessionEvent::Write { data, .. } => {
+                uploader
+                    .write(BufSlice::from_bytes(data.clone()))
+                    .map_err(|_| ReplayError::Rejected { index })?;
+            }
+            SessionEvent::Input {
+                elapsed, datagram, ..
+            } => {
+                let now = start + *elapsed;
+                let changes = downloader
+                    .write(BufSlice::from_bytes(datagram.clone()), &now)
+                    .map_err(|_| ReplayError::Rejected { index })?;
+                uploader
+                    .set_state(changes, &now)
+                    .map_err(|_| ReplayError::Rejected { index })?;
+            }
+            SessionEvent::Emit {
+                elapsed, datagrams, ..
+            } => {
+                let now = start + *elapsed;
+                let replayed: Vec<Vec<u8>> = uploader.emit(&now).iter().map(encode).collect();
+                if &replayed != datagrams {
+                    return Err(ReplayError::Diverged { index });
+                }
+            }
+            SessionEvent::Recv { data, .. } => {
+                let replayed = downloader.emit();
+                if replayed.as_ref().map(|x| x.data()) != Some(&data[..]) {
+                    return Err(ReplayError::Diverged { index });
+                }
+            }
+            SessionEvent::RecvMessage { frags, .. } => {
+                let replayed = downloader
+                    .emit_message()
+                    .map_err(|_| ReplayError::Rejected { index })?
+                    .map(|x| x.iter().map(|x| x.data().to_vec()).collect::<Vec<_>>());
+                if replayed.as_ref() != Some(frags) {
+                    return Err(ReplayError::Diverged { index });
+                }
+            }
+            SessionEvent::Timer { elapsed, acks, .. } => {
+                let now = start + *elapsed;
+                let replayed = downloader.on_timer(&now);
+                if &replayed != acks {
+                    return Err(ReplayError::Diverged { index });
+                }
+                for seq in replayed {
+                    uploader.queue_ack(seq);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Build(BuildError),
+    /// The event at `index` failed during the replay
+    Rejected {
+        index: usize,
+    },
+    /// The emit or timer at `index` differs from the recorded one
+    Diverged {
+        index: usize,
+    },
+    /// The event at `index` names a peer other than `0` or `1`
+    NoSuchPeer {
+        index: usize,
+    },
+}
+
+fn encode(packet: &Packet) -> Vec<u8> {
+    let mut wtr = OwnedBufWtr::new(u16::MAX as usize, 0);
+    packet.append_to(&mut wtr).unwrap();
+    wtr.data().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::DelayedAckPolicy;
+
+    #[test]
+    fn test_replay() {
+        let builder = || Builder {
+            local_recv_buf_len: 4,
+            ..Builder::default()
+        };
+        let mut now = Instant::now();
+        let mut recorder = SessionRecorder::new(now);
+        let mut peers = [builder().build().unwrap(), builder().build().unwrap()];
+
+        let data = vec![1, 2, 3];
+        peers[1]
+            .0
+            .write(BufSlice::from_bytes(data.clone()))
+            .map_err(|_| ())
+            .unwrap();
+        recorder.record_write(1, &data, &now);
+        for round in 0..4 {
+            for peer in 0..2 {
+                let packets = peers[peer].0.emit(&now);
+                recorder.record_emit(peer, &packets, &now);
+                // lose the first push from 1
+                if round == 0 && peer == 1 {
+                    continue;
+                }
+                let (uploader, downloader) = &mut peers[1 - peer];
+                for datagram in packets.iter().map(encode) {
+                    recorder.record_input(1 - peer, &datagram, &now);
+                    let changes = downloader
+                        .write(BufSlice::from_bytes(datagram), &now)
+                        .unwrap();
+                    uploader.set_state(changes, &now).unwrap();
+                }
+            }
+            now += peers[1].0.rto();
+        }
+        assert_eq!(peers[0].1.emit().unwrap().data(), data);
+        assert!(peers[1].0.stat().retransmissions > 0);
+
+        let events = recorder.into_events();
+        replay_session(builder, &events).unwrap();
+
+        // different bytes written emit different pushes
+        let mut tampered = events.clone();
+        if let Some(SessionEvent::Write { data, .. }) = tampered.first_mut() {
+            data.push(4);
+        }
+        assert!(matches!(
+            replay_session(builder, &tampered),
+            Err(ReplayError::Diverged { .. })
+        ));
+    }
+
+    #[test]
+    fn test_replay_recv_and_timer() {
+        let builder = || Builder {
+            local_recv_buf_len: 4,
+            delayed_ack: Some(DelayedAckPolicy {
+                max_delay: Duration::from_millis(40),
+                max_unacked: 4,
+            }),
+            ..Builder::default()
+        };
+        let mut now = Instant::now();
+        let mut recorder = SessionRecorder::new(now);
+        let mut peers = [builder().build().unwrap(), builder().build().unwrap()];
+
+        let data = vec![1, 2, 3];
+        peers[1]
+            .0
+            .write(BufSlice::from_bytes(data.clone()))
+            .map_err(|_| ())
+            .unwrap();
+        recorder.record_write(1, &data, &now);
+        let packets = peers[1].0.emit(&now);
+        recorder.record_emit(1, &packets, &now);
+        let (uploader, downloader) = &mut peers[0];
+        for datagram in packets.iter().map(encode) {
+            recorder.record_input(0, &datagram, &now);
+            let changes = downloader
+                .write(BufSlice::from_bytes(datagram), &now)
+                .unwrap();
+            uploader.set_state(changes, &now).unwrap();
+        }
+        let received = downloader.emit().unwrap();
+        recorder.record_recv(0, received.data(), &now);
+        // the ack is held back until the timer fires
+        for _ in 0..2 {
+            let acks = downloader.on_timer(&now);
+            recorder.record_timer(0, &acks, &now);
+            for &seq in &acks {
+                uploader.queue_ack(seq);
+            }
+            now += Duration::from_millis(40);
+        }
+        let packets = uploader.emit(&now);
+        assert!(!packets.is_empty());
+        recorder.record_emit(0, &packets, &now);
+
+        let events = recorder.into_events();
+        assert!(matches!(
+            &events[events.len() - 2],
+            SessionEvent::Timer { acks, .. } if acks.len() == 1
+        ));
+        replay_session(builder, &events).unwrap();
+
+        // a different slice received
+        let mut tampered = events.clone();
+        for event in &mut tampered {
+            if let SessionEvent::Recv { data, .. } = event {
+                data.push(4);
+            }
+        }
+        assert!(matches!(
+            replay_session(builder, &tampered),
+            Err(ReplayError::Diverged { .. })
+        ));
+
+        // without the timer, the ack is never emitted
+        let skipped: Vec<_> = events
+            .iter()
+            .filter(|x| !matches!(x, SessionEvent::Timer { .. }))
+            .cloned()
+            .collect();
+        assert!(matches!(
+            replay_session(builder, &skipped),
+            Err(ReplayError::Diverged { .. })
+        ));
+
+        let mut tampered = events.clone();
+        if let Some(SessionEvent::Write { peer, .. }) = tampered.first_mut() {
+            *peer = 2;
+        }
+        assert!(matches!(
+            replay_session(builder, &tampered),
+            Err(ReplayError::NoSuchPeer { index: 0 })
+        ));
+    }
+}
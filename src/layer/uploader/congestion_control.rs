@@ -0,0 +1,186 @@
+use crate::utils::CHECK_REP;
+
+/// Unit of windows: sequence
+pub trait ICongestionControl {
+    /// `acked` sequences have left the swnd
+    fn on_ack(&mut self, acked: usize);
+    /// A fast retransmission or an ECN echo, at most once per window of pushes
+    fn on_loss(&mut self);
+    /// An RTO, at most once per window of pushes unless an `on_loss` opened that window
+    fn on_timeout(&mut self) {
+        self.on_loss();
+    }
+    fn cwnd(&self) -> usize;
+    /// The slow start threshold, for algorithms with one
+    fn ssthresh(&self) -> Option<usize> {
+        None
+    }
+}
+
+const MIN_SSTHRESH: usize = 2;
+
+/// Slow start up to `ssthresh`, then one more sequence per cwnd acked; halve on loss and restart from one sequence on timeout
+pub struct NewReno {
+    cwnd: usize,
+    ssthresh: usize,
+    acked_in_avoidance: usize,
+}
+
+impl NewReno {
+    #[inline]
+    fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
+        assert!(self.cwnd > 0);
+        assert!(self.acked_in_avoidance < self.cwnd);
+    }
+
+    /// Slow start until the first loss
+    #[must_use]
+    pub fn new(initial_cwnd: usize) -> Self {
+        Self::with_initial_ssthresh(initial_cwnd, usize::MAX)
+    }
+
+    /// Slow start up to `initial_ssthresh` at most, for a path whose capacity is known
+    #[must_use]
+    pub fn with_initial_ssthresh(initial_cwnd: usize, initial_ssthresh: usize) -> Self {
+        let this = NewReno {
+            cwnd: usize::max(initial_cwnd, 1),
+            ssthresh: initial_ssthresh,
+            acked_in_avoidance: 0,
+        };
+        this.check_rep();
+        this
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn is_in_slow_start(&self) -> bool {
+        self.cwnd < self.ssthresh
+    }
+}
+
+impl ICongestionControl for NewReno {
+    fn on_ack(&mut self, mut acked: usize) {
+        if self.is_in_slow_start() {
+            let grown = usize::min(acked, self.ssthresh - self.cwnd);
+            self.cwnd += grown;
+            acked -= grown;
+        }
+        // congestion avoidance
+        self.acked_in_avoidance += acked;
+        while self.acked_in_avoidance >= self.cwnd {
+            self.acked_in_avoidance -= self.cwnd;
+            self.cwnd += 1;
+        }
+        self.check_rep();
+    }
+
+    fn on_loss(&mut self) {
+        self.ssthresh = usize::max(self.cwnd / 2, MIN_SSTHRESH);
+        self.cwnd = self.ssthresh;
+        self.acked_in_avoidance = 0;
+        self.check_rep();
+    }
+
+    fn on_timeout(&mut self) {
+        self.ssthresh = usize::max(self.cwnd / 2, MIN_SSTHRESH);
+        self.cwnd = 1;
+        self.acked_in_avoidance = 0;
+        self.check_rep();
+    }
+
+    #[inline]
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    #[inline]
+    fn ssthresh(&self) -> Option<usize> {
+        Some(self.ssthresh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slow_start_to_avoidance() {
+        let mut cc = NewReno::new(2);
+        assert!(cc.is_in_slow_start());
+        cc.on_ack(2);
+        assert_eq!(cc.cwnd(), 4);
+        cc.on_ack(4);
+        assert_eq!(cc.cwnd(), 8);
+
+        cc.on_loss();
+        assert_eq!(cc.ssthresh(), 4);
+        assert_eq!(cc.cwnd(), 4);
+        assert!(!cc.is_in_slow_start());
+
+        // additive increase: one sequence per cwnd acked
+        cc.on_ack(3);
+        assert_eq!(cc.cwnd(), 4);
+        cc.on_ack(1);
+        assert_eq!(cc.cwnd(), 5);
+        cc.on_ack(5);
+        assert_eq!(cc.cwnd(), 6);
+    }
+
+    #[test]
+    fn test_initial_ssthresh() {
+        let mut cc = NewReno::with_initial_ssthresh(2, 6);
+        assert_eq!(cc.ssthresh(), 6);
+        cc.on_ack(2);
+        assert_eq!(cc.cwnd(), 4);
+        assert!(cc.is_in_slow_start());
+        // slow start stops at the threshold without any loss
+        cc.on_ack(4);
+        assert_eq!(cc.cwnd(), 6);
+        assert!(!cc.is_in_slow_start());
+        assert_eq!(cc.ssthresh(), 6);
+        cc.on_ack(4);
+        assert_eq!(cc.cwnd(), 7);
+
+        assert_eq!(NewReno::new(2).ssthresh(), usize::MAX);
+    }
+
+    #[test]
+    fn test_multiplicative_decrease() {
+        let mut cc = NewReno::new(8);
+        cc.on_loss();
+        assert_eq!((cc.cwnd(), cc.ssthresh()), (4, 4));
+        cc.on_loss();
+        assert_eq!((cc.cwnd(), cc.ssthresh()), (2, 2));
+        cc.on_loss();
+        assert_eq!((cc.cwnd(), cc.ssthresh()), (MIN_SSTHRESH, MIN_SSTHRESH));
+
+        // partial progress toward the next increase is lost too
+        cc.on_ack(1);
+        cc.on_loss();
+        cc.on_ack(1);
+        assert_eq!(cc.cwnd(), 2);
+        cc.on_ack(1);
+        assert_eq!(cc.cwnd(), 3);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut cc = NewReno::new(8);
+        cc.on_timeout();
+        assert_eq!((cc.cwnd(), cc.ssthresh()), (1, 4));
+        assert!(cc.is_in_slow_start());
+        cc.on_ack(1);
+        cc.on_ack(2);
+        assert_eq!(cc.cwnd(), 4);
+        assert!(!cc.is_in_slow_start());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::protocol::frag::Frag;
+use crate::utils::CHECK_REP;
 use std::mem;
 
 pub struct FragBundler {
@@ -11,6 +12,9 @@ pub struct FragBundler {
 
 impl FragBundler {
     pub fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
         for bundle in &self.bundles {
             let mut len = 0;
             for frag in bundle {
@@ -89,6 +93,8 @@ mod tests {
         let frag1 = FragBuilder {
             seq: Seq32::from_u32(1),
             cmd: FragCommand::Ack,
+            checksum: false,
+            more: false,
         }
         .build()
         .unwrap();
@@ -97,6 +103,8 @@ mod tests {
             cmd: FragCommand::Push {
                 body: Body::Slice(BufSlice::from_bytes(vec![9])),
             },
+            checksum: false,
+            more: false,
         }
         .build()
         .unwrap();
@@ -105,6 +113,8 @@ mod tests {
             cmd: FragCommand::Push {
                 body: Body::Slice(BufSlice::from_bytes(vec![9])),
             },
+            checksum: false,
+            more: false,
         }
         .build()
         .unwrap();
@@ -1,6 +1,8 @@
+mod congestion_control;
 mod frag_bundler;
 mod sending_push;
 mod uploader;
 
+pub use congestion_control::*;
 use sending_push::*;
 pub use uploader::*;
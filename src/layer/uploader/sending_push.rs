@@ -9,7 +9,7 @@ use crate::utils::buf::BufPasta;
 pub struct SendingPush {
     body: Arc<BufPasta>,
     last_sent: time::Instant,
-    is_retransmitted: bool,
+    retransmissions: usize,
 }
 
 impl SendingPush {
@@ -18,7 +18,7 @@ impl SendingPush {
         SendingPush {
             body,
             last_sent: now,
-            is_retransmitted: false,
+            retransmissions: 0,
         }
     }
 
@@ -29,7 +29,7 @@ impl SendingPush {
 
     pub fn to_retransmit(&mut self, now: Instant) {
         self.last_sent = now;
-        self.is_retransmitted = true;
+        self.retransmissions += 1;
     }
 
     // #[must_use]
@@ -44,7 +44,12 @@ impl SendingPush {
 
     #[must_use]
     pub fn is_retransmitted(&self) -> bool {
-        self.is_retransmitted
+        self.retransmissions > 0
+    }
+
+    #[must_use]
+    pub fn retransmissions(&self) -> usize {
+        self.retransmissions
     }
 
     #[must_use]
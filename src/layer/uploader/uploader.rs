@@ -1,53 +1,89 @@
 use super::{
     super::{IObserver, SetUploadState},
     frag_bundler::FragBundler,
-    SendingPush,
+    ICongestionControl, SendingPush,
 };
 use crate::{
     protocol::{
-        frag::{Body, Frag, FragBuilder, FragCommand, ACK_HDR_LEN, PUSH_HDR_LEN},
+        frag::{Body, Frag, FragBuilder, FragCommand, ACK_HDR_LEN, CHECKSUM_LEN, PUSH_HDR_LEN},
         packet::{Packet, PacketBuilder},
-        packet_hdr::{PacketHeaderBuilder, PACKET_HDR_LEN},
+        packet_hdr::{
+            packet_hdr_len, scale_rwnd, PacketHeader, PacketHeaderBuilder, Timestamps,
+            CURRENT_VERSION, MAX_SCALED_RWND,
+        },
     },
     utils::{
+        self,
         buf::{self, BufPasta, BufSlicerQue},
-        FastRetransmissionWnd, Seq32, Swnd,
+        FastRetransmissionWnd, RttEstimator, RttEstimatorBuilder, Seq32, Swnd, CHECK_REP,
     },
 };
 use keyed_priority_queue::KeyedPriorityQueue;
 use std::{
     cmp,
     collections::VecDeque,
+    ops::Range,
     sync::{Arc, Weak},
     time::{self, Duration, Instant},
 };
 
-const ALPHA: f64 = 1.0 / 8.0;
 const MAX_RTO_MS: u64 = 60_000;
 const DEFAULT_RTO_MS: u64 = 3_000; // make it bigger to avoid RTO floods
 const MIN_RTO_MS: u64 = 100;
-static MAX_RTO: time::Duration = Duration::from_millis(MAX_RTO_MS);
+pub static MAX_RTO: time::Duration = Duration::from_millis(MAX_RTO_MS);
 static DEFAULT_RTO: time::Duration = Duration::from_millis(DEFAULT_RTO_MS);
-static MIN_RTO: time::Duration = Duration::from_millis(MIN_RTO_MS);
+pub static MIN_RTO: time::Duration = Duration::from_millis(MIN_RTO_MS);
+/// Under pacing, the most new pushes released at once after the emits fell behind
+const PACING_BURST: u32 = 4;
 
 pub struct Uploader {
     // modified by `append_frags_to`
     to_send_queue: buf::BufSlicerQue,
+    /// The bytes in `to_send_queue` split into runs no push may straddle
+    to_send_runs: VecDeque<SendRun>,
     swnd: Swnd<Seq32, SendingPush>,
     to_ack_queue: VecDeque<Seq32>,
+    to_ping_queue: VecDeque<Seq32>,
+    to_pong_queue: VecDeque<Seq32>,
+    /// A local `Reset` yet to be acknowledged
+    pending_reset: Option<PendingReset>,
+    /// The epoch of a remote `Reset` to acknowledge
+    to_reset_ack: Option<Seq32>,
     last_sent_heap: KeyedPriorityQueue<Seq32, cmp::Reverse<Instant>>,
 
+    inflight_bytes: usize,
+    /// Under pacing, when the next new push may go out
+    next_paced_send: Option<Instant>,
+
     // modified by setters
+    corked: bool,
+    nagle: bool,
+    pacing: bool,
+    to_advertise_rwnd: bool,
+    to_echo_ce: bool,
     local_rwnd_size: usize,
     local_next_seq_to_receive: Seq32,
+    /// The latest `ts_val` from the peer, to be echoed
+    remote_ts_val: Option<u32>,
+    /// Where the local `ts_val`s count from
+    timestamps_epoch: Option<Instant>,
     fast_retransmission_wnd: FastRetransmissionWnd<Seq32>,
+    congestion_control: Option<Box<dyn ICongestionControl + Send + Sync + 'static>>,
+    /// The window of pushes the last congestion response covers
+    recovery: Option<Recovery>,
 
     // stat
+    rtt: RttEstimator,
     stat: LocalStat,
 
     // const
-    ratio_rto_to_one_rtt: f64,
     mtu: usize,
+    max_inflight_bytes: usize,
+    frag_checksum: bool,
+    packet_checksum: bool,
+    timestamps: bool,
+    delimited: bool,
+    strict_framing: bool,
 
     // observer
     on_send_available: Option<Weak<dyn IObserver + Send + Sync + 'static>>,
@@ -56,39 +92,90 @@ pub struct Uploader {
 pub struct UploaderBuilder {
     pub local_recv_buf_len: usize,
     pub nack_duplicate_threshold_to_activate_fast_retransmit: usize,
-    pub ratio_rto_to_one_rtt: f64,
+    /// Bounds of the RTO estimated from the RTT samples
+    pub min_rto: Duration,
+    pub max_rto: Duration,
     pub mtu: usize,
     pub to_send_queue_len_cap: usize,
     pub swnd_size_cap: usize,
+    pub max_inflight_bytes: usize,
+    /// Append a CRC-32 to each push so a corrupted one can be dropped alone
+    pub frag_checksum: bool,
+    /// Append a CRC-32 of the whole packet to each packet header
+    pub packet_checksum: bool,
+    /// Stamp `Timestamps` into each packet header and sample the RTT from the `ts_ecr`s the peer echoes
+    ///
+    /// Unlike the samples taken from acked pushes, these are not lost to retransmissions.
+    pub timestamps: bool,
+    /// Reject a state whose `remote_nack` is beyond any sequence ever sent
+    pub strict_framing: bool,
+    /// Prefix each packet header with its length so that packets can be concatenated, as over a stream transport
+    ///
+    /// See `Downloader::write_stream`. The length takes from the space of the pushes.
+    pub delimited: bool,
 }
 
 impl UploaderBuilder {
     #[must_use]
     pub fn build(self) -> Result<Uploader, BuildError> {
-        if !(PACKET_HDR_LEN + ACK_HDR_LEN <= self.mtu)
-            || !(PACKET_HDR_LEN + PUSH_HDR_LEN + 1 <= self.mtu)
+        let packet_hdr_len = packet_hdr_len(self.packet_checksum, self.timestamps, self.delimited);
+        if !(packet_hdr_len + ACK_HDR_LEN <= self.mtu)
+            || !(packet_hdr_len + push_hdr_len(self.frag_checksum) + 1 <= self.mtu)
         {
             return Err(BuildError::MtuTooSmall);
         }
+        // the length field could not tell a longer packet
+        if self.delimited && self.mtu > u16::MAX as usize {
+            return Err(BuildError::MtuTooLarge);
+        }
         let this = Uploader {
             to_send_queue: BufSlicerQue::new(self.to_send_queue_len_cap),
+            to_send_runs: VecDeque::new(),
             swnd: Swnd::new(self.swnd_size_cap),
             to_ack_queue: VecDeque::new(),
+            to_ping_queue: VecDeque::new(),
+            to_pong_queue: VecDeque::new(),
+            pending_reset: None,
+            to_reset_ack: None,
+            inflight_bytes: 0,
+            next_paced_send: None,
+            corked: false,
+            nagle: false,
+            pacing: false,
+            to_advertise_rwnd: false,
+            to_echo_ce: false,
             local_rwnd_size: self.local_recv_buf_len,
             local_next_seq_to_receive: Seq32::from_u32(0),
+            remote_ts_val: None,
+            timestamps_epoch: None,
+            rtt: RttEstimatorBuilder {
+                initial_rto: DEFAULT_RTO,
+                min_rto: self.min_rto,
+                max_rto: self.max_rto,
+            }
+            .build()
+            .map_err(|_| BuildError::MinRtoAboveMax)?,
             stat: LocalStat {
-                srtt: None,
                 retransmissions: 0,
                 rto_hits: 0,
                 fast_retransmissions: 0,
                 pushes: 0,
                 acks: 0,
+                bytes_acked: 0,
+                impossible_nacks: 0,
             },
             fast_retransmission_wnd: FastRetransmissionWnd::new(
                 self.nack_duplicate_threshold_to_activate_fast_retransmit,
             ),
-            ratio_rto_to_one_rtt: self.ratio_rto_to_one_rtt,
             mtu: self.mtu,
+            max_inflight_bytes: self.max_inflight_bytes,
+            frag_checksum: self.frag_checksum,
+            packet_checksum: self.packet_checksum,
+            timestamps: self.timestamps,
+            delimited: self.delimited,
+            strict_framing: self.strict_framing,
+            congestion_control: None,
+            recovery: None,
             on_send_available: None,
             last_sent_heap: KeyedPriorityQueue::new(),
         };
@@ -101,18 +188,27 @@ impl UploaderBuilder {
         let builder = Self {
             local_recv_buf_len: u16::MAX as usize,
             nack_duplicate_threshold_to_activate_fast_retransmit: 0,
-            ratio_rto_to_one_rtt: 1.5,
+            min_rto: MIN_RTO,
+            max_rto: MAX_RTO,
             mtu: 1300,
             to_send_queue_len_cap: 1024 * 64,
             swnd_size_cap: u16::MAX as usize,
+            max_inflight_bytes: usize::MAX,
+            frag_checksum: false,
+            packet_checksum: false,
+            timestamps: false,
+            delimited: false,
+            strict_framing: false,
         };
         builder
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum SetStateError {
     InvalidState,
+    /// Only under `strict_framing`
+    ImpossibleNack,
 }
 
 #[derive(Debug)]
@@ -124,6 +220,9 @@ pub enum OutputError {
 #[derive(Debug)]
 pub enum BuildError {
     MtuTooSmall,
+    /// Only under `delimited`
+    MtuTooLarge,
+    MinRtoAboveMax,
 }
 
 pub struct SendError<T>(pub T);
@@ -131,18 +230,31 @@ pub struct SendError<T>(pub T);
 impl Uploader {
     #[inline]
     fn check_rep(&self) {
-        assert!(self.local_rwnd_size <= u16::MAX as usize);
+        if !CHECK_REP {
+            return;
+        }
+        assert!(self.local_rwnd_size <= MAX_SCALED_RWND);
+        assert!(self.inflight_bytes <= self.max_inflight_bytes);
+        assert_eq!(
+            self.to_send_runs.iter().map(|run| run.len).sum::<usize>(),
+            self.to_send_queue.byte_len()
+        );
+        for run in &self.to_send_runs {
+            assert!(run.len > 0);
+        }
     }
 
     #[must_use]
     pub fn stat(&self) -> Stat {
         Stat {
-            srtt: self.stat.srtt,
+            srtt: self.rtt.srtt(),
             retransmissions: self.stat.retransmissions,
             rto_hits: self.stat.rto_hits,
             fast_retransmissions: self.stat.fast_retransmissions,
             pushes: self.stat.pushes,
             acks: self.stat.acks,
+            bytes_acked: self.stat.bytes_acked,
+            impossible_nacks: self.stat.impossible_nacks,
             next_seq_to_send: self.swnd.end(),
         }
     }
@@ -155,13 +267,144 @@ impl Uploader {
     }
 
     pub fn write(&mut self, slice: buf::BufSlice) -> Result<(), SendError<buf::BufSlice>> {
+        let len = slice.len();
+        let result = match self.to_send_queue.push_back(slice) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(SendError(e.0)),
+        };
+        if result.is_ok() && len > 0 {
+            match self.to_send_runs.back_mut() {
+                Some(run) if !run.message => run.len += len,
+                _ => self.to_send_runs.push_back(SendRun {
+                    len,
+                    message: false,
+                }),
+            }
+        }
+        self.check_rep();
+        result
+    }
+
+    /// Write `slice` as one application message for `Downloader::emit_message`
+    ///
+    /// The message is split into pushes of its own, each but the last flagged `more`. An empty message is not sent.
+    ///
+    /// The peer drops a message split into more pushes than its `DownloaderBuilder::recv_buf_len`, for it could never be reassembled.
+    pub fn write_message(&mut self, slice: buf::BufSlice) -> Result<(), SendError<buf::BufSlice>> {
+        let len = slice.len();
         let result = match self.to_send_queue.push_back(slice) {
             Ok(_) => Ok(()),
             Err(e) => Err(SendError(e.0)),
         };
+        if result.is_ok() && len > 0 {
+            self.to_send_runs.push_back(SendRun { len, message: true });
+        }
+        self.check_rep();
         result
     }
 
+    /// Hold back new pushes until `uncork`
+    ///
+    /// Acks and retransmissions are still emitted.
+    pub fn cork(&mut self) {
+        self.corked = true;
+        self.check_rep();
+    }
+
+    /// Let `emit` pack all the data written while corked
+    pub fn uncork(&mut self) {
+        self.corked = false;
+        self.check_rep();
+    }
+
+    /// Hold back a push shorter than a full packet while earlier pushes are unacknowledged
+    ///
+    /// It is off by default, as if `TCP_NODELAY` were set. The held bytes coalesce with later writes.
+    pub fn set_nagle(&mut self, enabled: bool) {
+        self.nagle = enabled;
+        self.check_rep();
+    }
+
+    /// Spread the new pushes over the smoothed RTT instead of bursting the whole window at once
+    ///
+    /// It is off by default. Acks and retransmissions are never held back. See `next_send_time`.
+    ///
+    /// An `emit` releases a push per `pacing_interval` elapsed since the last one was due, up to a burst of a few pushes, so a driver waking late does not fall behind.
+    pub fn set_pacing(&mut self, enabled: bool) {
+        self.pacing = enabled;
+        if !enabled {
+            self.next_paced_send = None;
+        }
+        self.check_rep();
+    }
+
+    /// The time between two new pushes under pacing: the smoothed RTT over the window
+    ///
+    /// The window is the cwnd under congestion control, or else the remote rwnd. `None` with pacing off or before the first RTT sample.
+    #[must_use]
+    pub fn pacing_interval(&self) -> Option<Duration> {
+        if !self.pacing {
+            return None;
+        }
+        let srtt = self.rtt.srtt()?;
+        let wnd = self.cwnd().unwrap_or_else(|| self.swnd.remote_rwnd_size());
+        let wnd = u32::try_from(usize::max(wnd, 1)).unwrap_or(u32::MAX);
+        Some(srtt / wnd)
+    }
+
+    /// When `emit` may next output a new push, for the driver to call it no earlier
+    ///
+    /// It is `now` if a new push may go out already, as it always may without pacing.
+    #[must_use]
+    pub fn next_send_time(&self, now: &Instant) -> Instant {
+        match self.next_paced_send {
+            Some(next) if self.pacing => Instant::max(next, *now),
+            _ => *now,
+        }
+    }
+
+    /// Schedule an ack for `seq` into the next emitted packets
+    ///
+    /// This bypasses the acks scheduled by `set_state`.
+    pub fn queue_ack(&mut self, seq: Seq32) {
+        self.add_remote_seq_to_ack(seq);
+    }
+
+    /// Schedule a `Ping` into the next emitted packets
+    ///
+    /// The peer answers with a `Pong` of the same `id`, surfaced in `SetUploadState::pongs`.
+    pub fn ping(&mut self, id: Seq32) {
+        self.to_ping_queue.push_back(id);
+        self.check_rep();
+    }
+
+    /// Schedule a `Reset` into the next emitted packets, telling the peer to expect the oldest unacked push next
+    ///
+    /// Call it after a restart. The `Reset` is retransmitted every RTO until the peer acknowledges it.
+    ///
+    /// The peer takes a `Reset` only if its `epoch` is after that of the last one taken, so a replayed or delayed one cannot discard what arrived after it. The `epoch` should increase with every reset, e.g. a restart counter kept across restarts.
+    pub fn reset(&mut self, epoch: Seq32) {
+        self.pending_reset = Some(PendingReset {
+            epoch,
+            last_sent: None,
+        });
+        self.check_rep();
+    }
+
+    /// A `Reset` is yet to be acknowledged by the peer
+    #[must_use]
+    pub fn is_resetting(&self) -> bool {
+        self.pending_reset.is_some()
+    }
+
+    /// Schedule acks for every sequence in `range` into the next emitted packets
+    pub fn queue_sack(&mut self, range: Range<Seq32>) {
+        for seq in utils::range(range.start, range.end) {
+            self.add_remote_seq_to_ack(seq);
+        }
+    }
+
+    /// The scheduled acks ride ahead of the pushes in the same packets
     #[must_use]
     pub fn emit(&mut self, now: &Instant) -> Vec<Packet> {
         let is_then_full = self.to_send_queue.is_full();
@@ -188,55 +431,61 @@ impl Uploader {
         packet_space: usize,
         now: &Instant,
     ) -> Result<Vec<Packet>, OutputError> {
-        if !(PACKET_HDR_LEN + ACK_HDR_LEN <= packet_space) {
+        let packet_hdr_len = packet_hdr_len(self.packet_checksum, self.timestamps, self.delimited);
+        if !(packet_hdr_len + ACK_HDR_LEN <= packet_space) {
             self.check_rep();
             return Err(OutputError::BufferTooSmall);
         }
-        if !(PACKET_HDR_LEN + PUSH_HDR_LEN + 1 <= packet_space) {
+        if !(packet_hdr_len + push_hdr_len(self.frag_checksum) + 1 <= packet_space) {
             self.check_rep();
             return Err(OutputError::BufferTooSmall);
         }
 
-        let bundles = self.emit_frags(packet_space - PACKET_HDR_LEN, now);
+        let bundles = self.emit_frags(packet_space - packet_hdr_len, now);
         let mut packets = Vec::new();
 
         for frags in bundles {
             // packet header
-            let hdr = PacketHeaderBuilder {
-                rwnd: self.local_rwnd_size as u16,
-                nack: self.local_next_seq_to_receive,
+            let hdr = self.packet_hdr(now);
+            let packet = PacketBuilder { hdr, frags }.build().unwrap();
+            packets.push(packet);
+        }
+        // a bare packet header still carries the local rwnd and the echo
+        if (self.to_advertise_rwnd || self.to_echo_ce) && packets.is_empty() {
+            let hdr = self.packet_hdr(now);
+            let packet = PacketBuilder {
+                hdr,
+                frags: Vec::new(),
             }
             .build()
             .unwrap();
-            let packet = PacketBuilder { hdr, frags }.build().unwrap();
             packets.push(packet);
         }
+        self.to_advertise_rwnd = false;
+        if !packets.is_empty() {
+            self.to_echo_ce = false;
+        }
         self.check_rep();
         Ok(packets)
     }
 
-    #[inline]
-    #[must_use]
-    fn emit_frags(&mut self, space: usize, now: &Instant) -> Vec<Vec<Frag>> {
-        let mut bundler = FragBundler::new(space);
-
-        // piggyback ack
-        loop {
-            let ack = match self.to_ack_queue.pop_front() {
-                Some(ack) => ack,
-                None => break,
-            };
-            let frag = FragBuilder {
-                seq: ack,
-                cmd: FragCommand::Ack,
-            }
-            .build()
-            .unwrap();
-            bundler.pack(frag).unwrap();
-            self.stat.acks += 1;
-        }
+    /// Pushes sent but not yet acknowledged, in sequence order
+    pub fn inflight_segments(&self) -> impl Iterator<Item = SegmentInfo> + '_ {
+        self.swnd.iter().map(|(&seq, push)| SegmentInfo {
+            seq,
+            len: push.body().len(),
+            last_sent: push.last_sent(),
+            retransmissions: push.retransmissions(),
+        })
+    }
 
-        // retransmission
+    /// Take the pushes due for retransmission, for the caller to send them on its own
+    ///
+    /// The pushes are regarded as retransmitted at `now`, so `emit` will not output them again until their next RTO.
+    #[must_use]
+    pub fn take_retransmissions(&mut self, now: &Instant) -> Vec<(Seq32, Arc<BufPasta>)> {
+        let mut due = Vec::new();
+        let mut timed_out = false;
         // write pushes from sending
         if !self.fast_retransmission_wnd.is_empty() {
             for (&seq, push) in self.swnd.range_mut(
@@ -245,15 +494,7 @@ impl Uploader {
             ) {
                 {
                     // add push to collection
-                    let frag = FragBuilder {
-                        seq,
-                        cmd: FragCommand::Push {
-                            body: Body::Pasta(Arc::clone(push.body())),
-                        },
-                    }
-                    .build()
-                    .unwrap();
-                    bundler.pack(frag).unwrap();
+                    due.push((seq, Arc::clone(push.body())));
                     push.to_retransmit(*now); // test case: `test_rto_once`
                     self.last_sent_heap
                         .set_priority(&seq, cmp::Reverse(push.last_sent()))
@@ -277,20 +518,13 @@ impl Uploader {
                 if let Some(push) = self.swnd.value_mut(&seq) {
                     {
                         // add push to collection
-                        let frag = FragBuilder {
-                            seq,
-                            cmd: FragCommand::Push {
-                                body: Body::Pasta(Arc::clone(push.body())),
-                            },
-                        }
-                        .build()
-                        .unwrap();
-                        bundler.pack(frag).unwrap();
+                        due.push((seq, Arc::clone(push.body())));
                         push.to_retransmit(*now);
                         self.last_sent_heap
                             .set_priority(&seq, cmp::Reverse(push.last_sent()))
                             .unwrap();
                     }
+                    timed_out = true;
                     self.stat.rto_hits += 1;
                     self.stat.retransmissions += 1;
                     self.stat.pushes += 1;
@@ -301,14 +535,167 @@ impl Uploader {
                 break;
             }
         }
+        if !due.is_empty() {
+            self.on_congestion(timed_out);
+        }
+        self.check_rep();
+        due
+    }
+
+    /// Unit of `max_frag_body_len`: byte
+    #[must_use]
+    #[inline]
+    fn is_nagle_holding(&self, max_frag_body_len: usize) -> bool {
+        self.nagle && !self.swnd.is_empty() && self.to_send_queue.byte_len() < max_frag_body_len
+    }
+
+    #[must_use]
+    fn packet_hdr(&mut self, now: &Instant) -> PacketHeader {
+        let (rwnd, rwnd_shift) = scale_rwnd(self.local_rwnd_size);
+        let timestamps = match self.timestamps {
+            true => Some(Timestamps {
+                ts_val: self.ts_val(now),
+                // nothing to echo yet; the peer samples no RTT from a packet acking nothing
+                ts_ecr: self.remote_ts_val.unwrap_or(0),
+            }),
+            false => None,
+        };
+        PacketHeaderBuilder {
+            version: CURRENT_VERSION,
+            rwnd,
+            rwnd_shift,
+            nack: self.local_next_seq_to_receive,
+            checksum: self.packet_checksum,
+            ece: self.to_echo_ce,
+            timestamps,
+            delimited: self.delimited,
+        }
+        .build()
+        .unwrap()
+    }
+
+    /// Milliseconds since the first stamp, wrapping around
+    fn ts_val(&mut self, now: &Instant) -> u32 {
+        let epoch = *self.timestamps_epoch.get_or_insert(*now);
+        now.saturating_duration_since(epoch).as_millis() as u32
+    }
+
+    #[inline]
+    #[must_use]
+    fn emit_frags(&mut self, space: usize, now: &Instant) -> Vec<Vec<Frag>> {
+        let mut bundler = FragBundler::new(space);
+
+        // piggyback ack
+        loop {
+            let ack = match self.to_ack_queue.pop_front() {
+                Some(ack) => ack,
+                None => break,
+            };
+            let frag = FragBuilder {
+                seq: ack,
+                cmd: FragCommand::Ack,
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap();
+            bundler.pack(frag).unwrap();
+            self.stat.acks += 1;
+        }
+
+        // liveness probes
+        let probes = self
+            .to_ping_queue
+            .drain(..)
+            .map(|id| (id, FragCommand::Ping))
+            .chain(
+                self.to_pong_queue
+                    .drain(..)
+                    .map(|id| (id, FragCommand::Pong)),
+            );
+        for (id, cmd) in probes {
+            let frag = FragBuilder {
+                seq: id,
+                cmd,
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap();
+            bundler.pack(frag).unwrap();
+        }
+
+        if let Some(epoch) = self.to_reset_ack.take() {
+            let frag = FragBuilder {
+                seq: epoch,
+                cmd: FragCommand::ResetAck,
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap();
+            bundler.pack(frag).unwrap();
+        }
+
+        // ahead of the pushes it restarts
+        let rto = self.rto();
+        if let Some(reset) = &mut self.pending_reset {
+            let is_due = match reset.last_sent {
+                Some(last_sent) => now.duration_since(last_sent) >= rto,
+                None => true,
+            };
+            if is_due {
+                let frag = FragBuilder {
+                    seq: self.swnd.start(),
+                    cmd: FragCommand::Reset { epoch: reset.epoch },
+                    checksum: false,
+                    more: false,
+                }
+                .build()
+                .unwrap();
+                bundler.pack(frag).unwrap();
+                reset.last_sent = Some(*now);
+            }
+        }
+
+        // retransmission
+        for (seq, body) in self.take_retransmissions(now) {
+            let frag = FragBuilder {
+                seq,
+                cmd: FragCommand::Push {
+                    body: Body::Pasta(body),
+                },
+                checksum: self.frag_checksum,
+                more: false,
+            }
+            .build()
+            .unwrap();
+            bundler.pack(frag).unwrap();
+        }
 
         // move data from to_send queue to sending queue and output those data
-        while !self.to_send_queue.is_empty() && !self.swnd.is_full() {
+        let max_frag_body_len = space - push_hdr_len(self.frag_checksum);
+        while !self.corked
+            && !self.is_nagle_holding(max_frag_body_len)
+            && !self.to_send_queue.is_empty()
+            && !self.swnd.is_full()
+            && !self.is_cwnd_full()
+            && self.inflight_bytes < self.max_inflight_bytes
+            && self.next_send_time(now) <= *now
+        {
             // get as many bytes from to_send_queue to body
-            let frag_body_limit = match PUSH_HDR_LEN + 1 <= bundler.loading_space() {
-                true => bundler.loading_space() - PUSH_HDR_LEN,
-                false => space - PUSH_HDR_LEN, // TODO: test when all body limit is used
+            let push_hdr_len = push_hdr_len(self.frag_checksum);
+            let frag_body_limit = match push_hdr_len + 1 <= bundler.loading_space() {
+                true => bundler.loading_space() - push_hdr_len,
+                false => space - push_hdr_len, // TODO: test when all body limit is used
             };
+            let frag_body_limit = usize::min(
+                frag_body_limit,
+                self.max_inflight_bytes - self.inflight_bytes,
+            );
+            // never straddle a message boundary
+            let run = self.to_send_runs.front_mut().unwrap();
+            let frag_body_limit = usize::min(frag_body_limit, run.len);
             assert!(frag_body_limit != 0);
             let mut body = BufPasta::new();
             while !self.to_send_queue.is_empty() {
@@ -321,6 +708,11 @@ impl Uploader {
             }
             assert!(body.len() <= frag_body_limit);
             assert!(body.len() > 0);
+            run.len -= body.len();
+            let more = run.message && run.len > 0;
+            if run.len == 0 {
+                self.to_send_runs.pop_front();
+            }
 
             let push = SendingPush::new(Arc::new(body), *now);
 
@@ -331,6 +723,8 @@ impl Uploader {
                 cmd: FragCommand::Push {
                     body: Body::Pasta(Arc::clone(push.body())),
                 },
+                checksum: self.frag_checksum,
+                more,
             }
             .build()
             .unwrap();
@@ -341,8 +735,21 @@ impl Uploader {
                 .push(seq, cmp::Reverse(push.last_sent()));
 
             // register the body to swnd
+            self.inflight_bytes += push.body().len();
             self.swnd.push_back(push);
 
+            if let Some(interval) = self.pacing_interval() {
+                // credit the intervals the emits fell behind by, up to a burst
+                let earliest = now
+                    .checked_sub(interval * (PACING_BURST - 1))
+                    .unwrap_or(*now);
+                let due = match self.next_paced_send {
+                    Some(next) => Instant::max(next, earliest),
+                    None => earliest,
+                };
+                self.next_paced_send = Some(due + interval);
+            }
+
             self.stat.pushes += 1;
         }
 
@@ -350,17 +757,95 @@ impl Uploader {
         return bundler.into_bundles();
     }
 
+    /// See `RttEstimator::rto`
     #[must_use]
     #[inline]
     pub fn rto(&self) -> time::Duration {
-        match self.stat.srtt {
-            Some(srtt) => {
-                let rto = srtt.mul_f64(self.ratio_rto_to_one_rtt);
-                let rto = Duration::min(rto, MAX_RTO);
-                let rto = Duration::max(rto, MIN_RTO);
-                rto
+        self.rtt.rto()
+    }
+
+    /// Limit the swnd further by a congestion window
+    ///
+    /// `None`, the default, leaves only the remote rwnd and `swnd_size_cap` as limits.
+    pub fn set_congestion_control(
+        &mut self,
+        congestion_control: Option<Box<dyn ICongestionControl + Send + Sync + 'static>>,
+    ) {
+        self.congestion_control = congestion_control;
+        self.recovery = None;
+        self.check_rep();
+    }
+
+    /// Unit: sequence
+    #[must_use]
+    pub fn cwnd(&self) -> Option<usize> {
+        self.congestion_control.as_ref().map(|cc| cc.cwnd())
+    }
+
+    /// See `ICongestionControl::ssthresh`
+    ///
+    /// Unit: sequence
+    #[must_use]
+    pub fn ssthresh(&self) -> Option<usize> {
+        self.congestion_control
+            .as_ref()
+            .and_then(|cc| cc.ssthresh())
+    }
+
+    /// Pushes sent and not yet acked
+    ///
+    /// Unit: sequence
+    #[must_use]
+    pub fn inflight(&self) -> usize {
+        self.swnd.size()
+    }
+
+    /// The rwnd last advertised by the peer
+    ///
+    /// Unit: sequence
+    #[must_use]
+    pub fn remote_rwnd(&self) -> usize {
+        self.swnd.remote_rwnd_size()
+    }
+
+    #[must_use]
+    fn is_cwnd_full(&self) -> bool {
+        match self.cwnd() {
+            Some(cwnd) => cwnd <= self.swnd.size(),
+            None => false,
+        }
+    }
+
+    /// Respond to a loss or an ECN echo once per window of pushes
+    ///
+    /// The response opens a recovery up to the pushes sent so far, which ends once they are all acked. Within it, only an RTO after a fast retransmission or an ECN echo is responded to, as the pushes it covers are lost after all.
+    fn on_congestion(&mut self, timed_out: bool) {
+        let cc = match &mut self.congestion_control {
+            Some(cc) => cc,
+            None => return,
+        };
+        if let Some(recovery) = &self.recovery {
+            if recovery.timed_out || !timed_out {
+                return;
             }
-            None => DEFAULT_RTO,
+        }
+        match timed_out {
+            true => cc.on_timeout(),
+            false => cc.on_loss(),
+        }
+        self.recovery = Some(Recovery {
+            end: self.swnd.end(),
+            timed_out,
+        });
+    }
+
+    #[inline]
+    fn on_acked(&mut self, acked: usize) {
+        if acked == 0 {
+            return;
+        }
+        if let Some(cc) = &mut self.congestion_control {
+            cc.on_ack(acked);
         }
     }
 
@@ -369,8 +854,14 @@ impl Uploader {
         self.mtu
     }
 
+    /// Unit: byte
+    #[must_use]
+    pub fn inflight_bytes(&self) -> usize {
+        self.inflight_bytes
+    }
+
     #[inline]
-    fn set_remote_rwnd_size(&mut self, wnd: u16) {
+    fn set_remote_rwnd_size(&mut self, wnd: u32) {
         self.swnd.set_remote_rwnd_size(wnd as usize);
         self.check_rep();
     }
@@ -391,16 +882,12 @@ impl Uploader {
     fn set_acked_local_seq(&mut self, acked_local_seq: Seq32, now: &Instant) {
         // remove the selected sequence
         if let Some(frag) = self.swnd.remove(&acked_local_seq) {
+            self.inflight_bytes -= frag.body().len();
+            self.stat.bytes_acked += frag.body().len() as u64;
+            self.on_acked(1);
             if !frag.is_retransmitted() {
                 // set smooth RTT
-                let frag_rtt = frag.since_last_sent(now);
-                match self.stat.srtt {
-                    Some(srtt) => {
-                        let new_srtt = srtt.mul_f64(1.0 - ALPHA) + frag_rtt.mul_f64(ALPHA);
-                        self.stat.srtt = Some(new_srtt);
-                    }
-                    None => self.stat.srtt = Some(frag_rtt),
-                }
+                self.rtt.on_rtt_sample(frag.since_last_sent(now));
             }
             // else, `last_seen` might just been modified, letting `srtt` become smaller
         }
@@ -409,7 +896,17 @@ impl Uploader {
 
     #[inline]
     fn remove_sending_before(&mut self, remote_nack: Seq32) {
-        self.swnd.remove_before(remote_nack);
+        let removed = self.swnd.remove_before(remote_nack);
+        for push in &removed {
+            self.inflight_bytes -= push.body().len();
+            self.stat.bytes_acked += push.body().len() as u64;
+        }
+        self.on_acked(removed.len());
+        if let Some(recovery) = &self.recovery {
+            if recovery.end <= self.swnd.start() {
+                self.recovery = None;
+            }
+        }
         self.check_rep();
     }
 
@@ -419,6 +916,33 @@ impl Uploader {
         self.check_rep();
     }
 
+    /// Release every push below `remote_nack`, the lowest seq the peer has yet to receive
+    ///
+    /// This is the cumulative counterpart of the per-seq `acked_local_seqs`, and `set_state` applies it along with them. Call it alone for a nack learned some other way. The pushes released count as acked, as in `Stat::bytes_acked`.
+    ///
+    /// A nack beyond `next_seq_to_send` is ignored, or rejected under `strict_framing`; see `Stat::impossible_nacks`.
+    pub fn apply_remote_nack(&mut self, remote_nack: Seq32) -> Result<(), SetStateError> {
+        if self.check_remote_nack(remote_nack)? {
+            self.remove_sending_before(remote_nack);
+        }
+        self.check_rep();
+        Ok(())
+    }
+
+    /// Whether `remote_nack` is possible, counting it in `Stat::impossible_nacks` if not
+    fn check_remote_nack(&mut self, remote_nack: Seq32) -> Result<bool, SetStateError> {
+        // the peer cannot have received a sequence never sent
+        let is_nack_possible = remote_nack <= self.swnd.end();
+        if !is_nack_possible {
+            self.stat.impossible_nacks += 1;
+            if self.strict_framing {
+                self.check_rep();
+                return Err(SetStateError::ImpossibleNack);
+            }
+        }
+        Ok(is_nack_possible)
+    }
+
     #[inline]
     pub fn set_state(&mut self, delta: SetUploadState, now: &Instant) -> Result<(), SetStateError> {
         for &acked_local_seq in &delta.acked_local_seqs {
@@ -426,10 +950,24 @@ impl Uploader {
                 return Err(SetStateError::InvalidState);
             }
         }
+        let is_nack_possible = self.check_remote_nack(delta.remote_nack)?;
 
         self.set_remote_rwnd_size(delta.remote_rwnd_size);
         self.set_local_next_seq_to_receive(delta.local_next_seq_to_receive);
         self.set_local_rwnd_size(delta.local_rwnd_size);
+        // only a packet acking something echoes a `ts_val` from a push it received
+        if self.timestamps && !delta.acked_local_seqs.is_empty() {
+            if let Some(ts_ecr) = delta.remote_ts_ecr {
+                let rtt = Duration::from_millis(u64::from(self.ts_val(now).wrapping_sub(ts_ecr)));
+                // an echo from the future wraps around to an absurd sample
+                if rtt <= MAX_RTO {
+                    self.rtt.on_rtt_sample(rtt);
+                }
+            }
+        }
+        if delta.remote_ts_val.is_some() {
+            self.remote_ts_val = delta.remote_ts_val;
+        }
         let mut max_acked_local_seq = None;
         for acked_local_seq in delta.acked_local_seqs {
             self.set_acked_local_seq(acked_local_seq, now);
@@ -438,30 +976,80 @@ impl Uploader {
                 None => acked_local_seq,
             });
         }
-        self.remove_sending_before(delta.remote_nack); // must after `set_acked_local_seq`s
-                                                       // to retransmit all sequences before the largest out-of-order sequence
-        if let Some(x) = max_acked_local_seq {
-            if delta.remote_nack < x {
-                self.fast_retransmission_wnd
-                    .try_set_boundaries(delta.remote_nack..x);
+        // otherwise, the nack is ignored
+        if is_nack_possible {
+            self.remove_sending_before(delta.remote_nack); // must after `set_acked_local_seq`s
+                                                           // to retransmit all sequences before the largest out-of-order sequence
+            if let Some(x) = max_acked_local_seq {
+                if delta.remote_nack < x {
+                    self.fast_retransmission_wnd
+                        .try_set_boundaries(delta.remote_nack..x);
+                }
             }
         }
 
         for remote_seq_to_ack in delta.remote_seqs_to_ack {
             self.add_remote_seq_to_ack(remote_seq_to_ack);
         }
+        self.to_pong_queue.extend(delta.remote_pings);
+        if delta.remote_reset_epoch.is_some() {
+            self.to_reset_ack = delta.remote_reset_epoch;
+        }
+        if let Some(reset) = &self.pending_reset {
+            if delta.reset_acks.contains(&reset.epoch) {
+                self.pending_reset = None;
+            }
+        }
+        // the peer should learn the restarted nack at once
+        if delta.local_rwnd_probed || delta.reset {
+            self.to_advertise_rwnd = true;
+        }
+        if delta.ce_marked {
+            self.to_echo_ce = true;
+        }
+        // respond to congestion as to a loss
+        if delta.remote_ece {
+            self.on_congestion(false);
+        }
         self.check_rep();
         Ok(())
     }
 }
 
+#[must_use]
+#[inline]
+fn push_hdr_len(checksum: bool) -> usize {
+    match checksum {
+        true => PUSH_HDR_LEN + CHECKSUM_LEN,
+        false => PUSH_HDR_LEN,
+    }
+}
+
+/// Bytes of `to_send_queue` from a single message or from consecutive `write`s
+struct SendRun {
+    len: usize,
+    message: bool,
+}
+
+struct Recovery {
+    /// The first push sent after the response
+    end: Seq32,
+    timed_out: bool,
+}
+
+struct PendingReset {
+    epoch: Seq32,
+    last_sent: Option<Instant>,
+}
+
 struct LocalStat {
-    srtt: Option<time::Duration>,
     retransmissions: u64,
     rto_hits: u64,
     fast_retransmissions: u64,
     pushes: u64,
     acks: u64,
+    bytes_acked: u64,
+    impossible_nacks: u64,
 }
 
 #[derive(Debug, PartialEq)]
@@ -472,13 +1060,32 @@ pub struct Stat {
     pub fast_retransmissions: u64,
     pub pushes: u64,
     pub acks: u64,
+    /// Bytes of pushes freed from swnd by acks or nacks, excluding retransmissions
+    pub bytes_acked: u64,
+    /// Nacks beyond `next_seq_to_send`, from a corrupted packet or a buggy peer
+    pub impossible_nacks: u64,
     pub next_seq_to_send: Seq32,
 }
 
+#[derive(Debug, PartialEq)]
+pub struct SegmentInfo {
+    pub seq: Seq32,
+    /// Unit: byte
+    pub len: usize,
+    pub last_sent: Instant,
+    pub retransmissions: usize,
+}
+
 #[cfg(test)]
 mod tests {
+    use super::PACING_BURST;
     use crate::{
-        layer::{uploader::UploaderBuilder, SetUploadState},
+        layer::{
+            uploader::{
+                BuildError, NewReno, SegmentInfo, SetStateError, Uploader, UploaderBuilder,
+            },
+            SetUploadState,
+        },
         protocol::{
             frag::{Body, FragCommand, ACK_HDR_LEN, PUSH_HDR_LEN},
             packet_hdr::PACKET_HDR_LEN,
@@ -488,7 +1095,7 @@ mod tests {
             Seq32,
         },
     };
-    use std::time::Instant;
+    use std::time::{Duration, Instant};
 
     const MTU: usize = 512;
 
@@ -522,7 +1129,7 @@ mod tests {
                     Body::Slice(_) => panic!(),
                     Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                 },
-                FragCommand::Ack => panic!(),
+                _ => panic!(),
             }
             assert_eq!(body.data(), origin);
         }
@@ -555,7 +1162,7 @@ mod tests {
                     Body::Slice(_) => panic!(),
                     Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                 },
-                FragCommand::Ack => panic!(),
+                _ => panic!(),
             }
             assert_eq!(body.data()[..origin1.len()], origin1);
             assert_eq!(body.data()[origin1.len()..], origin2);
@@ -589,7 +1196,7 @@ mod tests {
                     Body::Slice(_) => panic!(),
                     Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                 },
-                FragCommand::Ack => panic!(),
+                _ => panic!(),
             }
             assert_eq!(body.data()[..origin1.len()], origin1);
             assert_eq!(
@@ -613,7 +1220,7 @@ mod tests {
                     Body::Slice(_) => panic!(),
                     Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                 },
-                FragCommand::Ack => panic!(),
+                _ => panic!(),
             }
             assert_eq!(
                 body.data(),
@@ -651,7 +1258,7 @@ mod tests {
                     Body::Slice(_) => panic!(),
                     Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                 },
-                FragCommand::Ack => panic!(),
+                _ => panic!(),
             }
             assert_eq!(body.data(), &origin1[..MTU - PACKET_HDR_LEN - PUSH_HDR_LEN]);
         }
@@ -671,7 +1278,7 @@ mod tests {
                     Body::Slice(_) => panic!(),
                     Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                 },
-                FragCommand::Ack => panic!(),
+                _ => panic!(),
             }
             assert_eq!(
                 body.data()[..PACKET_HDR_LEN + PUSH_HDR_LEN],
@@ -748,10 +1355,10 @@ mod tests {
         let mut uploader = UploaderBuilder {
             local_recv_buf_len: 0,
             nack_duplicate_threshold_to_activate_fast_retransmit: dup,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: MTU,
+            ..UploaderBuilder::default()
         }
         .build()
         .unwrap();
@@ -782,6 +1389,22 @@ mod tests {
             remote_seqs_to_ack: vec![],
             acked_local_seqs: vec![Seq32::from_u32(1)],
             local_rwnd_size: 1,
+            local_recv_buf_bytes_left: usize::MAX,
+            local_rwnd_probed: false,
+            sack_ranges: vec![],
+            ce_marked: false,
+            remote_ece: false,
+            remote_ts_val: None,
+            remote_ts_ecr: None,
+            remote_pings: vec![],
+            pongs: vec![],
+            reset: false,
+            remote_reset_epoch: None,
+            reset_acks: vec![],
+            bytes_consumed: 0,
+            in_order_pushes: 0,
+            reordered_pushes: 0,
+            truncated_push: None,
         };
         uploader.set_state(state, &now).unwrap();
 
@@ -797,10 +1420,10 @@ mod tests {
         let mut uploader = UploaderBuilder {
             local_recv_buf_len: 0,
             nack_duplicate_threshold_to_activate_fast_retransmit: dup,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: MTU,
+            ..UploaderBuilder::default()
         }
         .build()
         .unwrap();
@@ -829,6 +1452,22 @@ mod tests {
             remote_seqs_to_ack: vec![],
             acked_local_seqs: vec![Seq32::from_u32(0)],
             local_rwnd_size: 1,
+            local_recv_buf_bytes_left: usize::MAX,
+            local_rwnd_probed: false,
+            sack_ranges: vec![],
+            ce_marked: false,
+            remote_ece: false,
+            remote_ts_val: None,
+            remote_ts_ecr: None,
+            remote_pings: vec![],
+            pongs: vec![],
+            reset: false,
+            remote_reset_epoch: None,
+            reset_acks: vec![],
+            bytes_consumed: 0,
+            in_order_pushes: 0,
+            reordered_pushes: 0,
+            truncated_push: None,
         };
         uploader.set_state(state, &now).unwrap();
 
@@ -850,10 +1489,10 @@ mod tests {
         let mut uploader = UploaderBuilder {
             local_recv_buf_len: 0,
             nack_duplicate_threshold_to_activate_fast_retransmit: dup,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: MTU,
+            ..UploaderBuilder::default()
         }
         .build()
         .unwrap();
@@ -890,6 +1529,22 @@ mod tests {
             remote_seqs_to_ack: vec![],
             acked_local_seqs: vec![Seq32::from_u32(2)],
             local_rwnd_size: 1,
+            local_recv_buf_bytes_left: usize::MAX,
+            local_rwnd_probed: false,
+            sack_ranges: vec![],
+            ce_marked: false,
+            remote_ece: false,
+            remote_ts_val: None,
+            remote_ts_ecr: None,
+            remote_pings: vec![],
+            pongs: vec![],
+            reset: false,
+            remote_reset_epoch: None,
+            reset_acks: vec![],
+            bytes_consumed: 0,
+            in_order_pushes: 0,
+            reordered_pushes: 0,
+            truncated_push: None,
         };
         uploader.set_state(state, &now).unwrap();
 
@@ -915,10 +1570,10 @@ mod tests {
         let mut uploader = UploaderBuilder {
             local_recv_buf_len: 0,
             nack_duplicate_threshold_to_activate_fast_retransmit: dup,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: MTU,
+            ..UploaderBuilder::default()
         }
         .build()
         .unwrap();
@@ -955,6 +1610,22 @@ mod tests {
             remote_seqs_to_ack: vec![],
             acked_local_seqs: vec![Seq32::from_u32(2)],
             local_rwnd_size: 1,
+            local_recv_buf_bytes_left: usize::MAX,
+            local_rwnd_probed: false,
+            sack_ranges: vec![],
+            ce_marked: false,
+            remote_ece: false,
+            remote_ts_val: None,
+            remote_ts_ecr: None,
+            remote_pings: vec![],
+            pongs: vec![],
+            reset: false,
+            remote_reset_epoch: None,
+            reset_acks: vec![],
+            bytes_consumed: 0,
+            in_order_pushes: 0,
+            reordered_pushes: 0,
+            truncated_push: None,
         };
         uploader.set_state(state, &now).unwrap();
 
@@ -979,6 +1650,22 @@ mod tests {
             remote_seqs_to_ack: vec![],
             acked_local_seqs: vec![Seq32::from_u32(2)],
             local_rwnd_size: 1,
+            local_recv_buf_bytes_left: usize::MAX,
+            local_rwnd_probed: false,
+            sack_ranges: vec![],
+            ce_marked: false,
+            remote_ece: false,
+            remote_ts_val: None,
+            remote_ts_ecr: None,
+            remote_pings: vec![],
+            pongs: vec![],
+            reset: false,
+            remote_reset_epoch: None,
+            reset_acks: vec![],
+            bytes_consumed: 0,
+            in_order_pushes: 0,
+            reordered_pushes: 0,
+            truncated_push: None,
         };
         uploader.set_state(state, &now).unwrap();
 
@@ -995,10 +1682,10 @@ mod tests {
         let mut uploader = UploaderBuilder {
             local_recv_buf_len: 0,
             nack_duplicate_threshold_to_activate_fast_retransmit: 0,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: PACKET_HDR_LEN + ACK_HDR_LEN * 2 + PUSH_HDR_LEN + 1,
+            ..UploaderBuilder::default()
         }
         .build()
         .unwrap();
@@ -1029,6 +1716,22 @@ mod tests {
                     remote_seqs_to_ack: vec![Seq32::from_u32(0), Seq32::from_u32(1)],
                     acked_local_seqs: Vec::new(),
                     local_rwnd_size: 99,
+                    local_recv_buf_bytes_left: usize::MAX,
+                    local_rwnd_probed: false,
+                    sack_ranges: vec![],
+                    ce_marked: false,
+                    remote_ece: false,
+                    remote_ts_val: None,
+                    remote_ts_ecr: None,
+                    remote_pings: vec![],
+                    pongs: vec![],
+                    reset: false,
+                    remote_reset_epoch: None,
+                    reset_acks: vec![],
+                    bytes_consumed: 0,
+                    in_order_pushes: 0,
+                    reordered_pushes: 0,
+                    truncated_push: None,
                 },
                 &now,
             )
@@ -1062,13 +1765,13 @@ mod tests {
                 assert_eq!(packets[0].frags().len(), 3);
                 assert_eq!(packets[0].frags()[0].seq().to_u32(), 0);
                 match packets[0].frags()[0].cmd() {
-                    FragCommand::Push { body: _ } => panic!(),
                     FragCommand::Ack => (),
+                    _ => panic!(),
                 }
                 assert_eq!(packets[0].frags()[1].seq().to_u32(), 1);
                 match packets[0].frags()[1].cmd() {
-                    FragCommand::Push { body: _ } => panic!(),
                     FragCommand::Ack => (),
+                    _ => panic!(),
                 }
                 assert_eq!(packets[0].frags()[2].seq().to_u32(), 0);
                 let mut body = OwnedBufWtr::new(1, 0);
@@ -1077,7 +1780,7 @@ mod tests {
                         Body::Slice(_) => panic!(),
                         Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                     },
-                    FragCommand::Ack => panic!(),
+                    _ => panic!(),
                 }
                 assert_eq!(body.data(), vec![9]);
             }
@@ -1089,7 +1792,7 @@ mod tests {
                         Body::Slice(_) => panic!(),
                         Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                     },
-                    FragCommand::Ack => panic!(),
+                    _ => panic!(),
                 }
                 assert_eq!(body.data(), vec![8, 7]);
             }
@@ -1126,16 +1829,313 @@ mod tests {
         // );
     }
 
+    #[test]
+    fn test_queue_ack() {
+        let now = Instant::now();
+        let mut uploader = UploaderBuilder::default().build().unwrap();
+
+        uploader.queue_ack(Seq32::from_u32(7));
+        uploader.queue_sack(Seq32::from_u32(u32::MAX)..Seq32::from_u32(1));
+
+        let packets = uploader.emit(&now);
+        assert_eq!(packets.len(), 1);
+        let seqs: Vec<u32> = packets[0]
+            .frags()
+            .iter()
+            .map(|frag| {
+                match frag.cmd() {
+                    FragCommand::Ack => (),
+                    _ => panic!(),
+                }
+                frag.seq().to_u32()
+            })
+            .collect();
+        assert_eq!(seqs, vec![7, u32::MAX, 0]);
+        assert!(uploader.to_ack_queue.is_empty());
+        assert_eq!(uploader.emit(&now).len(), 0);
+    }
+
+    #[test]
+    fn test_bytes_acked() {
+        let now = Instant::now();
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(99);
+
+        for data in [vec![0, 1, 2], vec![3, 4], vec![5]] {
+            uploader
+                .write(BufSlice::from_bytes(data))
+                .map_err(|_| ())
+                .unwrap();
+            assert_eq!(uploader.emit(&now).len(), 1);
+        }
+        assert_eq!(uploader.stat().bytes_acked, 0);
+
+        // selective
+        uploader.set_acked_local_seq(Seq32::from_u32(1), &now);
+        assert_eq!(uploader.stat().bytes_acked, 2);
+        // duplicate
+        uploader.set_acked_local_seq(Seq32::from_u32(1), &now);
+        assert_eq!(uploader.stat().bytes_acked, 2);
+        // cumulative
+        uploader.remove_sending_before(Seq32::from_u32(3));
+        assert_eq!(uploader.stat().bytes_acked, 6);
+    }
+
+    #[test]
+    fn test_max_inflight_bytes() {
+        let now = Instant::now();
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            max_inflight_bytes: 5,
+            mtu: MTU,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(99);
+
+        uploader
+            .write(BufSlice::from_bytes(vec![0, 1, 2, 3, 4, 5, 6, 7]))
+            .map_err(|_| ())
+            .unwrap();
+
+        // swnd would allow more pushes, but the in-flight bytes are capped
+        let packets = uploader.emit(&now);
+        {
+            assert_eq!(packets.len(), 1);
+            assert_eq!(packets[0].frags().len(), 1);
+            let mut body = OwnedBufWtr::new(MTU, 0);
+            match packets[0].frags()[0].cmd() {
+                FragCommand::Push { body: x } => match x {
+                    Body::Slice(_) => panic!(),
+                    Body::Pasta(x) => x.append_to(&mut body).unwrap(),
+                },
+                _ => panic!(),
+            }
+            assert_eq!(body.data(), vec![0, 1, 2, 3, 4]);
+        }
+        assert_eq!(uploader.inflight_bytes(), 5);
+        assert_eq!(uploader.emit(&now).len(), 0);
+
+        uploader.set_acked_local_seq(Seq32::from_u32(0), &now);
+        assert_eq!(uploader.inflight_bytes(), 0);
+
+        let packets = uploader.emit(&now);
+        {
+            assert_eq!(packets.len(), 1);
+            let mut body = OwnedBufWtr::new(MTU, 0);
+            match packets[0].frags()[0].cmd() {
+                FragCommand::Push { body: x } => match x {
+                    Body::Slice(_) => panic!(),
+                    Body::Pasta(x) => x.append_to(&mut body).unwrap(),
+                },
+                _ => panic!(),
+            }
+            assert_eq!(body.data(), vec![5, 6, 7]);
+        }
+        assert_eq!(uploader.inflight_bytes(), 3);
+    }
+
+    #[test]
+    fn test_take_retransmissions() {
+        let mut now = Instant::now();
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(99);
+
+        for data in [vec![0, 1, 2], vec![3, 4]] {
+            uploader
+                .write(BufSlice::from_bytes(data))
+                .map_err(|_| ())
+                .unwrap();
+            assert_eq!(uploader.emit(&now).len(), 1);
+        }
+        assert!(uploader.take_retransmissions(&now).is_empty());
+
+        now += uploader.rto();
+        let due = uploader.take_retransmissions(&now);
+        assert_eq!(due.len(), 2);
+        for ((seq, body), (expected_seq, expected_body)) in
+            due.iter().zip([(0, vec![0, 1, 2]), (1, vec![3, 4])])
+        {
+            assert_eq!(seq.to_u32(), expected_seq);
+            let mut wtr = OwnedBufWtr::new(MTU, 0);
+            body.append_to(&mut wtr).unwrap();
+            assert_eq!(wtr.data(), expected_body);
+        }
+        assert_eq!(uploader.stat().rto_hits, 2);
+
+        // already taken
+        assert_eq!(uploader.emit(&now).len(), 0);
+    }
+
+    #[test]
+    fn test_inflight_segments() {
+        let t0 = Instant::now();
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(99);
+
+        for data in [vec![0, 1, 2], vec![3, 4], vec![5]] {
+            uploader
+                .write(BufSlice::from_bytes(data))
+                .map_err(|_| ())
+                .unwrap();
+            assert_eq!(uploader.emit(&t0).len(), 1);
+        }
+        let t1 = t0 + uploader.rto();
+        assert_eq!(uploader.take_retransmissions(&t1).len(), 3);
+        uploader.set_acked_local_seq(Seq32::from_u32(1), &t1);
+
+        let segments: Vec<SegmentInfo> = uploader.inflight_segments().collect();
+        assert_eq!(
+            segments,
+            vec![
+                SegmentInfo {
+                    seq: Seq32::from_u32(0),
+                    len: 3,
+                    last_sent: t1,
+                    retransmissions: 1,
+                },
+                SegmentInfo {
+                    seq: Seq32::from_u32(2),
+                    len: 1,
+                    last_sent: t1,
+                    retransmissions: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_packets_within_mtu() {
+        let now = Instant::now();
+        let mtu = 64;
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(99);
+
+        for seq in 0..8 {
+            uploader.queue_ack(Seq32::from_u32(seq));
+        }
+        uploader
+            .write(BufSlice::from_bytes(vec![0; 50]))
+            .map_err(|_| ())
+            .unwrap();
+
+        // [packet_header] [ack]*8 [push_hdr] [50] does not fit in one datagram
+        let packets = uploader.emit(&now);
+        assert!(packets.len() >= 2);
+        let mut acks = 0;
+        let mut body_len = 0;
+        for packet in &packets {
+            let mut wtr = OwnedBufWtr::new(MTU, 0);
+            packet.append_to(&mut wtr).unwrap();
+            assert!(wtr.data_len() <= mtu);
+            for frag in packet.frags() {
+                match frag.cmd() {
+                    FragCommand::Push { body } => body_len += body.len(),
+                    FragCommand::Ack => acks += 1,
+                    _ => panic!(),
+                }
+            }
+        }
+        assert_eq!(acks, 8);
+        assert_eq!(body_len, 50);
+    }
+
+    #[test]
+    fn test_cork() {
+        let now = Instant::now();
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(99);
+
+        uploader.cork();
+        for data in [vec![0, 1], vec![2], vec![3, 4, 5]] {
+            uploader
+                .write(BufSlice::from_bytes(data))
+                .map_err(|_| ())
+                .unwrap();
+            assert_eq!(uploader.emit(&now).len(), 0);
+        }
+
+        // acks are not held back
+        uploader.queue_ack(Seq32::from_u32(0));
+        let packets = uploader.emit(&now);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].frags().len(), 1);
+        assert!(matches!(packets[0].frags()[0].cmd(), FragCommand::Ack));
+
+        uploader.uncork();
+        let packets = uploader.emit(&now);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].frags().len(), 1);
+        let mut body = OwnedBufWtr::new(MTU, 0);
+        match packets[0].frags()[0].cmd() {
+            FragCommand::Push { body: x } => match x {
+                Body::Slice(_) => panic!(),
+                Body::Pasta(x) => x.append_to(&mut body).unwrap(),
+            },
+            _ => panic!(),
+        }
+        assert_eq!(body.data(), vec![0, 1, 2, 3, 4, 5]);
+    }
+
     #[test]
     fn test_body_pasta() {
         let now = Instant::now();
         let mut uploader = UploaderBuilder {
             local_recv_buf_len: 0,
             nack_duplicate_threshold_to_activate_fast_retransmit: 0,
-            ratio_rto_to_one_rtt: 1.5,
             to_send_queue_len_cap: usize::MAX,
             swnd_size_cap: usize::MAX,
             mtu: PACKET_HDR_LEN + PUSH_HDR_LEN + 6,
+            ..UploaderBuilder::default()
         }
         .build()
         .unwrap();
@@ -1166,9 +2166,371 @@ mod tests {
                     Body::Slice(_) => panic!(),
                     Body::Pasta(x) => x.append_to(&mut body).unwrap(),
                 },
-                FragCommand::Ack => panic!(),
+                _ => panic!(),
             };
             assert_eq!(body.data(), vec![0, 1, 2, 3, 4, 5]);
         }
     }
+
+    #[test]
+    fn test_impossible_nack() {
+        let now = Instant::now();
+        let state = || SetUploadState {
+            remote_rwnd_size: 99,
+            remote_nack: Seq32::from_u32(5),
+            local_next_seq_to_receive: Seq32::from_u32(0),
+            remote_seqs_to_ack: vec![],
+            acked_local_seqs: vec![],
+            local_rwnd_size: 0,
+            local_recv_buf_bytes_left: usize::MAX,
+            local_rwnd_probed: false,
+            sack_ranges: vec![],
+            ce_marked: false,
+            remote_ece: false,
+            remote_ts_val: None,
+            remote_ts_ecr: None,
+            remote_pings: vec![],
+            pongs: vec![],
+            reset: false,
+            remote_reset_epoch: None,
+            reset_acks: vec![],
+            bytes_consumed: 0,
+            in_order_pushes: 0,
+            reordered_pushes: 0,
+            truncated_push: None,
+        };
+        for strict_framing in [false, true] {
+            let mut uploader = UploaderBuilder {
+                local_recv_buf_len: 0,
+                nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+                to_send_queue_len_cap: usize::MAX,
+                swnd_size_cap: usize::MAX,
+                strict_framing,
+                mtu: MTU,
+                ..UploaderBuilder::default()
+            }
+            .build()
+            .unwrap();
+            uploader.set_remote_rwnd_size(99);
+            uploader
+                .write(BufSlice::from_bytes(vec![0, 1, 2]))
+                .map_err(|_| ())
+                .unwrap();
+            assert_eq!(uploader.emit(&now).len(), 1);
+
+            // only seq(0) has been sent
+            let result = uploader.set_state(state(), &now);
+            match strict_framing {
+                true => assert_eq!(result.err(), Some(SetStateError::ImpossibleNack)),
+                false => assert!(result.is_ok()),
+            }
+            assert_eq!(uploader.stat().impossible_nacks, 1);
+            assert_eq!(uploader.stat().bytes_acked, 0);
+            assert_eq!(uploader.inflight_segments().count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_congestion_control() {
+        let now = Instant::now();
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(99);
+        uploader.set_congestion_control(Some(Box::new(NewReno::new(1))));
+        let write_then_emit = |uploader: &mut Uploader, data: u8| {
+            uploader
+                .write(BufSlice::from_bytes(vec![data]))
+                .map_err(|_| ())
+                .unwrap();
+            uploader.emit(&now).len()
+        };
+
+        assert_eq!(write_then_emit(&mut uploader, 0), 1);
+        // cwnd of 1 is full
+        assert_eq!(write_then_emit(&mut uploader, 1), 0);
+
+        uploader.set_acked_local_seq(Seq32::from_u32(0), &now);
+        assert_eq!(uploader.cwnd(), Some(2));
+        assert_eq!(uploader.emit(&now).len(), 1);
+        assert_eq!(write_then_emit(&mut uploader, 2), 1);
+        assert_eq!(write_then_emit(&mut uploader, 3), 0);
+        assert_eq!(uploader.inflight_segments().count(), 2);
+    }
+
+    #[test]
+    fn test_congestion_recovery() {
+        let mut now = Instant::now();
+        let mut builder = UploaderBuilder::default();
+        builder.mtu = MTU;
+        let mut uploader = builder.build().unwrap();
+        uploader.set_remote_rwnd_size(99);
+        uploader.set_congestion_control(Some(Box::new(NewReno::new(8))));
+        for data in 0..8 {
+            uploader
+                .write(BufSlice::from_bytes(vec![data]))
+                .map_err(|_| ())
+                .unwrap();
+            assert_eq!(uploader.emit(&now).len(), 1);
+        }
+        let state = |remote_nack: u32, remote_ece: bool| SetUploadState {
+            remote_rwnd_size: 99,
+            remote_nack: Seq32::from_u32(remote_nack),
+            local_next_seq_to_receive: Seq32::from_u32(0),
+            remote_seqs_to_ack: vec![],
+            acked_local_seqs: vec![],
+            local_rwnd_size: 0,
+            local_recv_buf_bytes_left: usize::MAX,
+            local_rwnd_probed: false,
+            sack_ranges: vec![],
+            ce_marked: false,
+            remote_ece,
+            remote_ts_val: None,
+            remote_ts_ecr: None,
+            remote_pings: vec![],
+            pongs: vec![],
+            reset: false,
+            remote_reset_epoch: None,
+            reset_acks: vec![],
+            bytes_consumed: 0,
+            in_order_pushes: 0,
+            reordered_pushes: 0,
+            truncated_push: None,
+        };
+
+        // halved once for the whole window
+        uploader.set_state(state(0, true), &now).unwrap();
+        assert_eq!(uploader.cwnd(), Some(4));
+        uploader.set_state(state(0, true), &now).unwrap();
+        assert_eq!(uploader.cwnd(), Some(4));
+
+        // the window is lost after all
+        now += uploader.rto();
+        assert_eq!(uploader.take_retransmissions(&now).len(), 8);
+        assert_eq!(uploader.cwnd(), Some(1));
+        now += uploader.rto();
+        assert_eq!(uploader.take_retransmissions(&now).len(), 8);
+        uploader.set_state(state(0, true), &now).unwrap();
+        assert_eq!(uploader.cwnd(), Some(1));
+
+        // the recovery ends with the window acked
+        uploader.set_state(state(8, false), &now).unwrap();
+        let cwnd = uploader.cwnd().unwrap();
+        uploader.set_state(state(8, true), &now).unwrap();
+        assert_eq!(uploader.cwnd(), Some(usize::max(cwnd / 2, 2)));
+    }
+
+    #[test]
+    fn test_nagle() {
+        let now = Instant::now();
+        let uploader = |nagle: bool| {
+            let mut uploader = UploaderBuilder {
+                local_recv_buf_len: 0,
+                nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+                to_send_queue_len_cap: usize::MAX,
+                swnd_size_cap: usize::MAX,
+                mtu: MTU,
+                ..UploaderBuilder::default()
+            }
+            .build()
+            .unwrap();
+            uploader.set_remote_rwnd_size(99);
+            uploader.set_nagle(nagle);
+            uploader
+        };
+        let write_then_emit = |uploader: &mut Uploader, data: Vec<u8>| {
+            uploader
+                .write(BufSlice::from_bytes(data))
+                .map_err(|_| ())
+                .unwrap();
+            uploader.emit(&now).len()
+        };
+
+        // without nagle, every small write is a push of its own
+        let mut uploader1 = uploader(false);
+        assert_eq!(write_then_emit(&mut uploader1, vec![0]), 1);
+        assert_eq!(write_then_emit(&mut uploader1, vec![1]), 1);
+        assert_eq!(write_then_emit(&mut uploader1, vec![2]), 1);
+        assert_eq!(uploader1.inflight_segments().count(), 3);
+
+        // with nagle, small writes coalesce while a push is unacked
+        let mut uploader2 = uploader(true);
+        assert_eq!(write_then_emit(&mut uploader2, vec![0]), 1);
+        assert_eq!(write_then_emit(&mut uploader2, vec![1]), 0);
+        assert_eq!(write_then_emit(&mut uploader2, vec![2]), 0);
+        uploader2.set_acked_local_seq(Seq32::from_u32(0), &now);
+        let packets = uploader2.emit(&now);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].frags().len(), 1);
+        assert_eq!(packets[0].frags()[0].seq(), Seq32::from_u32(1));
+        match packets[0].frags()[0].cmd() {
+            FragCommand::Push { body } => assert_eq!(body.len(), 2),
+            _ => panic!(),
+        }
+
+        // a full packet is not held
+        let full = vec![0; MTU];
+        assert!(write_then_emit(&mut uploader2, full) > 0);
+    }
+
+    fn pacing_uploader(remote_rwnd: u32) -> Uploader {
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(remote_rwnd);
+        uploader.set_pacing(true);
+        uploader.rtt.on_rtt_sample(Duration::from_millis(100));
+        uploader
+    }
+
+    #[test]
+    fn test_rto_bounds() {
+        let mut builder = UploaderBuilder::default();
+        builder.min_rto = Duration::from_millis(200);
+        builder.max_rto = Duration::from_secs(1);
+        let mut uploader = builder.build().unwrap();
+        // the initial RTO is clamped too
+        assert_eq!(uploader.rto(), Duration::from_secs(1));
+
+        // 200 + 4 * 100
+        uploader.rtt.on_rtt_sample(Duration::from_millis(200));
+        assert_eq!(uploader.rto(), Duration::from_millis(600));
+        for _ in 0..100 {
+            uploader.rtt.on_rtt_sample(Duration::from_millis(10));
+        }
+        assert_eq!(uploader.rto(), Duration::from_millis(200));
+
+        let mut builder = UploaderBuilder::default();
+        builder.min_rto = Duration::from_secs(2);
+        builder.max_rto = Duration::from_secs(1);
+        assert!(matches!(builder.build(), Err(BuildError::MinRtoAboveMax)));
+    }
+
+    #[test]
+    fn test_pacing_interval() {
+        let mut uploader = pacing_uploader(10);
+        uploader.set_pacing(false);
+        assert_eq!(uploader.pacing_interval(), None);
+        uploader.set_pacing(true);
+        assert_eq!(uploader.pacing_interval(), Some(Duration::from_millis(10)));
+
+        // inversely with the remote rwnd
+        let uploader = pacing_uploader(20);
+        assert_eq!(uploader.pacing_interval(), Some(Duration::from_millis(5)));
+
+        // the cwnd takes over from the remote rwnd
+        let mut uploader = pacing_uploader(20);
+        uploader.set_congestion_control(Some(Box::new(NewReno::new(4))));
+        assert_eq!(uploader.pacing_interval(), Some(Duration::from_millis(25)));
+        uploader.set_congestion_control(Some(Box::new(NewReno::new(8))));
+        assert_eq!(
+            uploader.pacing_interval(),
+            Some(Duration::from_micros(12_500))
+        );
+
+        // a closed window paces as one of a single sequence
+        let uploader = pacing_uploader(0);
+        assert_eq!(uploader.pacing_interval(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_pacing_emit() {
+        let now = Instant::now();
+        let interval = Duration::from_millis(5);
+        let mut uploader = pacing_uploader(20);
+        assert_eq!(uploader.next_send_time(&now), now);
+        for i in 0..12 {
+            uploader
+                .write(BufSlice::from_bytes(vec![i; MTU]))
+                .map_err(|_| ())
+                .unwrap();
+        }
+
+        // a burst, then one push per interval rather than the whole window
+        assert_eq!(uploader.emit(&now).len(), PACING_BURST as usize);
+        let next = uploader.next_send_time(&now);
+        assert_eq!(next, now + interval);
+        assert!(uploader.emit(&now).is_empty());
+        assert_eq!(uploader.emit(&next).len(), 1);
+
+        // an emit running late catches up with the intervals missed
+        let late = next + interval * 3;
+        assert_eq!(uploader.emit(&late).len(), 3);
+        // but not beyond the burst
+        let idle = late + interval * 10;
+        assert_eq!(uploader.emit(&idle).len(), PACING_BURST as usize);
+        assert_eq!(uploader.inflight_segments().count(), 12);
+
+        // acks are not held back
+        uploader.queue_ack(Seq32::from_u32(0));
+        assert_eq!(uploader.emit(&idle).len(), 1);
+
+        // without pacing, the rest goes out at once
+        for i in 0..4 {
+            uploader
+                .write(BufSlice::from_bytes(vec![i; MTU]))
+                .map_err(|_| ())
+                .unwrap();
+        }
+        uploader.set_pacing(false);
+        assert_eq!(uploader.next_send_time(&idle), idle);
+        assert!(uploader.emit(&idle).len() > 1);
+        assert_eq!(uploader.inflight_bytes(), MTU * 16);
+    }
+
+    #[test]
+    fn test_apply_remote_nack() {
+        let now = Instant::now();
+        let mut uploader = UploaderBuilder {
+            local_recv_buf_len: 0,
+            nack_duplicate_threshold_to_activate_fast_retransmit: 0,
+            to_send_queue_len_cap: usize::MAX,
+            swnd_size_cap: usize::MAX,
+            mtu: MTU,
+            ..UploaderBuilder::default()
+        }
+        .build()
+        .unwrap();
+        uploader.set_remote_rwnd_size(10);
+        for i in 0..5 {
+            uploader
+                .write(BufSlice::from_bytes(vec![i; 3]))
+                .map_err(|_| ())
+                .unwrap();
+            assert_eq!(uploader.emit(&now).len(), 1);
+        }
+        assert_eq!(uploader.inflight_bytes(), 15);
+
+        // no acks of their own, yet the run below the nack is freed
+        uploader.apply_remote_nack(Seq32::from_u32(4)).unwrap();
+        let seqs: Vec<Seq32> = uploader.inflight_segments().map(|x| x.seq).collect();
+        assert_eq!(seqs, vec![Seq32::from_u32(4)]);
+        assert_eq!(uploader.inflight_bytes(), 3);
+        assert_eq!(uploader.stat().bytes_acked, 12);
+
+        // nothing freed is retransmitted
+        let packets = uploader.emit(&(now + uploader.rto()));
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].frags().len(), 1);
+        assert_eq!(packets[0].frags()[0].seq(), Seq32::from_u32(4));
+
+        // beyond anything sent
+        uploader.apply_remote_nack(Seq32::from_u32(9)).unwrap();
+        assert_eq!(uploader.inflight_bytes(), 3);
+        assert_eq!(uploader.stat().impossible_nacks, 1);
+    }
 }
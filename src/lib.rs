@@ -1,3 +1,12 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 pub mod layer;
 pub mod protocol;
+#[cfg(feature = "tokio")]
+pub mod stream;
 pub mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasm;
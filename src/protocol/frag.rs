@@ -1,44 +1,93 @@
 use super::{DecodingError, EncodingError};
 use crate::utils::{
-    buf::{BufPasta, BufSlice, BufWtr},
-    Seq32,
+    buf::{BufPasta, BufRdr, BufSlice, BufWtr, Cursor},
+    Crc32, Seq32, CHECK_REP,
 };
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use alloc::{sync::Arc, vec::Vec};
+use byteorder::BigEndian;
+use core::fmt;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::{io::Cursor, sync::Arc};
 
 pub const PUSH_HDR_LEN: usize = 9;
 pub const ACK_HDR_LEN: usize = 5;
+pub const RESET_HDR_LEN: usize = 9;
+pub const CHECKSUM_LEN: usize = 4;
+const CMD_CHECKSUM_FLAG: u8 = 0x80;
+const CMD_MORE_FLAG: u8 = 0x40;
+const CMD_FLAGS: u8 = CMD_CHECKSUM_FLAG | CMD_MORE_FLAG;
 
+#[derive(Debug)]
 pub struct Frag {
     seq: Seq32,
     cmd: FragCommand,
+    checksum: bool,
+    more: bool,
 }
 
 pub struct FragBuilder {
     pub seq: Seq32,
     pub cmd: FragCommand,
+    /// Append a CRC-32 of the header and the body to a `Push`
+    pub checksum: bool,
+    /// More `Push`es of the same application message follow this one
+    pub more: bool,
 }
 
 impl FragBuilder {
     pub fn build(self) -> Result<Frag, Error> {
-        if let FragCommand::Push { body } = &self.cmd {
-            if body.is_empty() {
-                return Err(Error::EmptyBody);
+        match &self.cmd {
+            FragCommand::Push { body } => {
+                if body.is_empty() {
+                    return Err(Error::EmptyBody);
+                }
+                // `len` is encoded in 4 bytes
+                if body.len() > u32::MAX as usize {
+                    return Err(Error::BodyTooLong);
+                }
+            }
+            FragCommand::Ack
+            | FragCommand::Ping
+            | FragCommand::Pong
+            | FragCommand::Reset { .. }
+            | FragCommand::ResetAck => {
+                if self.checksum {
+                    return Err(Error::ChecksumWithoutBody);
+                }
+                if self.more {
+                    return Err(Error::MoreWithoutBody);
+                }
             }
         }
         let this = Frag {
             seq: self.seq,
             cmd: self.cmd,
+            checksum: self.checksum,
+            more: self.more,
         };
         this.check_rep();
         Ok(this)
     }
 }
 
+#[derive(Debug)]
 pub enum FragCommand {
-    Push { body: Body },
+    Push {
+        body: Body,
+    },
     Ack,
+    /// Probe the liveness of an idle peer, which answers with a `Pong` of the same seq
+    ///
+    /// The seq of a `Ping` or a `Pong` is an id outside the sequence space of pushes.
+    Ping,
+    Pong,
+    /// The peer restarted its sequence space from this seq
+    ///
+    /// The receiver discards whatever it holds and expects this seq next, unless it has already done so for the same `epoch`, so a replayed `Reset` is harmless.
+    Reset {
+        epoch: Seq32,
+    },
+    /// Acknowledge a `Reset`, the seq being its `epoch`
+    ResetAck,
 }
 
 pub enum Body {
@@ -46,6 +95,17 @@ pub enum Body {
     Pasta(Arc<BufPasta>),
 }
 
+/// Only the length, for the bytes can be many
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let variant = match self {
+            Body::Slice(_) => "Slice",
+            Body::Pasta(_) => "Pasta",
+        };
+        f.debug_struct(variant).field("len", &self.len()).finish()
+    }
+}
+
 impl Body {
     pub fn is_empty(&self) -> bool {
         match self {
@@ -60,90 +120,179 @@ impl Body {
             Body::Pasta(x) => x.len(),
         }
     }
+
+    pub fn crc32(&self) -> u32 {
+        let mut crc = Crc32::new();
+        self.update_crc32(&mut crc);
+        crc.finalize()
+    }
+
+    fn update_crc32(&self, crc: &mut Crc32) {
+        match self {
+            Body::Slice(x) => crc.update(x.data()),
+            Body::Pasta(x) => {
+                for slice in x.slices() {
+                    crc.update(slice.data());
+                }
+            }
+        }
+    }
 }
 
 impl Frag {
     fn check_rep(&self) {
-        if let FragCommand::Push { body } = &self.cmd {
-            assert!(!body.is_empty());
-        }
-    }
-
-    pub fn from_slice(slice: &mut BufSlice) -> Result<Self, DecodingError> {
-        let mut rdr = Cursor::new(slice.data());
-        let seq = rdr
-            .read_u32::<BigEndian>()
-            .map_err(|_e| DecodingError::Decoding { field: "seq" })?;
-        let seq = Seq32::from_u32(seq);
-        let cmd = rdr
-            .read_u8()
-            .map_err(|_e| DecodingError::Decoding { field: "cmd" })?;
-        let cmd =
-            CommandType::try_from(cmd).map_err(|_e| DecodingError::Decoding { field: "cmd" })?;
-        let cmd = match cmd {
-            CommandType::Push => {
-                let len = rdr
-                    .read_u32::<BigEndian>()
-                    .map_err(|_e| DecodingError::Decoding { field: "len" })?
-                    as usize;
-                if len == 0 {
-                    return Err(DecodingError::Decoding { field: "len" });
-                }
-                let rdr_len = rdr.position() as usize;
-                drop(rdr);
-                slice.pop_front(rdr_len).unwrap();
+        if !CHECK_REP {
+            return;
+        }
+        match &self.cmd {
+            FragCommand::Push { body } => assert!(!body.is_empty()),
+            FragCommand::Ack
+            | FragCommand::Ping
+            | FragCommand::Pong
+            | FragCommand::Reset { .. }
+            | FragCommand::ResetAck => assert!(!self.checksum && !self.more),
+        }
+    }
+
+    /// Fail with `DecodingError::Checksum` if a checksummed `Push` is corrupted, in its header or its body
+    ///
+    /// The slice is still advanced past the whole fragment so the following fragments can be decoded.
+    ///
+    /// Likewise, fail with `DecodingError::UnknownCommand` for a command this version does not know, skipped by its `len`.
+    ///
+    /// Offsets in the errors are relative to the start of the fragment.
+    ///
+    /// The body of a `Push` shares the buffer of an owned `slice` but is copied out of a borrowed one.
+    pub fn from_slice(slice: &mut impl BufRdr) -> Result<Self, DecodingError> {
+        let frag_len = slice.len();
+        let (seq, hdr) = slice.read_with(read_hdr)?;
+        let (cmd, checksum, more) = match hdr {
+            CommandHeader::Push { len, crc, more } => {
+                let available = slice.len();
                 let body = slice
                     .pop_front(len)
-                    .map_err(|_e| DecodingError::Decoding { field: "body" })?;
+                    .map_err(|_e| DecodingError::PushBodyTruncated {
+                        seq,
+                        declared_len: len,
+                        available,
+                    })?;
                 let body = Body::Slice(body);
-                FragCommand::Push { body }
+                if let Some(crc) = crc {
+                    let cmd = u8::from(CommandType::Push)
+                        | CMD_CHECKSUM_FLAG
+                        | if more { CMD_MORE_FLAG } else { 0 };
+                    if push_crc32(&push_hdr(seq, cmd, len as u32), &body) != crc {
+                        return Err(DecodingError::Checksum);
+                    }
+                }
+                (FragCommand::Push { body }, crc.is_some(), more)
             }
-            CommandType::Ack => {
-                let rdr_len = rdr.position() as usize;
-                slice.pop_front(rdr_len).unwrap();
-                FragCommand::Ack
+            CommandHeader::Unknown { cmd, len } => {
+                slice
+                    .pop_front(len)
+                    .map_err(|_e| DecodingError::FragHeaderInvalid { offset: 5 })?;
+                return Err(DecodingError::UnknownCommand { cmd });
             }
+            // nothing follows the header
+            CommandHeader::Ack => (FragCommand::Ack, false, false),
+            CommandHeader::Ping => (FragCommand::Ping, false, false),
+            CommandHeader::Pong => (FragCommand::Pong, false, false),
+            CommandHeader::Reset { epoch } => (FragCommand::Reset { epoch }, false, false),
+            CommandHeader::ResetAck => (FragCommand::ResetAck, false, false),
         };
+        if CHECK_REP {
+            match cmd {
+                FragCommand::Push { .. } => (),
+                FragCommand::Reset { .. } => assert_eq!(frag_len - slice.len(), RESET_HDR_LEN),
+                _ => assert_eq!(frag_len - slice.len(), ACK_HDR_LEN),
+            }
+        }
 
-        let this = Frag { seq, cmd };
+        let this = Frag {
+            seq,
+            cmd,
+            checksum,
+            more,
+        };
         this.check_rep();
         Ok(this)
     }
 
     pub fn append_to(&self, wtr: &mut impl BufWtr) -> Result<(), EncodingError> {
-        let mut hdr = Vec::new();
-        hdr.write_u32::<BigEndian>(self.seq.to_u32()).unwrap();
+        let (hdr, len) = self.encode_hdr();
+        wtr.append(&hdr[..len])
+            .map_err(|_| EncodingError::NotEnoughSpace)?;
+        if let FragCommand::Push { body } = &self.cmd {
+            match body {
+                Body::Slice(body) => wtr
+                    .append(body.data())
+                    .map_err(|_| EncodingError::NotEnoughSpace)?,
+                Body::Pasta(body) => body
+                    .append_to(wtr)
+                    .map_err(|_| EncodingError::NotEnoughSpace)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Append the bytes of `append_to` to `out` in place
+    pub fn to_bytes_into(&self, out: &mut Vec<u8>) {
+        let (hdr, len) = self.encode_hdr();
+        out.extend_from_slice(&hdr[..len]);
+        if let FragCommand::Push { body } = &self.cmd {
+            match body {
+                Body::Slice(body) => out.extend_from_slice(body.data()),
+                Body::Pasta(body) => {
+                    for slice in body.slices() {
+                        out.extend_from_slice(slice.data());
+                    }
+                }
+            }
+        }
+    }
+
+    /// The bytes of `append_to` in a buffer of their own
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.len());
+        self.to_bytes_into(&mut out);
+        out
+    }
+
+    /// Encode the header on the stack, returning the buffer and the length taken of it
+    fn encode_hdr(&self) -> ([u8; PUSH_HDR_LEN + CHECKSUM_LEN], usize) {
+        let mut hdr = [0; PUSH_HDR_LEN + CHECKSUM_LEN];
+        hdr[..4].copy_from_slice(&self.seq.to_u32().to_be_bytes());
         let cmd = match self.cmd {
             FragCommand::Push { body: _ } => CommandType::Push,
             FragCommand::Ack => CommandType::Ack,
+            FragCommand::Ping => CommandType::Ping,
+            FragCommand::Pong => CommandType::Pong,
+            FragCommand::Reset { .. } => CommandType::Reset,
+            FragCommand::ResetAck => CommandType::ResetAck,
         };
-        hdr.write_u8(cmd.into()).unwrap();
+        let mut cmd: u8 = cmd.into();
+        if self.checksum {
+            cmd |= CMD_CHECKSUM_FLAG;
+        }
+        if self.more {
+            cmd |= CMD_MORE_FLAG;
+        }
+        hdr[4] = cmd;
         match &self.cmd {
             FragCommand::Push { body } => {
-                hdr.write_u32::<BigEndian>(body.len() as u32).unwrap();
-                assert_eq!(hdr.len(), PUSH_HDR_LEN);
-                match body {
-                    Body::Slice(body) => {
-                        wtr.append(&hdr)
-                            .map_err(|_| EncodingError::NotEnoughSpace)?;
-                        wtr.append(body.data())
-                            .map_err(|_| EncodingError::NotEnoughSpace)?;
-                    }
-                    Body::Pasta(body) => {
-                        wtr.append(&hdr)
-                            .map_err(|_| EncodingError::NotEnoughSpace)?;
-                        body.append_to(wtr)
-                            .map_err(|_| EncodingError::NotEnoughSpace)?;
-                    }
+                hdr[..PUSH_HDR_LEN].copy_from_slice(&push_hdr(self.seq, cmd, body.len() as u32));
+                if self.checksum {
+                    let crc = push_crc32(&hdr[..PUSH_HDR_LEN], body);
+                    hdr[PUSH_HDR_LEN..].copy_from_slice(&crc.to_be_bytes());
                 }
             }
-            FragCommand::Ack => {
-                assert_eq!(hdr.len(), ACK_HDR_LEN);
-                wtr.append(&hdr)
-                    .map_err(|_| EncodingError::NotEnoughSpace)?;
+            FragCommand::Reset { epoch } => {
+                hdr[5..RESET_HDR_LEN].copy_from_slice(&epoch.to_u32().to_be_bytes());
             }
+            FragCommand::Ack | FragCommand::Ping | FragCommand::Pong | FragCommand::ResetAck => (),
         }
-        Ok(())
+        (hdr, self.hdr_len())
     }
 
     #[must_use]
@@ -151,6 +300,8 @@ impl Frag {
         FragBuilder {
             seq: self.seq,
             cmd: self.cmd,
+            checksum: self.checksum,
+            more: self.more,
         }
     }
 
@@ -166,31 +317,190 @@ impl Frag {
         self.seq
     }
 
+    #[must_use]
+    #[inline]
+    pub fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn more(&self) -> bool {
+        self.more
+    }
+
+    /// The body length encoded in a `Push`
+    ///
+    /// Unit: byte
+    #[must_use]
+    #[inline]
+    pub fn body_len(&self) -> Option<usize> {
+        match &self.cmd {
+            FragCommand::Push { body } => Some(body.len()),
+            FragCommand::Ack
+            | FragCommand::Ping
+            | FragCommand::Pong
+            | FragCommand::Reset { .. }
+            | FragCommand::ResetAck => None,
+        }
+    }
+
+    /// Bytes of the header alone, the checksum of a `Push` included
+    #[must_use]
+    pub fn hdr_len(&self) -> usize {
+        match &self.cmd {
+            FragCommand::Push { .. } => match self.checksum {
+                true => PUSH_HDR_LEN + CHECKSUM_LEN,
+                false => PUSH_HDR_LEN,
+            },
+            FragCommand::Reset { .. } => RESET_HDR_LEN,
+            FragCommand::Ack | FragCommand::Ping | FragCommand::Pong | FragCommand::ResetAck => {
+                ACK_HDR_LEN
+            }
+        }
+    }
+
+    /// Exactly the bytes `append_to` appends, so a buffer can be sized without encoding
     #[must_use]
     pub fn len(&self) -> usize {
         match &self.cmd {
-            FragCommand::Push { body } => PUSH_HDR_LEN + body.len(),
-            FragCommand::Ack => ACK_HDR_LEN,
+            FragCommand::Push { body } => match self.checksum {
+                true => PUSH_HDR_LEN + CHECKSUM_LEN + body.len(),
+                false => PUSH_HDR_LEN + body.len(),
+            },
+            FragCommand::Reset { .. } => RESET_HDR_LEN,
+            FragCommand::Ack | FragCommand::Ping | FragCommand::Pong | FragCommand::ResetAck => {
+                ACK_HDR_LEN
+            }
         }
     }
 }
 
+/// The header of a `Push` up to its `crc`
+fn push_hdr(seq: Seq32, cmd: u8, len: u32) -> [u8; PUSH_HDR_LEN] {
+    let mut hdr = [0; PUSH_HDR_LEN];
+    hdr[..4].copy_from_slice(&seq.to_u32().to_be_bytes());
+    hdr[4] = cmd;
+    hdr[5..].copy_from_slice(&len.to_be_bytes());
+    hdr
+}
+
+/// The CRC-32 of a `Push`, covering `hdr` up to the `crc` and then the body
+fn push_crc32(hdr: &[u8], body: &Body) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(hdr);
+    body.update_crc32(&mut crc);
+    crc.finalize()
+}
+
+enum CommandHeader {
+    Push {
+        len: usize,
+        crc: Option<u32>,
+        more: bool,
+    },
+    Ack,
+    Ping,
+    Pong,
+    Reset {
+        epoch: Seq32,
+    },
+    ResetAck,
+    /// Unit of `len`: byte
+    Unknown {
+        cmd: u8,
+        len: usize,
+    },
+}
+
+fn read_hdr(rdr: &mut Cursor<'_>) -> Result<(Seq32, CommandHeader), DecodingError> {
+    let seq = rdr
+        .read_u32::<BigEndian>()
+        .map_err(|_e| DecodingError::FragHeaderInvalid { offset: 0 })?;
+    let seq = Seq32::from_u32(seq);
+    let cmd = rdr
+        .read_u8()
+        .map_err(|_e| DecodingError::FragHeaderInvalid { offset: 4 })?;
+    let checksum = cmd & CMD_CHECKSUM_FLAG != 0;
+    let more = cmd & CMD_MORE_FLAG != 0;
+    let cmd = match CommandType::try_from(cmd & !CMD_FLAGS) {
+        Ok(x) => x,
+        // left for a later version to define, with a body of `len` bytes like a push
+        Err(_) => {
+            let len = rdr
+                .read_u32::<BigEndian>()
+                .map_err(|_e| DecodingError::FragHeaderInvalid { offset: 5 })?
+                as usize;
+            return Ok((
+                seq,
+                CommandHeader::Unknown {
+                    cmd: cmd & !CMD_FLAGS,
+                    len,
+                },
+            ));
+        }
+    };
+    // only a push has a body to checksum or to continue
+    if !matches!(cmd, CommandType::Push) && (checksum || more) {
+        return Err(DecodingError::FragHeaderInvalid { offset: 4 });
+    }
+    let hdr = match cmd {
+        CommandType::Push => {
+            let len = rdr
+                .read_u32::<BigEndian>()
+                .map_err(|_e| DecodingError::FragHeaderInvalid { offset: 5 })?
+                as usize;
+            if len == 0 {
+                return Err(DecodingError::FragHeaderInvalid { offset: 5 });
+            }
+            let crc = match checksum {
+                true => Some(
+                    rdr.read_u32::<BigEndian>()
+                        .map_err(|_e| DecodingError::FragHeaderInvalid { offset: 9 })?,
+                ),
+                false => None,
+            };
+            CommandHeader::Push { len, crc, more }
+        }
+        CommandType::Ack => CommandHeader::Ack,
+        CommandType::Ping => CommandHeader::Ping,
+        CommandType::Pong => CommandHeader::Pong,
+        CommandType::Reset => {
+            let epoch = rdr
+                .read_u32::<BigEndian>()
+                .map_err(|_e| DecodingError::FragHeaderInvalid { offset: 5 })?;
+            CommandHeader::Reset {
+                epoch: Seq32::from_u32(epoch),
+            }
+        }
+        CommandType::ResetAck => CommandHeader::ResetAck,
+    };
+    Ok((seq, hdr))
+}
+
 #[derive(IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum CommandType {
     Push,
     Ack,
+    Ping,
+    Pong,
+    Reset,
+    ResetAck,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Error {
     EmptyBody,
+    BodyTooLong,
+    ChecksumWithoutBody,
+    MoreWithoutBody,
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::utils::buf::OwnedBufWtr;
+    use crate::utils::{buf::OwnedBufWtr, crc32};
 
     use super::*;
 
@@ -201,6 +511,8 @@ mod tests {
             cmd: FragCommand::Push {
                 body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2, 3, 4])),
             },
+            checksum: false,
+            more: false,
         }
         .build()
         .unwrap();
@@ -238,6 +550,8 @@ mod tests {
             cmd: FragCommand::Push {
                 body: Body::Pasta(Arc::new(pasta)),
             },
+            checksum: false,
+            more: false,
         }
         .build()
         .unwrap();
@@ -274,6 +588,8 @@ mod tests {
         let frag1 = FragBuilder {
             seq: Seq32::from_u32(345),
             cmd: FragCommand::Ack,
+            checksum: false,
+            more: false,
         }
         .build()
         .unwrap();
@@ -290,4 +606,339 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn test_ack_hdr_len() {
+        // no room for the cmd
+        let mut slice = BufSlice::from_bytes(vec![0, 0, 1, 89]);
+        assert_eq!(
+            Frag::from_slice(&mut slice).unwrap_err(),
+            DecodingError::FragHeaderInvalid { offset: 4 }
+        );
+
+        // the bytes after the header are left for the next fragment
+        let mut slice = BufSlice::from_bytes(vec![0, 0, 1, 89, CommandType::Ack.into(), 0, 0]);
+        let frag = Frag::from_slice(&mut slice).unwrap();
+        assert!(matches!(frag.cmd(), FragCommand::Ack));
+        assert_eq!(slice.len(), 2);
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        // [seq(345)] [cmd(9)] [len(2)] [2] [seq(1)]
+        let mut slice = BufSlice::from_bytes(vec![0, 0, 1, 89, 9, 0, 0, 0, 2, 7, 7, 0, 0, 0, 1]);
+        assert_eq!(
+            Frag::from_slice(&mut slice).unwrap_err(),
+            DecodingError::UnknownCommand { cmd: 9 }
+        );
+        assert_eq!(slice.data(), [0, 0, 0, 1]);
+
+        // the body runs past the end
+        let mut slice = BufSlice::from_bytes(vec![0, 0, 1, 89, 9, 0, 0, 0, 2, 7]);
+        assert_eq!(
+            Frag::from_slice(&mut slice).unwrap_err(),
+            DecodingError::FragHeaderInvalid { offset: 5 }
+        );
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        for cmd in [FragCommand::Ping, FragCommand::Pong] {
+            let frag1 = FragBuilder {
+                seq: Seq32::from_u32(7),
+                cmd,
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap();
+            let mut wtr = OwnedBufWtr::new(1024, 512);
+            frag1.append_to(&mut wtr).unwrap();
+            assert_eq!(wtr.data_len(), ACK_HDR_LEN);
+            let frag2 = Frag::from_slice(&mut wtr.into_slice()).unwrap();
+            assert_eq!(frag2.seq(), Seq32::from_u32(7));
+            match (frag1.cmd, frag2.cmd) {
+                (FragCommand::Ping, FragCommand::Ping) | (FragCommand::Pong, FragCommand::Pong) => {
+                    ()
+                }
+                _ => panic!(),
+            }
+        }
+
+        assert!(FragBuilder {
+            seq: Seq32::from_u32(7),
+            cmd: FragCommand::Ping,
+            checksum: true,
+            more: false,
+        }
+        .build()
+        .is_err());
+        let ping_with_checksum = vec![0, 0, 0, 7, u8::from(CommandType::Ping) | CMD_CHECKSUM_FLAG];
+        assert!(matches!(
+            Frag::from_slice(&mut BufSlice::from_bytes(ping_with_checksum)),
+            Err(DecodingError::FragHeaderInvalid { offset: 4 })
+        ));
+    }
+
+    #[test]
+    fn test_reset() {
+        let frag1 = FragBuilder {
+            seq: Seq32::from_u32(5),
+            cmd: FragCommand::Reset {
+                epoch: Seq32::from_u32(9),
+            },
+            checksum: false,
+            more: false,
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        frag1.append_to(&mut wtr).unwrap();
+        assert_eq!(frag1.len(), RESET_HDR_LEN);
+        assert_eq!(
+            wtr.data(),
+            vec![0, 0, 0, 5, u8::from(CommandType::Reset), 0, 0, 0, 9]
+        );
+        let frag2 = Frag::from_slice(&mut wtr.into_slice()).unwrap();
+        assert_eq!(frag2.seq(), Seq32::from_u32(5));
+        assert!(matches!(
+            frag2.cmd(),
+            FragCommand::Reset { epoch } if *epoch == Seq32::from_u32(9)
+        ));
+
+        let ack = FragBuilder {
+            seq: Seq32::from_u32(9),
+            cmd: FragCommand::ResetAck,
+            checksum: false,
+            more: false,
+        }
+        .build()
+        .unwrap();
+        assert_eq!(
+            ack.to_bytes(),
+            vec![0, 0, 0, 9, u8::from(CommandType::ResetAck)]
+        );
+    }
+
+    #[test]
+    fn test_wire_len() {
+        let push = FragBuilder {
+            seq: Seq32::from_u32(3),
+            cmd: FragCommand::Push {
+                body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2])),
+            },
+            checksum: true,
+            more: true,
+        }
+        .build()
+        .unwrap();
+        let ack = FragBuilder {
+            seq: Seq32::from_u32(3),
+            cmd: FragCommand::Ack,
+            checksum: false,
+            more: false,
+        }
+        .build()
+        .unwrap();
+        for frag in [push, ack] {
+            let mut wtr = OwnedBufWtr::new(1024, 0);
+            frag.append_to(&mut wtr).unwrap();
+            assert_eq!(frag.len(), wtr.data_len());
+            assert_eq!(frag.hdr_len() + frag.body_len().unwrap_or(0), frag.len());
+            let decoded = Frag::from_slice(&mut wtr.into_slice()).unwrap();
+            assert_eq!(decoded.len(), frag.len());
+            assert_eq!(decoded.body_len(), frag.body_len());
+        }
+    }
+
+    #[test]
+    fn test_debug() {
+        let frag = FragBuilder {
+            seq: Seq32::from_u32(3),
+            cmd: FragCommand::Push {
+                body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2])),
+            },
+            checksum: false,
+            more: true,
+        }
+        .build()
+        .unwrap();
+        assert_eq!(
+            format!("{:?}", frag),
+            "Frag { seq: Seq32 { n: 3 }, cmd: Push { body: Slice { len: 3 } }, checksum: false, more: true }"
+        );
+    }
+
+    #[test]
+    fn test_push_pasta_checksum() {
+        let mut pasta = BufPasta::new();
+        pasta.append(BufSlice::from_bytes(vec![0, 1, 2, 3, 4]));
+        pasta.append(BufSlice::from_bytes(vec![5, 6]));
+        let frag1 = FragBuilder {
+            seq: Seq32::from_u32(345),
+            cmd: FragCommand::Push {
+                body: Body::Pasta(Arc::new(pasta)),
+            },
+            checksum: true,
+            more: false,
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        frag1.append_to(&mut wtr).unwrap();
+        assert_eq!(frag1.len(), wtr.data_len());
+        let frag2 = Frag::from_slice(&mut wtr.into_slice()).unwrap();
+        assert!(frag2.checksum());
+        match frag2.cmd {
+            FragCommand::Push { body } => assert_eq!(body.crc32(), crc32(&[0, 1, 2, 3, 4, 5, 6])),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_push_checksum_covers_hdr() {
+        let frag = FragBuilder {
+            seq: Seq32::from_u32(345),
+            cmd: FragCommand::Push {
+                body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2])),
+            },
+            checksum: true,
+            more: false,
+        }
+        .build()
+        .unwrap();
+        let bytes = frag.to_bytes();
+        assert!(Frag::from_slice(&mut BufSlice::from_bytes(bytes.clone())).is_ok());
+
+        // a bit flipped in the seq, in the more flag and in the body
+        for (i, bit) in [
+            (3, 0x01),
+            (4, CMD_MORE_FLAG),
+            (PUSH_HDR_LEN + CHECKSUM_LEN, 0x01),
+        ] {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= bit;
+            assert_eq!(
+                Frag::from_slice(&mut BufSlice::from_bytes(corrupted)).unwrap_err(),
+                DecodingError::Checksum
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_bytes_into() {
+        let mut pasta = BufPasta::new();
+        pasta.append(BufSlice::from_bytes(vec![0, 1, 2]));
+        pasta.append(BufSlice::from_bytes(vec![3, 4]));
+        let frags = [
+            FragBuilder {
+                seq: Seq32::from_u32(345),
+                cmd: FragCommand::Push {
+                    body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2, 3, 4])),
+                },
+                checksum: false,
+                more: true,
+            },
+            FragBuilder {
+                seq: Seq32::from_u32(346),
+                cmd: FragCommand::Push {
+                    body: Body::Pasta(Arc::new(pasta)),
+                },
+                checksum: true,
+                more: false,
+            },
+            FragBuilder {
+                seq: Seq32::from_u32(347),
+                cmd: FragCommand::Ack,
+                checksum: false,
+                more: false,
+            },
+        ];
+        for frag in frags {
+            let frag = frag.build().unwrap();
+            let mut wtr = OwnedBufWtr::new(1024, 512);
+            frag.append_to(&mut wtr).unwrap();
+            assert_eq!(frag.to_bytes(), wtr.data());
+            assert_eq!(frag.to_bytes().len(), frag.len());
+
+            // appended after what is already there
+            let mut out = vec![0xff; 3];
+            frag.to_bytes_into(&mut out);
+            assert_eq!(out[..3], [0xff; 3]);
+            assert_eq!(out[3..], frag.to_bytes());
+        }
+    }
+
+    #[test]
+    fn test_push_more() {
+        let frag1 = FragBuilder {
+            seq: Seq32::from_u32(345),
+            cmd: FragCommand::Push {
+                body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2])),
+            },
+            checksum: true,
+            more: true,
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        frag1.append_to(&mut wtr).unwrap();
+        let frag2 = Frag::from_slice(&mut wtr.into_slice()).unwrap();
+        assert!(frag2.more());
+        assert!(frag2.checksum());
+
+        assert!(matches!(
+            FragBuilder {
+                seq: Seq32::from_u32(345),
+                cmd: FragCommand::Ack,
+                checksum: false,
+                more: true,
+            }
+            .build(),
+            Err(Error::MoreWithoutBody)
+        ));
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let build = |cmd, checksum| {
+            FragBuilder {
+                seq: Seq32::from_u32(0),
+                cmd,
+                checksum,
+                more: false,
+            }
+            .build()
+            .err()
+        };
+        assert_eq!(
+            build(
+                FragCommand::Push {
+                    body: Body::Slice(BufSlice::from_bytes(vec![])),
+                },
+                false
+            ),
+            Some(Error::EmptyBody)
+        );
+        assert_eq!(
+            build(
+                FragCommand::Push {
+                    body: Body::Pasta(Arc::new(BufPasta::new())),
+                },
+                true
+            ),
+            Some(Error::EmptyBody)
+        );
+        for cmd in [
+            FragCommand::Ack,
+            FragCommand::Ping,
+            FragCommand::Pong,
+            FragCommand::Reset {
+                epoch: Seq32::from_u32(0),
+            },
+            FragCommand::ResetAck,
+        ] {
+            assert_eq!(build(cmd, true), Some(Error::ChecksumWithoutBody));
+        }
+    }
 }
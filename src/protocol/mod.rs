@@ -1,10 +1,12 @@
 //! # Packet header
 //!
 //! ```text
-//! 0       2               6         (BYTE)
-//! +-------+---------------+
-//! | rwnd  |     nack      |
-//! +-------+---------------+
+//! 0   1       3   4               8               12 (BYTE)
+//! +---+-------+---+---------------+---------------+
+//! |ver| rwnd  |sft|     nack      |      crc      |
+//! +---+-------+---+---------------+---------------+
+//! |    ts_val     |    ts_ecr     |  len  |
+//! +---------------+---------------+-------+
 //! ```
 //!
 //! # Fragment
@@ -15,6 +17,8 @@
 //! |      seq      |cmd|
 //! +---------------+---+
 //! |  len (Push)   |
+//! +---------------+
+//! |  crc (Push)   |
 //! +---------------+---------------+
 //! |                               |
 //! |          Body (Push)          |
@@ -28,24 +32,81 @@
 //! (Packet header)
 //! (Fragment header of type Ack)*
 //! (Fragment header of type Ack)*
+//! (Fragment header of type Ping or Pong)*
+//! (Fragment header of type ResetAck)?
+//! (Fragment header of type Reset)?
 //! ((Fragment header of type Push) (Body))*
 //! ((Fragment header of type Push) (Body))*
 //! ```
 //!
 //! # Invariants
 //!
+//! - `ver` should be `CURRENT_VERSION`
+//! - The advertised rwnd is `rwnd << (sft & 0x0f)`
+//! - `sft & 0x0f` should not exceed `14`
+//! - The second highest bit of `sft` echoes a congestion-experienced mark
+//! - `crc` is present only if the highest bit of `sft` is set
+//!   - It is the CRC-32 of the whole packet with `crc` zeroed
+//! - `ts_val` and `ts_ecr` are present only if the third highest bit of `sft` is set
+//!   - They follow `crc` if any, or else `nack`
+//!   - `ts_ecr` echoes the latest `ts_val` from the peer
+//! - `len` is present only if the fourth highest bit of `sft` is set
+//!   - It follows whichever of `nack`, `crc` and `ts_ecr` comes last
+//!   - It is the length of the whole packet, so that packets can be concatenated in one buffer
 //! - `len` (`Push`) should not be `0`
+//! - `crc` (`Push`) is present only if the highest bit of `cmd` is set
+//!   - It is the CRC-32 of `seq`, `cmd` and `len` followed by the body
+//! - The second highest bit of `cmd` (`Push`) is set if more pushes of the same message follow
+//!   - A message ends at the first push in order with the bit cleared
+//! - `seq` of a `Ping` or a `Pong` is an id, not a push sequence
+//! - `seq` of a `Reset` is the next push sequence after the restart
+//!   - Its `epoch` takes the place of `len` (`Push`)
+//!   - A `Reset` whose `epoch` is not after that of the last one taken is a replay or stale and is only acknowledged
+//! - `seq` of a `ResetAck` is the `epoch` of the `Reset` it acknowledges
+//! - A fragment of any other `cmd` carries `len` and a body like a `Push`, so an older receiver can skip it
 
 pub mod frag;
 pub mod packet;
 pub mod packet_hdr;
 
-#[derive(Debug)]
+use crate::utils::Seq32;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum DecodingError {
-    Decoding { field: &'static str },
+    PacketHeaderTooShort,
+    PacketHeaderInvalid,
+    /// The peer speaks another version of the protocol
+    VersionMismatch {
+        version: u8,
+    },
+    PacketChecksum,
+    /// A delimited packet declares a length other than that of its datagram
+    ///
+    /// Unit of `declared_len` and `available`: byte
+    PacketLenMismatch {
+        declared_len: usize,
+        available: usize,
+    },
+    /// Unit of `offset`: byte
+    FragHeaderInvalid {
+        offset: usize,
+    },
+    /// Unit of `declared_len` and `available`: byte
+    PushBodyTruncated {
+        seq: Seq32,
+        declared_len: usize,
+        available: usize,
+    },
+    Checksum,
+    /// A fragment of a command this version does not know, skipped whole
+    UnknownCommand {
+        cmd: u8,
+    },
 }
 
 #[derive(Debug)]
 pub enum EncodingError {
     NotEnoughSpace,
+    /// A delimited packet is longer than its length field can tell
+    PacketTooLong,
 }
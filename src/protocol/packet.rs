@@ -1,9 +1,33 @@
-use super::{frag::Frag, packet_hdr::PacketHeader, DecodingError, EncodingError};
-use crate::utils::buf::{BufSlice, BufWtr};
+use super::{
+    frag::{Body, Frag, FragBuilder, FragCommand, CHECKSUM_LEN, PUSH_HDR_LEN},
+    packet_hdr::{PacketHeader, PACKET_HDR_LEN, PACKET_LEN_LEN},
+    DecodingError, EncodingError,
+};
+use crate::utils::{
+    buf::{BufRdr, BufSlice, BufWtr},
+    crc32, Seq32,
+};
+use alloc::vec::Vec;
 
+#[derive(Debug)]
 pub struct Packet {
     hdr: PacketHeader,
     frags: Vec<Frag>,
+    corrupted_frags: usize,
+    unknown_frags: usize,
+    truncated_push: Option<TruncatedPush>,
+}
+
+/// A push whose body runs past the end of the datagram
+///
+/// Unit of `offset`, `declared_len` and `available`: byte
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruncatedPush {
+    pub seq: Seq32,
+    /// Where the push starts, from the start of the packet
+    pub offset: usize,
+    pub declared_len: usize,
+    pub available: usize,
 }
 
 pub struct PacketBuilder {
@@ -16,6 +40,9 @@ impl PacketBuilder {
         let this = Packet {
             hdr: self.hdr,
             frags: self.frags,
+            corrupted_frags: 0,
+            unknown_frags: 0,
+            truncated_push: None,
         };
         this.check_rep();
         Ok(this)
@@ -25,24 +52,95 @@ impl PacketBuilder {
 impl Packet {
     fn check_rep(&self) {}
 
-    pub fn from_slice(slice: &mut BufSlice) -> Result<Self, DecodingError> {
-        let hdr = PacketHeader::from_slice(slice)?;
+    pub fn from_slice(slice: &mut impl BufRdr) -> Result<Self, DecodingError> {
+        Self::from_slice_up_to(slice, usize::MAX)
+    }
+
+    /// Decode at most `max_frags` fragments, corrupted and unknown ones included
+    ///
+    /// The fragments beyond are left undecoded in `slice`.
+    ///
+    /// A push clipped by the end of `slice` is dropped with the fragments before it kept; see `truncated_push`.
+    pub fn from_slice_up_to(
+        slice: &mut impl BufRdr,
+        max_frags: usize,
+    ) -> Result<Self, DecodingError> {
+        Self::from_slice_with(slice, max_frags, false)
+    }
+
+    /// `from_slice_up_to` with the header decoded as by `PacketHeader::from_slice_with`
+    pub fn from_slice_with(
+        slice: &mut impl BufRdr,
+        max_frags: usize,
+        legacy_mode: bool,
+    ) -> Result<Self, DecodingError> {
+        let packet_len = slice.len();
+        let hdr = PacketHeader::from_slice_with(slice, legacy_mode)?;
         let mut frags = Vec::new();
-        while !slice.is_empty() {
-            let frag = Frag::from_slice(slice)?;
-            frags.push(frag);
+        let mut corrupted_frags = 0;
+        let mut unknown_frags = 0;
+        let mut truncated_push = None;
+        while !slice.is_empty() && frags.len() + corrupted_frags + unknown_frags < max_frags {
+            let frag_offset = packet_len - slice.len();
+            match Frag::from_slice(slice) {
+                Ok(frag) => frags.push(frag),
+                // the other fragments are still intact
+                Err(DecodingError::Checksum) => corrupted_frags += 1,
+                Err(DecodingError::UnknownCommand { .. }) => unknown_frags += 1,
+                // nothing follows the end of the datagram
+                Err(DecodingError::PushBodyTruncated {
+                    seq,
+                    declared_len,
+                    available,
+                }) => {
+                    truncated_push = Some(TruncatedPush {
+                        seq,
+                        offset: frag_offset,
+                        declared_len,
+                        available,
+                    });
+                    break;
+                }
+                // offset from the start of the packet
+                Err(DecodingError::FragHeaderInvalid { offset }) => {
+                    return Err(DecodingError::FragHeaderInvalid {
+                        offset: frag_offset + offset,
+                    })
+                }
+                Err(e) => return Err(e),
+            }
         }
 
-        let this = Packet { hdr, frags };
+        let this = Packet {
+            hdr,
+            frags,
+            corrupted_frags,
+            unknown_frags,
+            truncated_push,
+        };
         this.check_rep();
         Ok(this)
     }
 
     pub fn append_to(&self, wtr: &mut impl BufWtr) -> Result<(), EncodingError> {
+        let start = wtr.data_len();
         self.hdr.append_to(wtr)?;
         for frag in &self.frags {
             frag.append_to(wtr)?;
         }
+        if self.hdr.delimited() {
+            let len =
+                u16::try_from(wtr.data_len() - start).map_err(|_| EncodingError::PacketTooLong)?;
+            let hdr_len = self.hdr.hdr_len();
+            wtr.data_mut()[start + hdr_len - PACKET_LEN_LEN..start + hdr_len]
+                .copy_from_slice(&len.to_be_bytes());
+        }
+        if self.hdr.checksum() {
+            let packet = &mut wtr.data_mut()[start..];
+            let crc = crc32(packet);
+            packet[PACKET_HDR_LEN..PACKET_HDR_LEN + CHECKSUM_LEN]
+                .copy_from_slice(&crc.to_be_bytes());
+        }
         Ok(())
     }
 
@@ -63,32 +161,151 @@ impl Packet {
     pub fn frags(&self) -> &Vec<Frag> {
         &self.frags
     }
+
+    /// Number of checksummed fragments dropped during decoding
+    #[must_use]
+    pub fn corrupted_frags(&self) -> usize {
+        self.corrupted_frags
+    }
+
+    /// Number of fragments of unknown commands skipped during decoding
+    #[must_use]
+    pub fn unknown_frags(&self) -> usize {
+        self.unknown_frags
+    }
+
+    /// The last push, dropped for its body running past the end of the datagram
+    #[must_use]
+    pub fn truncated_push(&self) -> Option<&TruncatedPush> {
+        self.truncated_push.as_ref()
+    }
 }
 
-#[derive(Debug)]
-pub enum Error {}
+/// Cut `data` into `Push`es each fitting in a packet of `mtu` bytes, with a header neither checksummed, timestamped nor delimited
+///
+/// The pushes form one message, seqs counting up from `starting_seq`. The seq after the last push is returned with them.
+pub fn fragment(data: &[u8], starting_seq: Seq32, mtu: usize) -> Result<(Vec<Frag>, Seq32), Error> {
+    let max_body_len = mtu.saturating_sub(PACKET_HDR_LEN + PUSH_HDR_LEN);
+    if max_body_len == 0 {
+        return Err(Error::MtuTooSmall);
+    }
+    let mut frags = Vec::with_capacity(data.len().div_ceil(max_body_len));
+    let mut seq = starting_seq;
+    let mut chunks = data.chunks(max_body_len).peekable();
+    while let Some(chunk) = chunks.next() {
+        let frag = FragBuilder {
+            seq,
+            cmd: FragCommand::Push {
+                body: Body::Slice(BufSlice::from_bytes(chunk.to_vec())),
+            },
+            checksum: false,
+            more: chunks.peek().is_some(),
+        }
+        .build()
+        .unwrap();
+        frags.push(frag);
+        seq.increment();
+    }
+    Ok((frags, seq))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Not even a one-byte push fits
+    MtuTooSmall,
+}
 
 #[cfg(test)]
 mod tests {
 
     use crate::{
         protocol::{
-            frag::{Body, FragBuilder, FragCommand},
-            packet_hdr::PacketHeaderBuilder,
+            frag::{Body, FragBuilder, FragCommand, PUSH_HDR_LEN},
+            packet_hdr::{PacketHeaderBuilder, CURRENT_VERSION, PACKET_HDR_LEN},
         },
         utils::{
-            buf::{BufSlice, OwnedBufWtr},
-            Seq32,
+            buf::{BorrowedBufSlice, BufSlice, BufWtr, OwnedBufWtr},
+            RecvBuf, Seq32, SeqLocationToRwnd,
         },
     };
 
-    use super::{Packet, PacketBuilder};
+    use super::{fragment, Error, Packet, PacketBuilder};
+    use crate::protocol::DecodingError;
+
+    /// The push of `layer::tests::test_few_1`, decoded with only what builds without `std`
+    #[test]
+    fn test_few_1_without_std() {
+        let datagram = [
+            2, // ver
+            0, 2, // rwnd
+            0, // rwnd shift
+            0, 0, 0, 0, // nack
+            0, 0, 0, 0, // seq
+            0, // cmd (Push)
+            0, 0, 0, 3, // len
+            0, 1, 2, // data
+        ];
+        let packet = Packet::from_slice(&mut BorrowedBufSlice::from_slice(&datagram)).unwrap();
+        assert_eq!(packet.hdr().scaled_rwnd(), 2);
+        assert_eq!(packet.frags().len(), 1);
+
+        let mut recv_buf = RecvBuf::new(2);
+        let frag = packet.into_builder().frags.pop().unwrap();
+        let body = match frag.into_builder().cmd {
+            FragCommand::Push {
+                body: Body::Slice(body),
+            } => body,
+            _ => panic!(),
+        };
+        match recv_buf.insert(Seq32::from_u32(0), body) {
+            SeqLocationToRwnd::AtRecvWindowStart => (),
+            _ => panic!(),
+        }
+        assert_eq!(recv_buf.pop_front().unwrap().data(), vec![0, 1, 2]);
+
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 2,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(0),
+            }
+            .build()
+            .unwrap(),
+            frags: vec![FragBuilder {
+                seq: Seq32::from_u32(0),
+                cmd: FragCommand::Push {
+                    body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2])),
+                },
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap()],
+        }
+        .build()
+        .unwrap()
+        .append_to(&mut wtr)
+        .unwrap();
+        assert_eq!(wtr.data(), datagram);
+    }
 
     #[test]
     fn test1() {
         let packet1 = PacketBuilder {
             hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
                 rwnd: 123,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
                 nack: Seq32::from_u32(456),
             }
             .build()
@@ -97,6 +314,8 @@ mod tests {
                 FragBuilder {
                     seq: Seq32::from_u32(345),
                     cmd: FragCommand::Ack,
+                    checksum: false,
+                    more: false,
                 }
                 .build()
                 .unwrap(),
@@ -105,6 +324,8 @@ mod tests {
                     cmd: FragCommand::Push {
                         body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2, 3, 4])),
                     },
+                    checksum: false,
+                    more: false,
                 }
                 .build()
                 .unwrap(),
@@ -121,4 +342,158 @@ mod tests {
         assert_eq!(packet1.frags[0].seq(), packet2.frags[0].seq());
         assert_eq!(packet1.frags[1].seq(), packet2.frags[1].seq());
     }
+
+    #[test]
+    fn test_corrupted_frag() {
+        let packet1 = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 123,
+                rwnd_shift: 0,
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(456),
+            }
+            .build()
+            .unwrap(),
+            frags: vec![
+                FragBuilder {
+                    seq: Seq32::from_u32(345),
+                    cmd: FragCommand::Push {
+                        body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2])),
+                    },
+                    checksum: true,
+                    more: false,
+                }
+                .build()
+                .unwrap(),
+                FragBuilder {
+                    seq: Seq32::from_u32(346),
+                    cmd: FragCommand::Push {
+                        body: Body::Slice(BufSlice::from_bytes(vec![3, 4, 5])),
+                    },
+                    checksum: true,
+                    more: false,
+                }
+                .build()
+                .unwrap(),
+            ],
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        packet1.append_to(&mut wtr).unwrap();
+        let mut data = wtr.data().to_vec();
+        // flip a bit in the body of the first fragment
+        data[PACKET_HDR_LEN + 13] ^= 1;
+        let packet2 = Packet::from_slice(&mut BufSlice::from_bytes(data)).unwrap();
+        assert_eq!(packet2.corrupted_frags(), 1);
+        assert_eq!(packet2.frags.len(), 1);
+        assert_eq!(packet2.frags[0].seq(), Seq32::from_u32(346));
+    }
+
+    #[test]
+    fn test_packet_checksum() {
+        let packet1 = PacketBuilder {
+            hdr: PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 123,
+                rwnd_shift: 0,
+                checksum: true,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+                nack: Seq32::from_u32(456),
+            }
+            .build()
+            .unwrap(),
+            frags: vec![FragBuilder {
+                seq: Seq32::from_u32(345),
+                cmd: FragCommand::Push {
+                    body: Body::Slice(BufSlice::from_bytes(vec![0, 1, 2])),
+                },
+                checksum: false,
+                more: false,
+            }
+            .build()
+            .unwrap()],
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        packet1.append_to(&mut wtr).unwrap();
+        assert_eq!(wtr.data_len(), PACKET_HDR_LEN + 4 + PUSH_HDR_LEN + 3);
+        let data = wtr.data().to_vec();
+
+        let packet2 = Packet::from_slice(&mut BufSlice::from_bytes(data.clone())).unwrap();
+        assert!(packet2.hdr().checksum());
+        assert_eq!(packet2.hdr().nack(), Seq32::from_u32(456));
+        assert_eq!(packet2.frags().len(), 1);
+
+        // any flipped bit in the header or the fragments is caught
+        for i in 0..data.len() {
+            let mut data = data.clone();
+            data[i] ^= 0x10;
+            assert!(Packet::from_slice(&mut BufSlice::from_bytes(data)).is_err());
+        }
+        let mut corrupted = data.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            Packet::from_slice(&mut BufSlice::from_bytes(corrupted)),
+            Err(DecodingError::PacketChecksum)
+        ));
+    }
+
+    #[test]
+    fn test_fragment() {
+        let mtu = PACKET_HDR_LEN + PUSH_HDR_LEN + 4;
+        let data: Vec<u8> = (0..9).collect();
+
+        // exact fit
+        let (frags, next) = fragment(&data[..4], Seq32::from_u32(7), mtu).unwrap();
+        assert_eq!(frags.len(), 1);
+        assert_eq!(frags[0].seq(), Seq32::from_u32(7));
+        assert!(!frags[0].more());
+        assert_eq!(PACKET_HDR_LEN + frags[0].len(), mtu);
+        assert_eq!(next, Seq32::from_u32(8));
+
+        // one byte over
+        let (frags, next) = fragment(&data[..5], Seq32::from_u32(7), mtu).unwrap();
+        assert_eq!(frags.len(), 2);
+        assert_eq!(frags[1].body_len(), Some(1));
+        assert!(frags[0].more());
+        assert!(!frags[1].more());
+        assert_eq!(next, Seq32::from_u32(9));
+
+        // across the wraparound, every push fitting the mtu
+        let (frags, next) = fragment(&data, Seq32::from_u32(u32::MAX), mtu).unwrap();
+        let seqs: Vec<u32> = frags.iter().map(|x| x.seq().to_u32()).collect();
+        assert_eq!(seqs, vec![u32::MAX, 0, 1]);
+        assert!(frags.iter().all(|x| PACKET_HDR_LEN + x.len() <= mtu));
+        let mut reassembled = Vec::new();
+        for frag in frags {
+            match frag.into_builder().cmd {
+                FragCommand::Push {
+                    body: Body::Slice(body),
+                } => reassembled.extend_from_slice(body.data()),
+                _ => panic!(),
+            }
+        }
+        assert_eq!(reassembled, data);
+        assert_eq!(next, Seq32::from_u32(2));
+
+        // zero-length input
+        let (frags, next) = fragment(&[], Seq32::from_u32(7), mtu).unwrap();
+        assert!(frags.is_empty());
+        assert_eq!(next, Seq32::from_u32(7));
+
+        // too small for a one-byte body
+        assert_eq!(
+            fragment(&data, Seq32::from_u32(7), PACKET_HDR_LEN + PUSH_HDR_LEN).err(),
+            Some(Error::MtuTooSmall)
+        );
+        assert!(fragment(&data, Seq32::from_u32(7), PACKET_HDR_LEN + PUSH_HDR_LEN + 1).is_ok());
+    }
 }
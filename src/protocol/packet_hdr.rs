@@ -1,78 +1,458 @@
-use super::{DecodingError, EncodingError};
+use super::{frag::CHECKSUM_LEN, DecodingError, EncodingError};
 use crate::utils::{
-    buf::{BufSlice, BufWtr},
-    Seq32,
+    buf::{BufRdr, BufWtr, Cursor},
+    Crc32, Seq32, CHECK_REP,
 };
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::io::Cursor;
+use alloc::vec::Vec;
+use byteorder::BigEndian;
 
-pub const PACKET_HDR_LEN: usize = 6;
+pub const PACKET_HDR_LEN: usize = 8;
+/// Bumped on every change to the wire format
+///
+/// - `2`: fragments of unknown commands carry a `len`, a `Reset` carries an `epoch` acknowledged by a `ResetAck`, and the `crc` of a `Push` covers its header
+pub const CURRENT_VERSION: u8 = 2;
+/// Of the layout before `version` existed: `rwnd`, `rwnd_shift` with the `checksum` and `ece` flags, and `nack`
+pub const LEGACY_PACKET_HDR_LEN: usize = 7;
+pub const MAX_RWND_SHIFT: u8 = 14;
+const SFT_CHECKSUM_FLAG: u8 = 0x80;
+const SFT_ECE_FLAG: u8 = 0x40;
+const SFT_TIMESTAMPS_FLAG: u8 = 0x20;
+const SFT_DELIMITED_FLAG: u8 = 0x10;
+const SFT_FLAGS: u8 = SFT_CHECKSUM_FLAG | SFT_ECE_FLAG | SFT_TIMESTAMPS_FLAG | SFT_DELIMITED_FLAG;
+/// Unit: byte
+pub const TIMESTAMPS_LEN: usize = 8;
+/// Unit: byte
+pub const PACKET_LEN_LEN: usize = 2;
+/// With every optional field present
+///
+/// Unit: byte
+const MAX_PACKET_HDR_LEN: usize = PACKET_HDR_LEN + CHECKSUM_LEN + TIMESTAMPS_LEN + PACKET_LEN_LEN;
+/// Unit: sequence
+pub const MAX_SCALED_RWND: usize = (u16::MAX as usize) << MAX_RWND_SHIFT;
 
+#[derive(Debug)]
 pub struct PacketHeader {
+    version: u8,
     rwnd: u16,
+    rwnd_shift: u8,
     nack: Seq32,
+    checksum: bool,
+    ece: bool,
+    timestamps: Option<Timestamps>,
+    delimited: bool,
+    format: PacketFormat,
+}
+
+/// The layout a header was decoded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketFormat {
+    /// Led by `CURRENT_VERSION`
+    Current,
+    /// Of `LEGACY_PACKET_HDR_LEN` bytes without a `version`, from a peer predating it
+    ///
+    /// Only the header is laid out the old way; the fragments decode as of `CURRENT_VERSION`. A legacy header whose `rwnd` starts with the byte of `CURRENT_VERSION` is taken for a current one.
+    Legacy,
+}
+
+/// TCP-style timestamps for RTT measurement, following the checksum if any
+///
+/// Unit: millisecond, on a clock of the sender's own choosing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timestamps {
+    /// When the sender sent the packet
+    pub ts_val: u32,
+    /// The latest `ts_val` the sender received from the peer
+    pub ts_ecr: u32,
 }
 
 pub struct PacketHeaderBuilder {
+    /// `CURRENT_VERSION` unless testing a mismatch
+    pub version: u8,
     pub rwnd: u16,
+    /// The advertised rwnd is `rwnd << rwnd_shift`
+    pub rwnd_shift: u8,
     pub nack: Seq32,
+    /// Follow the header with a CRC-32 of the whole packet
+    pub checksum: bool,
+    /// Echo that a packet from the peer arrived congestion-experienced
+    pub ece: bool,
+    pub timestamps: Option<Timestamps>,
+    /// Carry the length of the whole packet so that packets can be concatenated in one buffer
+    pub delimited: bool,
 }
 
 impl PacketHeaderBuilder {
     pub fn build(self) -> Result<PacketHeader, Error> {
+        if self.rwnd_shift > MAX_RWND_SHIFT {
+            return Err(Error::RwndShiftTooLarge);
+        }
         let this = PacketHeader {
+            version: self.version,
             rwnd: self.rwnd,
+            rwnd_shift: self.rwnd_shift,
             nack: self.nack,
+            checksum: self.checksum,
+            ece: self.ece,
+            timestamps: self.timestamps,
+            delimited: self.delimited,
+            format: PacketFormat::Current,
         };
         this.check_rep();
         Ok(this)
     }
 }
 
-#[derive(Debug)]
-pub enum Error {}
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    RwndShiftTooLarge,
+}
+
+/// Unit: byte
+#[must_use]
+#[inline]
+pub fn packet_hdr_len(checksum: bool, timestamps: bool, delimited: bool) -> usize {
+    let mut len = PACKET_HDR_LEN;
+    if checksum {
+        len += CHECKSUM_LEN;
+    }
+    if timestamps {
+        len += TIMESTAMPS_LEN;
+    }
+    if delimited {
+        len += PACKET_LEN_LEN;
+    }
+    len
+}
+
+/// Split `rwnd` into the `rwnd` and `rwnd_shift` of a header, rounding down
+#[must_use]
+pub fn scale_rwnd(rwnd: usize) -> (u16, u8) {
+    assert!(rwnd <= MAX_SCALED_RWND);
+    let mut shift = 0;
+    while rwnd >> shift > u16::MAX as usize {
+        shift += 1;
+    }
+    ((rwnd >> shift) as u16, shift)
+}
 
 impl PacketHeader {
     #[inline]
-    fn check_rep(&self) {}
+    fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
+        assert!(self.rwnd_shift <= MAX_RWND_SHIFT);
+    }
 
+    /// `slice` should hold the whole packet for the checksum to be verified
     #[must_use]
-    pub fn from_slice(slice: &mut BufSlice) -> Result<Self, DecodingError> {
-        let mut rdr = Cursor::new(slice.data());
+    pub fn from_slice(slice: &mut impl BufRdr) -> Result<Self, DecodingError> {
+        Self::from_slice_with(slice, false)
+    }
+
+    /// Under `legacy_mode`, a header not led by `CURRENT_VERSION` is decoded as `PacketFormat::Legacy` instead of rejected
+    #[must_use]
+    pub fn from_slice_with(
+        slice: &mut impl BufRdr,
+        legacy_mode: bool,
+    ) -> Result<Self, DecodingError> {
+        let this = slice.read_with(|rdr| Self::read(rdr, legacy_mode))?;
+        this.check_rep();
+        Ok(this)
+    }
+
+    fn read(rdr: &mut Cursor<'_>, legacy_mode: bool) -> Result<Self, DecodingError> {
+        let data = rdr.get_ref();
+        let version = *data.first().ok_or(DecodingError::PacketHeaderTooShort)?;
+        // the rest of the header may be laid out differently
+        if version != CURRENT_VERSION {
+            if legacy_mode {
+                return Self::read_legacy(rdr);
+            }
+            return Err(DecodingError::VersionMismatch { version });
+        }
+        rdr.read_u8()
+            .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
         let rwnd = rdr
             .read_u16::<BigEndian>()
-            .map_err(|_e| DecodingError::Decoding { field: "rwnd" })?;
+            .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
+        let sft = rdr
+            .read_u8()
+            .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
+        let checksum = sft & SFT_CHECKSUM_FLAG != 0;
+        let ece = sft & SFT_ECE_FLAG != 0;
+        let has_timestamps = sft & SFT_TIMESTAMPS_FLAG != 0;
+        let delimited = sft & SFT_DELIMITED_FLAG != 0;
+        let rwnd_shift = sft & !SFT_FLAGS;
+        if rwnd_shift > MAX_RWND_SHIFT {
+            return Err(DecodingError::PacketHeaderInvalid);
+        }
         let nack = rdr
             .read_u32::<BigEndian>()
-            .map_err(|_e| DecodingError::Decoding { field: "nack" })?;
+            .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
         let nack = Seq32::from_u32(nack);
+        let crc = match checksum {
+            true => Some(
+                rdr.read_u32::<BigEndian>()
+                    .map_err(|_e| DecodingError::PacketHeaderTooShort)?,
+            ),
+            false => None,
+        };
+        let timestamps = match has_timestamps {
+            true => {
+                let ts_val = rdr
+                    .read_u32::<BigEndian>()
+                    .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
+                let ts_ecr = rdr
+                    .read_u32::<BigEndian>()
+                    .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
+                Some(Timestamps { ts_val, ts_ecr })
+            }
+            false => None,
+        };
+        if delimited {
+            let declared_len =
+                rdr.read_u16::<BigEndian>()
+                    .map_err(|_e| DecodingError::PacketHeaderTooShort)? as usize;
+            if declared_len != data.len() {
+                return Err(DecodingError::PacketLenMismatch {
+                    declared_len,
+                    available: data.len(),
+                });
+            }
+        }
+        if let Some(crc) = crc {
+            // the checksum is computed with its own field zeroed
+            let mut expected = Crc32::new();
+            expected.update(&data[..PACKET_HDR_LEN]);
+            expected.update(&[0; CHECKSUM_LEN]);
+            expected.update(&data[PACKET_HDR_LEN + CHECKSUM_LEN..]);
+            if expected.finalize() != crc {
+                return Err(DecodingError::PacketChecksum);
+            }
+        }
 
-        let rdr_len = rdr.position() as usize;
-        slice.pop_front(rdr_len).unwrap();
+        Ok(PacketHeader {
+            version,
+            rwnd,
+            rwnd_shift,
+            nack,
+            checksum,
+            ece,
+            timestamps,
+            delimited,
+            format: PacketFormat::Current,
+        })
+    }
 
-        let this = PacketHeader { rwnd, nack };
-        this.check_rep();
-        Ok(this)
+    fn read_legacy(rdr: &mut Cursor<'_>) -> Result<Self, DecodingError> {
+        let data = rdr.get_ref();
+        let rwnd = rdr
+            .read_u16::<BigEndian>()
+            .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
+        let sft = rdr
+            .read_u8()
+            .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
+        let checksum = sft & SFT_CHECKSUM_FLAG != 0;
+        let ece = sft & SFT_ECE_FLAG != 0;
+        // the later flags were still bits of the shift
+        let rwnd_shift = sft & !(SFT_CHECKSUM_FLAG | SFT_ECE_FLAG);
+        if rwnd_shift > MAX_RWND_SHIFT {
+            return Err(DecodingError::PacketHeaderInvalid);
+        }
+        let nack = rdr
+            .read_u32::<BigEndian>()
+            .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
+        if checksum {
+            let crc = rdr
+                .read_u32::<BigEndian>()
+                .map_err(|_e| DecodingError::PacketHeaderTooShort)?;
+            let mut expected = Crc32::new();
+            expected.update(&data[..LEGACY_PACKET_HDR_LEN]);
+            expected.update(&[0; CHECKSUM_LEN]);
+            expected.update(&data[LEGACY_PACKET_HDR_LEN + CHECKSUM_LEN..]);
+            if expected.finalize() != crc {
+                return Err(DecodingError::PacketChecksum);
+            }
+        }
+
+        Ok(PacketHeader {
+            version: 0,
+            rwnd,
+            rwnd_shift,
+            nack: Seq32::from_u32(nack),
+            checksum,
+            ece,
+            timestamps: None,
+            delimited: false,
+            format: PacketFormat::Legacy,
+        })
+    }
+
+    /// The length of the packet starting at `data`, if it is delimited, or else `data.len()`
+    ///
+    /// Only the header is decoded, so the packet may still fail to decode.
+    pub fn peek_packet_len(data: &[u8]) -> Result<usize, DecodingError> {
+        if data.len() < PACKET_HDR_LEN {
+            return Err(DecodingError::PacketHeaderTooShort);
+        }
+        if data[0] != CURRENT_VERSION {
+            return Err(DecodingError::VersionMismatch { version: data[0] });
+        }
+        let sft = data[3];
+        if sft & SFT_DELIMITED_FLAG == 0 {
+            return Ok(data.len());
+        }
+        let hdr_len = packet_hdr_len(
+            sft & SFT_CHECKSUM_FLAG != 0,
+            sft & SFT_TIMESTAMPS_FLAG != 0,
+            true,
+        );
+        let len_field = data
+            .get(hdr_len - PACKET_LEN_LEN..hdr_len)
+            .ok_or(DecodingError::PacketHeaderTooShort)?;
+        let declared_len = u16::from_be_bytes([len_field[0], len_field[1]]) as usize;
+        if declared_len < hdr_len {
+            return Err(DecodingError::PacketHeaderInvalid);
+        }
+        if declared_len > data.len() {
+            return Err(DecodingError::PacketLenMismatch {
+                declared_len,
+                available: data.len(),
+            });
+        }
+        Ok(declared_len)
     }
 
+    /// Unit: byte
     #[must_use]
-    pub fn append_to(&self, wtr: &mut impl BufWtr) -> Result<(), EncodingError> {
-        let mut hdr = Vec::new();
-        hdr.write_u16::<BigEndian>(self.rwnd).unwrap();
-        hdr.write_u32::<BigEndian>(self.nack.to_u32()).unwrap();
-        assert_eq!(hdr.len(), PACKET_HDR_LEN);
+    pub fn hdr_len(&self) -> usize {
+        packet_hdr_len(self.checksum, self.timestamps.is_some(), self.delimited)
+    }
 
-        wtr.append(&hdr)
+    /// The checksum and the packet length are left zeroed for `Packet::append_to` to fill in
+    #[must_use]
+    pub fn append_to(&self, wtr: &mut impl BufWtr) -> Result<(), EncodingError> {
+        let (hdr, len) = self.encode();
+        wtr.append(&hdr[..len])
             .map_err(|_| EncodingError::NotEnoughSpace)?;
         Ok(())
     }
 
+    /// Append the bytes of `append_to` to `out` in place
+    pub fn to_bytes_into(&self, out: &mut Vec<u8>) {
+        let (hdr, len) = self.encode();
+        out.extend_from_slice(&hdr[..len]);
+    }
+
+    /// The bytes of `append_to` in a buffer of their own
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.hdr_len());
+        self.to_bytes_into(&mut out);
+        out
+    }
+
+    /// Encode on the stack, returning the buffer and the length taken of it
+    fn encode(&self) -> ([u8; MAX_PACKET_HDR_LEN], usize) {
+        let mut hdr = [0; MAX_PACKET_HDR_LEN];
+        let mut len = 0;
+        let mut put = |bytes: &[u8]| {
+            hdr[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        };
+        let version = match self.format {
+            PacketFormat::Current => self.version,
+            PacketFormat::Legacy => CURRENT_VERSION,
+        };
+        put(&[version]);
+        put(&self.rwnd.to_be_bytes());
+        let mut sft = self.rwnd_shift;
+        if self.checksum {
+            sft |= SFT_CHECKSUM_FLAG;
+        }
+        if self.ece {
+            sft |= SFT_ECE_FLAG;
+        }
+        if self.timestamps.is_some() {
+            sft |= SFT_TIMESTAMPS_FLAG;
+        }
+        if self.delimited {
+            sft |= SFT_DELIMITED_FLAG;
+        }
+        put(&[sft]);
+        put(&self.nack.to_u32().to_be_bytes());
+        if self.checksum {
+            put(&0_u32.to_be_bytes());
+        }
+        if let Some(timestamps) = &self.timestamps {
+            put(&timestamps.ts_val.to_be_bytes());
+            put(&timestamps.ts_ecr.to_be_bytes());
+        }
+        if self.delimited {
+            put(&0_u16.to_be_bytes());
+        }
+        assert_eq!(len, self.hdr_len());
+        (hdr, len)
+    }
+
+    /// `0` for a `PacketFormat::Legacy` header, which carries none
+    #[must_use]
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// A `Legacy` header is still encoded in the current layout
+    #[must_use]
+    #[inline]
+    pub fn format(&self) -> PacketFormat {
+        self.format
+    }
+
     #[must_use]
     #[inline]
     pub fn rwnd(&self) -> u16 {
         self.rwnd
     }
 
+    #[must_use]
+    #[inline]
+    pub fn rwnd_shift(&self) -> u8 {
+        self.rwnd_shift
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn ece(&self) -> bool {
+        self.ece
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn delimited(&self) -> bool {
+        self.delimited
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn timestamps(&self) -> Option<Timestamps> {
+        self.timestamps
+    }
+
+    /// Unit: sequence
+    #[must_use]
+    #[inline]
+    pub fn scaled_rwnd(&self) -> u32 {
+        (self.rwnd as u32) << self.rwnd_shift
+    }
+
     #[must_use]
     #[inline]
     pub fn nack(&self) -> Seq32 {
@@ -83,14 +463,20 @@ impl PacketHeader {
 #[cfg(test)]
 mod tests {
 
-    use crate::utils::buf::OwnedBufWtr;
+    use crate::utils::buf::{BufSlice, OwnedBufWtr};
 
     use super::*;
 
     #[test]
     fn test1() {
         let hdr1 = PacketHeaderBuilder {
+            version: CURRENT_VERSION,
             rwnd: 123,
+            rwnd_shift: 0,
+            checksum: false,
+            ece: false,
+            timestamps: None,
+            delimited: false,
             nack: Seq32::from_u32(456),
         }
         .build()
@@ -100,5 +486,317 @@ mod tests {
         let hdr2 = PacketHeader::from_slice(&mut wtr.into_slice()).unwrap();
         assert_eq!(hdr1.rwnd, hdr2.rwnd);
         assert_eq!(hdr1.nack, hdr2.nack);
+        assert_eq!(hdr2.scaled_rwnd(), 123);
+        assert!(!hdr2.ece());
+        assert_eq!(hdr2.version(), CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_to_bytes_into() {
+        for (checksum, timestamps, delimited) in [
+            (false, None, false),
+            (true, None, false),
+            (
+                false,
+                Some(Timestamps {
+                    ts_val: 7,
+                    ts_ecr: 8,
+                }),
+                false,
+            ),
+            (
+                true,
+                Some(Timestamps {
+                    ts_val: 7,
+                    ts_ecr: 8,
+                }),
+                true,
+            ),
+        ] {
+            let hdr = PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: 123,
+                rwnd_shift: 2,
+                checksum,
+                ece: true,
+                timestamps,
+                delimited,
+                nack: Seq32::from_u32(456),
+            }
+            .build()
+            .unwrap();
+            let mut wtr = OwnedBufWtr::new(1024, 512);
+            hdr.append_to(&mut wtr).unwrap();
+            assert_eq!(hdr.to_bytes(), wtr.data());
+            assert_eq!(hdr.to_bytes().len(), hdr.hdr_len());
+
+            // appended after what is already there
+            let mut out = vec![0xff; 3];
+            hdr.to_bytes_into(&mut out);
+            assert_eq!(out[..3], [0xff; 3]);
+            assert_eq!(out[3..], hdr.to_bytes());
+        }
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let build = |rwnd_shift| {
+            PacketHeaderBuilder {
+                version: CURRENT_VERSION,
+                rwnd: u16::MAX,
+                rwnd_shift,
+                nack: Seq32::from_u32(0),
+                checksum: false,
+                ece: false,
+                timestamps: None,
+                delimited: false,
+            }
+            .build()
+        };
+        assert_eq!(
+            build(MAX_RWND_SHIFT).unwrap().scaled_rwnd(),
+            MAX_SCALED_RWND as u32
+        );
+        assert_eq!(
+            build(MAX_RWND_SHIFT + 1).err(),
+            Some(Error::RwndShiftTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let hdr1 = PacketHeaderBuilder {
+            version: CURRENT_VERSION + 1,
+            rwnd: 123,
+            rwnd_shift: 0,
+            checksum: false,
+            ece: false,
+            timestamps: None,
+            delimited: false,
+            nack: Seq32::from_u32(456),
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        hdr1.append_to(&mut wtr).unwrap();
+        assert!(matches!(
+            PacketHeader::from_slice(&mut wtr.into_slice()),
+            Err(DecodingError::VersionMismatch { version }) if version == CURRENT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_legacy() {
+        // [rwnd(123)] [sft(ece, 3)] [nack(456)] [body]
+        let data = vec![0, 123, SFT_ECE_FLAG | 3, 0, 0, 1, 200, 9];
+        assert!(matches!(
+            PacketHeader::from_slice(&mut BufSlice::from_bytes(data.clone())),
+            Err(DecodingError::VersionMismatch { version: 0 })
+        ));
+        let mut slice = BufSlice::from_bytes(data);
+        let hdr = PacketHeader::from_slice_with(&mut slice, true).unwrap();
+        assert_eq!(hdr.format(), PacketFormat::Legacy);
+        assert_eq!(hdr.version(), 0);
+        assert_eq!(hdr.rwnd(), 123);
+        assert_eq!(hdr.rwnd_shift(), 3);
+        assert_eq!(hdr.nack(), Seq32::from_u32(456));
+        assert!(hdr.ece());
+        assert!(!hdr.checksum());
+        assert_eq!(slice.data(), &[9]);
+
+        // [rwnd(123)] [sft(checksum)] [nack(456)] [crc] [body]
+        let mut data = vec![0, 123, SFT_CHECKSUM_FLAG, 0, 0, 1, 200, 0, 0, 0, 0, 9];
+        let mut crc = Crc32::new();
+        crc.update(&data);
+        let crc = crc.finalize().to_be_bytes();
+        data[LEGACY_PACKET_HDR_LEN..LEGACY_PACKET_HDR_LEN + CHECKSUM_LEN].copy_from_slice(&crc);
+        let hdr =
+            PacketHeader::from_slice_with(&mut BufSlice::from_bytes(data.clone()), true).unwrap();
+        assert!(hdr.checksum());
+        data[LEGACY_PACKET_HDR_LEN + CHECKSUM_LEN] = 8;
+        assert!(matches!(
+            PacketHeader::from_slice_with(&mut BufSlice::from_bytes(data), true),
+            Err(DecodingError::PacketChecksum)
+        ));
+
+        // the current layout still decodes as such
+        let hdr1 = PacketHeaderBuilder {
+            version: CURRENT_VERSION,
+            rwnd: 123,
+            rwnd_shift: 0,
+            checksum: false,
+            ece: false,
+            timestamps: None,
+            delimited: false,
+            nack: Seq32::from_u32(456),
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        hdr1.append_to(&mut wtr).unwrap();
+        let hdr2 = PacketHeader::from_slice_with(&mut wtr.into_slice(), true).unwrap();
+        assert_eq!(hdr2.format(), PacketFormat::Current);
+        assert_eq!(hdr2.version(), CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_ece() {
+        let hdr1 = PacketHeaderBuilder {
+            version: CURRENT_VERSION,
+            rwnd: 123,
+            rwnd_shift: MAX_RWND_SHIFT,
+            nack: Seq32::from_u32(456),
+            checksum: false,
+            ece: true,
+            timestamps: None,
+            delimited: false,
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        hdr1.append_to(&mut wtr).unwrap();
+        let hdr2 = PacketHeader::from_slice(&mut wtr.into_slice()).unwrap();
+        assert!(hdr2.ece());
+        assert_eq!(hdr2.rwnd_shift(), MAX_RWND_SHIFT);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let timestamps = Timestamps {
+            ts_val: 0x01020304,
+            ts_ecr: u32::MAX,
+        };
+        let hdr1 = PacketHeaderBuilder {
+            version: CURRENT_VERSION,
+            rwnd: 123,
+            rwnd_shift: 0,
+            nack: Seq32::from_u32(456),
+            checksum: false,
+            ece: true,
+            timestamps: Some(timestamps),
+            delimited: false,
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        hdr1.append_to(&mut wtr).unwrap();
+        assert_eq!(wtr.data_len(), packet_hdr_len(false, true, false));
+        let mut slice = wtr.into_slice();
+        let hdr2 = PacketHeader::from_slice(&mut slice).unwrap();
+        assert!(slice.is_empty());
+        assert!(hdr2.ece());
+        assert_eq!(hdr2.timestamps(), Some(timestamps));
+
+        // a header without the flag carries no timestamps
+        let mut slice = BufSlice::from_bytes(vec![CURRENT_VERSION, 0, 1, 0, 0, 0, 0, 0]);
+        let hdr = PacketHeader::from_slice(&mut slice).unwrap();
+        assert_eq!(hdr.timestamps(), None);
+
+        // the flag promises 8 more bytes
+        let mut slice = BufSlice::from_bytes(vec![
+            CURRENT_VERSION,
+            0,
+            1,
+            SFT_TIMESTAMPS_FLAG,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        assert!(matches!(
+            PacketHeader::from_slice(&mut slice),
+            Err(DecodingError::PacketHeaderTooShort)
+        ));
+    }
+
+    #[test]
+    fn test_delimited() {
+        let hdr = PacketHeaderBuilder {
+            version: CURRENT_VERSION,
+            rwnd: 123,
+            rwnd_shift: 0,
+            nack: Seq32::from_u32(456),
+            checksum: false,
+            ece: false,
+            timestamps: None,
+            delimited: true,
+        }
+        .build()
+        .unwrap();
+        assert_eq!(hdr.hdr_len(), PACKET_HDR_LEN + PACKET_LEN_LEN);
+        let mut wtr = OwnedBufWtr::new(1024, 0);
+        hdr.append_to(&mut wtr).unwrap();
+        let mut data = wtr.data().to_vec();
+        data[PACKET_HDR_LEN + 1] = 10;
+        assert_eq!(PacketHeader::peek_packet_len(&data).unwrap(), 10);
+        let hdr = PacketHeader::from_slice(&mut BufSlice::from_bytes(data.clone())).unwrap();
+        assert!(hdr.delimited());
+
+        // the declared length covers the whole packet
+        data[PACKET_HDR_LEN + 1] = 2;
+        assert!(matches!(
+            PacketHeader::peek_packet_len(&data),
+            Err(DecodingError::PacketHeaderInvalid)
+        ));
+        data[PACKET_HDR_LEN + 1] = 12;
+        assert!(matches!(
+            PacketHeader::peek_packet_len(&data),
+            Err(DecodingError::PacketLenMismatch {
+                declared_len: 12,
+                available: 10
+            })
+        ));
+
+        // whatever follows is left for the next packet
+        data[PACKET_HDR_LEN + 1] = 10;
+        data.extend_from_slice(&[0; 4]);
+        assert_eq!(PacketHeader::peek_packet_len(&data).unwrap(), 10);
+        assert!(matches!(
+            PacketHeader::from_slice(&mut BufSlice::from_bytes(data)),
+            Err(DecodingError::PacketLenMismatch {
+                declared_len: 10,
+                available: 14
+            })
+        ));
+
+        // a packet not delimited runs to the end
+        let data = vec![CURRENT_VERSION, 0, 1, 0, 0, 0, 0, 0, 1, 2];
+        assert_eq!(PacketHeader::peek_packet_len(&data).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_rwnd_shift() {
+        let (rwnd, rwnd_shift) = scale_rwnd(u16::MAX as usize * 4 + 3);
+        assert_eq!((rwnd, rwnd_shift), (u16::MAX, 2));
+        let hdr1 = PacketHeaderBuilder {
+            version: CURRENT_VERSION,
+            rwnd,
+            rwnd_shift,
+            nack: Seq32::from_u32(456),
+            checksum: false,
+            ece: false,
+            timestamps: None,
+            delimited: false,
+        }
+        .build()
+        .unwrap();
+        let mut wtr = OwnedBufWtr::new(1024, 512);
+        hdr1.append_to(&mut wtr).unwrap();
+        assert_eq!(wtr.data_len(), PACKET_HDR_LEN);
+        let hdr2 = PacketHeader::from_slice(&mut wtr.into_slice()).unwrap();
+        assert_eq!(hdr2.rwnd_shift(), 2);
+        assert_eq!(hdr2.scaled_rwnd(), u16::MAX as u32 * 4);
+
+        assert_eq!(scale_rwnd(0), (0, 0));
+        assert_eq!(scale_rwnd(u16::MAX as usize), (u16::MAX, 0));
+        assert_eq!(scale_rwnd(MAX_SCALED_RWND), (u16::MAX, MAX_RWND_SHIFT));
+
+        let mut slice =
+            BufSlice::from_bytes(vec![CURRENT_VERSION, 0, 1, MAX_RWND_SHIFT + 1, 0, 0, 0, 0]);
+        assert!(matches!(
+            PacketHeader::from_slice(&mut slice),
+            Err(DecodingError::PacketHeaderInvalid)
+        ));
     }
 }
@@ -0,0 +1,300 @@
+use crate::{
+    layer::{BuildError, Builder, Downloader, Uploader},
+    utils::buf::{BufSlice, BufWtr, OwnedBufWtr},
+};
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::UdpSocket,
+    sync::Notify,
+    task::JoinHandle,
+};
+
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A reliable byte stream over a UDP socket connected to the peer
+///
+/// A background task receives datagrams and drives retransmissions and acks. Dropping the stream stops it.
+pub struct AsyncYatcpStream {
+    shared: Arc<Shared>,
+    driver: JoinHandle<()>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    to_flush: Notify,
+}
+
+struct State {
+    uploader: Uploader,
+    downloader: Downloader,
+    unread: Option<BufSlice>,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    error: Option<io::ErrorKind>,
+}
+
+impl AsyncYatcpStream {
+    /// Must be called within a tokio runtime
+    pub fn new(socket: UdpSocket, builder: Builder) -> Result<Self, BuildError> {
+        let mtu = builder.mtu;
+        let (uploader, downloader) = builder.build()?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                uploader,
+                downloader,
+                unread: None,
+                read_waker: None,
+                write_waker: None,
+                error: None,
+            }),
+            to_flush: Notify::new(),
+        });
+        let driver = tokio::spawn(drive(socket, Arc::clone(&shared), mtu));
+        Ok(AsyncYatcpStream { shared, driver })
+    }
+}
+
+impl Drop for AsyncYatcpStream {
+    fn drop(&mut self) {
+        self.driver.abort();
+    }
+}
+
+async fn drive(socket: UdpSocket, shared: Arc<Shared>, mtu: usize) {
+    let mut buf = vec![0; mtu];
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        let ack_deadline = shared.state.lock().unwrap().downloader.ack_deadline();
+        tokio::select! {
+            res = socket.recv(&mut buf) => {
+                let mut state = shared.state.lock().unwrap();
+                let len = match res {
+                    Ok(len) => len,
+                    Err(e) => {
+                        state.error = Some(e.kind());
+                        state.wake_all();
+                        return;
+                    }
+                };
+                let now = Instant::now();
+                let State {
+                    uploader,
+                    downloader,
+                    ..
+                } = &mut *state;
+                // a malformed datagram is as good as a lost one
+                if let Ok(changes) = downloader.write(BufSlice::from_bytes(buf[..len].to_vec()), &now) {
+                    let _ = uploader.set_state(changes, &now);
+                }
+                if let Some(waker) = state.read_waker.take() {
+                    waker.wake();
+                }
+            }
+            _ = interval.tick() => {}
+            _ = shared.to_flush.notified() => {}
+            _ = ack_timer(ack_deadline) => {}
+        }
+
+        let packets = {
+            let mut state = shared.state.lock().unwrap();
+            let now = Instant::now();
+            let State {
+                uploader,
+                downloader,
+                ..
+            } = &mut *state;
+            for seq in downloader.on_timer(&now) {
+                uploader.queue_ack(seq);
+            }
+            let packets = uploader.emit(&now);
+            // acks may have freed the send queue
+            if let Some(waker) = state.write_waker.take() {
+                waker.wake();
+            }
+            packets
+        };
+        for packet in packets {
+            let mut wtr = OwnedBufWtr::new(mtu, 0);
+            packet.append_to(&mut wtr).unwrap();
+            // a datagram failing to send is retransmitted like a lost one
+            let _ = socket.send(wtr.data()).await;
+        }
+    }
+}
+
+/// Wait for the acks held back by the downloader to fall due, or forever if none are
+async fn ack_timer(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+impl State {
+    fn wake_all(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl AsyncRead for AsyncYatcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.unread.is_none() {
+            state.unread = state.downloader.emit();
+        }
+        match state.unread.take() {
+            Some(mut slice) => {
+                let len = usize::min(slice.len(), buf.remaining());
+                let front = slice.pop_front(len).unwrap();
+                buf.put_slice(front.data());
+                if !slice.is_empty() {
+                    state.unread = Some(slice);
+                }
+                Poll::Ready(Ok(()))
+            }
+            None => {
+                if let Some(kind) = state.error {
+                    return Poll::Ready(Err(kind.into()));
+                }
+                state.read_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl AsyncWrite for AsyncYatcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(kind) = state.error {
+            return Poll::Ready(Err(kind.into()));
+        }
+        match state.uploader.write(BufSlice::from_bytes(buf.to_vec())) {
+            Ok(()) => {
+                self.shared.to_flush.notify_one();
+                Poll::Ready(Ok(buf.len()))
+            }
+            Err(_) => {
+                state.write_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// The written bytes are handed to the background task but not necessarily acked
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.shared.to_flush.notify_one();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::DelayedAckPolicy;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn connected_pair() -> (UdpSocket, UdpSocket) {
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        a.connect(b.local_addr().unwrap()).await.unwrap();
+        b.connect(a.local_addr().unwrap()).await.unwrap();
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn test_loopback() {
+        let (a, b) = connected_pair().await;
+        let builder = || Builder {
+            local_recv_buf_len: 64,
+            to_send_queue_len_cap: 16,
+            swnd_size_cap: 64,
+            ..Builder::default()
+        };
+        let mut a = AsyncYatcpStream::new(a, builder()).unwrap();
+        let mut b = AsyncYatcpStream::new(b, builder()).unwrap();
+
+        let data: Vec<u8> = (0..50_000).map(|i| i as u8).collect();
+        let sent = data.clone();
+        let writer = tokio::spawn(async move {
+            for chunk in sent.chunks(1000) {
+                a.write_all(chunk).await.unwrap();
+            }
+            a.flush().await.unwrap();
+            // keep retransmitting until the reader is done
+            a
+        });
+
+        let mut received = vec![0; data.len()];
+        tokio::time::timeout(Duration::from_secs(10), b.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, data);
+
+        // and back
+        let mut a = writer.await.unwrap();
+        b.write_all(b"done").await.unwrap();
+        let mut reply = [0; 4];
+        tokio::time::timeout(Duration::from_secs(10), a.read_exact(&mut reply))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&reply, b"done");
+    }
+
+    #[tokio::test]
+    async fn test_delayed_ack() {
+        let (a, b) = connected_pair().await;
+        let builder = || Builder {
+            delayed_ack: Some(DelayedAckPolicy {
+                max_delay: Duration::from_millis(20),
+                max_unacked: usize::MAX,
+            }),
+            ..Builder::default()
+        };
+        let mut a = AsyncYatcpStream::new(a, builder()).unwrap();
+        let mut b = AsyncYatcpStream::new(b, builder()).unwrap();
+
+        // each write past the first waits on the acks of the one before, well within an RTO
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let sent = data.clone();
+        let writer = tokio::spawn(async move {
+            for chunk in sent.chunks(1000) {
+                a.write_all(chunk).await.unwrap();
+            }
+            a
+        });
+        let mut received = vec![0; data.len()];
+        tokio::time::timeout(Duration::from_secs(2), b.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, data);
+        drop(writer.await.unwrap());
+    }
+}
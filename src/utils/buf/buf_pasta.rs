@@ -1,4 +1,6 @@
 use super::{BufSlice, BufWtr, OwnedBufWtr};
+use crate::utils::CHECK_REP;
+use alloc::vec::Vec;
 
 pub struct BufPasta {
     slices: Vec<BufSlice>,
@@ -13,6 +15,9 @@ pub enum Error {
 impl BufPasta {
     #[inline]
     fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
         let mut cum_len = 0;
         for slice in &self.slices {
             cum_len += slice.data().len();
@@ -37,6 +42,10 @@ impl BufPasta {
         self.len == 0
     }
 
+    pub fn slices(&self) -> &[BufSlice] {
+        &self.slices
+    }
+
     pub fn append(&mut self, slice: BufSlice) {
         self.len += slice.data().len();
         self.slices.push(slice);
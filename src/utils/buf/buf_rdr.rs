@@ -0,0 +1,142 @@
+use super::{
+    buf_slice::{BufSlice, Error},
+    Cursor, FragAllocator, GlobalAllocator,
+};
+
+/// A reader consumed from the front, either over an owned `BufSlice` or over a borrowed `BorrowedBufSlice`
+pub trait BufRdr {
+    fn data(&self) -> &[u8];
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    /// Decode from the front, consuming the bytes read by `f` only if it succeeds
+    fn read_with<T, E>(&mut self, f: impl FnOnce(&mut Cursor<'_>) -> Result<T, E>) -> Result<T, E>;
+    /// Consume the first `len` bytes into a slice that outlives the reader
+    ///
+    /// An owned reader shares its buffer, while a borrowed one copies the bytes.
+    fn pop_front(&mut self, len: usize) -> Result<BufSlice, Error>;
+    /// Consume the first `len` bytes into a reader of the same kind, without copying
+    fn split_front(&mut self, len: usize) -> Result<Self, Error>
+    where
+        Self: Sized;
+}
+
+impl BufRdr for BufSlice {
+    #[inline]
+    fn data(&self) -> &[u8] {
+        BufSlice::data(self)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        BufSlice::len(self)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        BufSlice::is_empty(self)
+    }
+
+    fn read_with<T, E>(&mut self, f: impl FnOnce(&mut Cursor<'_>) -> Result<T, E>) -> Result<T, E> {
+        BufSlice::read_with(self, f)
+    }
+
+    #[inline]
+    fn pop_front(&mut self, len: usize) -> Result<BufSlice, Error> {
+        BufSlice::pop_front(self, len)
+    }
+
+    #[inline]
+    fn split_front(&mut self, len: usize) -> Result<Self, Error> {
+        BufSlice::pop_front(self, len)
+    }
+}
+
+/// A reader over bytes it does not own, such as a socket buffer reused across reads
+pub struct BorrowedBufSlice<'a> {
+    data: &'a [u8],
+    allocator: &'a dyn FragAllocator,
+}
+
+impl<'a> BorrowedBufSlice<'a> {
+    #[must_use]
+    pub fn from_slice(data: &'a [u8]) -> Self {
+        Self::with_allocator(data, &GlobalAllocator)
+    }
+
+    /// The bodies popped are copied into buffers from `allocator`
+    #[must_use]
+    pub fn with_allocator(data: &'a [u8], allocator: &'a dyn FragAllocator) -> Self {
+        BorrowedBufSlice { data, allocator }
+    }
+}
+
+impl<'a> BufRdr for BorrowedBufSlice<'a> {
+    #[inline]
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn read_with<T, E>(&mut self, f: impl FnOnce(&mut Cursor<'_>) -> Result<T, E>) -> Result<T, E> {
+        let mut rdr = Cursor::new(self.data);
+        let v = f(&mut rdr)?;
+        let read_len = usize::min(rdr.position(), self.data.len());
+        self.data = &self.data[read_len..];
+        Ok(v)
+    }
+
+    fn pop_front(&mut self, len: usize) -> Result<BufSlice, Error> {
+        if len > self.data.len() {
+            return Err(Error::IndexOutOfRange);
+        }
+        let (front, rest) = self.data.split_at(len);
+        self.data = rest;
+        let mut buf = self.allocator.alloc(len);
+        buf.clear();
+        buf.extend_from_slice(front);
+        Ok(BufSlice::from_bytes(buf))
+    }
+
+    fn split_front(&mut self, len: usize) -> Result<Self, Error> {
+        if len > self.data.len() {
+            return Err(Error::IndexOutOfRange);
+        }
+        let (front, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(Self::with_allocator(front, self.allocator))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn borrowed() {
+        let buf = vec![0, 1, 2, 3];
+        let mut rdr = BorrowedBufSlice::from_slice(&buf);
+        assert_eq!(rdr.read_with(|rdr| rdr.read_u8()).unwrap(), 0);
+        assert!(rdr.read_with(|_| Err::<(), _>(())).is_err());
+        assert_eq!(rdr.data(), vec![1, 2, 3]);
+        assert!(rdr.pop_front(4).is_err());
+        assert_eq!(rdr.pop_front(2).unwrap().data(), vec![1, 2]);
+        assert_eq!(rdr.len(), 1);
+        assert!(!rdr.is_empty());
+        assert_eq!(rdr.pop_front(1).unwrap().data(), vec![3]);
+        assert!(rdr.is_empty());
+
+        let mut rdr = BorrowedBufSlice::from_slice(&buf);
+        assert!(rdr.split_front(5).is_err());
+        assert_eq!(rdr.split_front(3).unwrap().data(), vec![0, 1, 2]);
+        assert_eq!(rdr.data(), vec![3]);
+    }
+}
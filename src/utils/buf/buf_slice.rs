@@ -1,4 +1,7 @@
-use std::{ops::Range, sync::Arc};
+use super::Cursor;
+use crate::utils::CHECK_REP;
+use alloc::{sync::Arc, vec::Vec};
+use core::ops::Range;
 
 pub struct BufSlice {
     buf: Arc<Vec<u8>>,
@@ -31,6 +34,9 @@ impl BufSliceBuilder {
 impl BufSlice {
     #[inline]
     fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
         assert!(self.range.start <= self.range.end);
         assert!(self.range.end <= self.buf.len());
     }
@@ -68,8 +74,12 @@ impl BufSlice {
         self.len() == 0
     }
 
+    /// A sub-slice over the same buffer, with `range` relative to this slice
     #[must_use]
     pub fn slice(&self, range: Range<usize>) -> Result<BufSlice, Error> {
+        if range.end > self.len() {
+            return Err(Error::IndexOutOfRange);
+        }
         let start = self.range.start + range.start;
         let end = self.range.start + range.end;
         let slice = BufSliceBuilder {
@@ -96,9 +106,32 @@ impl BufSlice {
         Ok((head, tail))
     }
 
+    /// Split into `[0, mid)` and `[mid, len)` over the same buffer
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid` is greater than `len`.
+    #[must_use]
+    pub fn split_at(self, mid: usize) -> (BufSlice, BufSlice) {
+        assert!(mid <= self.len());
+        let range_mid = self.range.start + mid;
+        let head = BufSlice {
+            buf: Arc::clone(&self.buf),
+            range: self.range.start..range_mid,
+        };
+        let tail = BufSlice {
+            buf: self.buf,
+            range: range_mid..self.range.end,
+        };
+        (head, tail)
+    }
+
     #[must_use]
     #[inline]
     pub fn pop_front(&mut self, len: usize) -> Result<BufSlice, Error> {
+        if len > self.len() {
+            return Err(Error::IndexOutOfRange);
+        }
         let range_mid = self.range.start + len;
         let front = BufSliceBuilder {
             buf: Arc::clone(&self.buf),
@@ -108,6 +141,32 @@ impl BufSlice {
         self.range.start = range_mid;
         Ok(front)
     }
+
+    /// Give up the slice for its whole buffer, unless other slices still share it
+    #[must_use]
+    pub fn into_buf(self) -> Option<Vec<u8>> {
+        Arc::try_unwrap(self.buf).ok()
+    }
+
+    /// The first `n` bytes, without consuming them
+    #[must_use]
+    #[inline]
+    pub fn peek_bytes(&self, n: usize) -> Option<&[u8]> {
+        self.data().get(..n)
+    }
+
+    /// Decode from the front, consuming the bytes read by `f` only if it succeeds
+    pub fn read_with<T, E>(
+        &mut self,
+        f: impl FnOnce(&mut Cursor<'_>) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut rdr = Cursor::new(self.data());
+        let v = f(&mut rdr)?;
+        let read_len = usize::min(rdr.position(), self.len());
+        self.range.start += read_len;
+        self.check_rep();
+        Ok(v)
+    }
 }
 
 #[derive(Debug)]
@@ -120,8 +179,18 @@ mod tests {
 
     use std::sync::Arc;
 
+    use byteorder::BigEndian;
+
     use super::{BufSlice, BufSliceBuilder};
 
+    #[test]
+    fn into_buf() {
+        let slice = BufSlice::from_bytes(vec![0, 1, 2]);
+        let (head, tail) = slice.split_at(1);
+        assert!(head.into_buf().is_none());
+        assert_eq!(tail.into_buf().unwrap(), vec![0, 1, 2]);
+    }
+
     #[test]
     fn data() {
         let slice = BufSliceBuilder {
@@ -152,6 +221,16 @@ mod tests {
         assert_eq!(sub_slice.data(), vec![3]);
         let sub_slice = slice.slice(0..3).unwrap();
         assert_eq!(sub_slice.data(), vec![1, 2, 3]);
+        assert!(Arc::ptr_eq(&slice.buf, &sub_slice.buf));
+
+        // within the buffer but beyond the slice
+        let beyond = BufSliceBuilder {
+            buf: Arc::new(vec![0, 1, 2, 3]),
+            range: 0..2,
+        }
+        .build()
+        .unwrap();
+        assert!(beyond.slice(1..3).is_err());
     }
 
     #[test]
@@ -170,6 +249,40 @@ mod tests {
         assert_eq!(tail.data(), vec![1, 2]);
     }
 
+    #[test]
+    fn split_at() {
+        let slice = BufSliceBuilder {
+            buf: Arc::new(vec![9, 0, 1, 2, 3]),
+            range: 1..4,
+        }
+        .build()
+        .unwrap();
+        let buf = Arc::clone(&slice.buf);
+
+        let (head, tail) = slice.split_at(1);
+        assert_eq!(head.data(), vec![0]);
+        assert_eq!(tail.data(), vec![1, 2]);
+        assert!(Arc::ptr_eq(&head.buf, &buf));
+        assert!(Arc::ptr_eq(&tail.buf, &buf));
+        assert_eq!([head.data(), tail.data()].concat(), vec![0, 1, 2]);
+
+        let (head, tail) = tail.split_at(2);
+        assert_eq!(head.data(), vec![1, 2]);
+        assert!(tail.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn split_at_out_of_range() {
+        let slice = BufSliceBuilder {
+            buf: Arc::new(vec![9, 0, 1, 2, 3]),
+            range: 1..4,
+        }
+        .build()
+        .unwrap();
+        let _ = slice.split_at(4);
+    }
+
     #[test]
     fn pop_front() {
         let mut buf = BufSlice::from_bytes(vec![0, 1, 2, 3, 4, 5]);
@@ -193,4 +306,23 @@ mod tests {
         let slice2 = BufSlice::clone(&slice1);
         assert_eq!(slice1.data(), slice2.data());
     }
+
+    #[test]
+    fn read_with() {
+        let mut buf = BufSlice::from_bytes(vec![0, 1, 2, 3]);
+        assert_eq!(buf.peek_bytes(2).unwrap(), vec![0, 1]);
+        assert!(buf.peek_bytes(5).is_none());
+        assert_eq!(buf.len(), 4);
+
+        let result: Result<u16, ()> =
+            buf.read_with(|rdr| rdr.read_u16::<BigEndian>().map_err(|_| ()));
+        assert_eq!(result, Ok(0x0001));
+        assert_eq!(buf.data(), vec![2, 3]);
+
+        // nothing is consumed on failure
+        let result: Result<u32, ()> =
+            buf.read_with(|rdr| rdr.read_u32::<BigEndian>().map_err(|_| ()));
+        assert!(result.is_err());
+        assert_eq!(buf.data(), vec![2, 3]);
+    }
 }
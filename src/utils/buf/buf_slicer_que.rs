@@ -1,23 +1,33 @@
 use super::BufSlice;
-use std::collections::VecDeque;
+use crate::utils::CHECK_REP;
+use alloc::collections::VecDeque;
 
 pub struct BufSlicerQue {
     queue: VecDeque<BufSlice>,
     len_cap: usize,
+    byte_len: usize,
 }
 
 impl BufSlicerQue {
     fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
         assert!(self.queue.len() <= self.len_cap);
         for slice in &self.queue {
             assert!(!slice.is_empty());
         }
+        assert_eq!(
+            self.queue.iter().map(|slice| slice.len()).sum::<usize>(),
+            self.byte_len
+        );
     }
 
     pub fn new(len_cap: usize) -> Self {
         let this = BufSlicerQue {
             queue: VecDeque::new(),
             len_cap,
+            byte_len: 0,
         };
         this.check_rep();
         this
@@ -31,6 +41,7 @@ impl BufSlicerQue {
             return Ok(());
         }
 
+        self.byte_len += slice.len();
         self.queue.push_back(slice);
         self.check_rep();
         Ok(())
@@ -41,6 +52,7 @@ impl BufSlicerQue {
             Some(x) => x,
             None => return Err(Error::NothingToSlice),
         };
+        self.byte_len -= usize::min(slice.len(), max_len);
         if slice.len() <= max_len {
             self.check_rep();
             Ok(slice)
@@ -60,6 +72,11 @@ impl BufSlicerQue {
     pub fn is_full(&self) -> bool {
         self.queue.len() == self.len_cap
     }
+
+    /// Unit: byte
+    pub fn byte_len(&self) -> usize {
+        self.byte_len
+    }
 }
 
 #[derive(Debug)]
@@ -84,11 +101,14 @@ mod tests {
         slicer.push_back(slice2).map_err(|_| ()).unwrap();
         assert!(slicer.is_full());
 
+        assert_eq!(slicer.byte_len(), 3);
+
         let slice1 = slicer.slice_front(2).unwrap();
         assert_eq!(slice1.data(), vec![0]);
 
         let slice2 = slicer.slice_front(1).unwrap();
         assert_eq!(slice2.data(), vec![1]);
+        assert_eq!(slicer.byte_len(), 1);
 
         let slice3 = slicer.slice_front(2).unwrap();
         assert_eq!(slice3.data(), vec![2]);
@@ -0,0 +1,72 @@
+use byteorder::ByteOrder;
+
+/// A read position over borrowed bytes, standing in for `std::io::Cursor` without `std`
+pub struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// Fewer bytes are left than a read asks for
+#[derive(Debug)]
+pub struct UnexpectedEof;
+
+impl<'a> Cursor<'a> {
+    #[must_use]
+    pub fn new(data: &'a [u8]) -> Self {
+        Cursor { data, pos: 0 }
+    }
+
+    /// All the bytes, the ones read included
+    #[must_use]
+    #[inline]
+    pub fn get_ref(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Unit: byte
+    #[must_use]
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// The position is left as is on failure
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], UnexpectedEof> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(UnexpectedEof)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, UnexpectedEof> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16<B: ByteOrder>(&mut self) -> Result<u16, UnexpectedEof> {
+        Ok(B::read_u16(self.read_bytes(2)?))
+    }
+
+    pub fn read_u32<B: ByteOrder>(&mut self) -> Result<u32, UnexpectedEof> {
+        Ok(B::read_u32(self.read_bytes(4)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::BigEndian;
+
+    use super::*;
+
+    #[test]
+    fn read() {
+        let mut rdr = Cursor::new(&[0, 1, 2, 3, 4, 5]);
+        assert_eq!(rdr.read_u8().unwrap(), 0);
+        assert_eq!(rdr.read_u16::<BigEndian>().unwrap(), 0x0102);
+        assert!(rdr.read_u32::<BigEndian>().is_err());
+        assert_eq!(rdr.position(), 3);
+        assert_eq!(rdr.read_u16::<BigEndian>().unwrap(), 0x0304);
+        assert_eq!(rdr.get_ref().len(), 6);
+    }
+}
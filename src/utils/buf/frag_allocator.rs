@@ -0,0 +1,19 @@
+use alloc::vec::Vec;
+
+/// Where the bodies copied out of borrowed datagrams get their buffers, e.g. a pool
+pub trait FragAllocator {
+    /// An empty buffer with room for at least `len` bytes
+    fn alloc(&self, len: usize) -> Vec<u8> {
+        Vec::with_capacity(len)
+    }
+
+    /// Take back a buffer no longer referenced by any slice
+    fn free(&self, buf: Vec<u8>) {
+        drop(buf);
+    }
+}
+
+/// Allocate straight from the global allocator
+pub struct GlobalAllocator;
+
+impl FragAllocator for GlobalAllocator {}
@@ -1,13 +1,19 @@
 mod buf_pasta;
+mod buf_rdr;
 mod buf_slice;
 mod buf_slicer_que;
 mod buf_wtr;
+mod cursor;
+mod frag_allocator;
 mod owned_buf_wtr;
 mod sub_buf_wtr;
 
 pub use buf_pasta::*;
+pub use buf_rdr::*;
 pub use buf_slice::*;
 pub use buf_slicer_que::*;
 pub use buf_wtr::*;
+pub use cursor::*;
+pub use frag_allocator::*;
 pub use owned_buf_wtr::*;
 pub use sub_buf_wtr::*;
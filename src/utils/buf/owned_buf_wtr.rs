@@ -1,4 +1,5 @@
-use std::sync::Arc;
+use crate::utils::CHECK_REP;
+use alloc::{sync::Arc, vec, vec::Vec};
 
 use super::{buf_wtr::Error, BufSlice, BufSliceBuilder, BufWtr};
 
@@ -12,6 +13,9 @@ pub struct OwnedBufWtr {
 impl OwnedBufWtr {
     #[inline]
     fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
         assert!(self.start <= self.end);
         assert!(self.end <= self.buf.len());
     }
@@ -1,4 +1,5 @@
 use super::{buf_wtr::Error, BufWtr};
+use crate::utils::CHECK_REP;
 
 #[derive(Debug)]
 pub struct SubBufWtr<'a> {
@@ -10,6 +11,9 @@ pub struct SubBufWtr<'a> {
 impl<'a> SubBufWtr<'a> {
     #[inline]
     pub fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
         assert!(self.start <= self.end);
         assert!(self.end <= self.buf.len());
     }
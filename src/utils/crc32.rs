@@ -0,0 +1,77 @@
+/// CRC-32 (IEEE 802.3)
+pub struct Crc32 {
+    crc: u32,
+}
+
+const POLY: u32 = 0xEDB8_8320;
+static TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ POLY,
+                _ => crc >> 1,
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+impl Crc32 {
+    #[must_use]
+    pub fn new() -> Self {
+        Crc32 { crc: u32::MAX }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            let i = (self.crc ^ byte as u32) & 0xFF;
+            self.crc = (self.crc >> 8) ^ TABLE[i as usize];
+        }
+    }
+
+    #[must_use]
+    pub fn finalize(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[must_use]
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Crc32};
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn incremental() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finalize(), crc32(b"123456789"));
+    }
+}
@@ -1,13 +1,20 @@
 pub mod buf;
+mod crc32;
 pub mod dup;
 mod fast_retransmit_wnd;
 mod recv_buf;
+mod rto;
 mod seq;
 mod seq32;
 mod swnd;
 
+pub use crc32::*;
 pub use fast_retransmit_wnd::*;
 pub use recv_buf::*;
+pub use rto::*;
 pub use seq::*;
 pub use seq32::*;
 pub use swnd::*;
+
+/// Whether `check_rep` asserts the invariants, off in release builds unless the `paranoid` feature is on
+pub(crate) const CHECK_REP: bool = cfg!(any(debug_assertions, feature = "paranoid"));
@@ -1,6 +1,7 @@
 use super::{rwnd::Rwnd, SeqLocationToRwnd};
-use crate::utils::Seq;
-use std::collections::VecDeque;
+use crate::utils::{Seq, CHECK_REP};
+use alloc::{collections::VecDeque, vec::Vec};
+use core::ops::Range;
 
 pub struct RecvBuf<TSeq, T>
 where
@@ -16,8 +17,11 @@ where
     TSeq: Seq,
 {
     fn check_rep(&self) {
-        let ofo_len = self.rwnd.size();
-        assert_eq!(ofo_len + self.sorted.len(), self.len);
+        if !CHECK_REP {
+            return;
+        }
+        // `sorted` outgrows `len` after a shrink, leaving no room in the rwnd
+        assert_eq!(self.rwnd.size(), self.len.saturating_sub(self.sorted.len()));
     }
 
     #[must_use]
@@ -34,7 +38,9 @@ where
     #[must_use]
     pub fn pop_front(&mut self) -> Option<T> {
         if let Some(x) = self.sorted.pop_front() {
-            self.rwnd.increment_size();
+            if self.sorted.len() < self.len {
+                self.rwnd.increment_size();
+            }
             self.check_rep();
             Some(x)
         } else {
@@ -43,6 +49,47 @@ where
         }
     }
 
+    /// Pop at most `max` values from the front
+    #[must_use]
+    pub fn pop_front_up_to(&mut self, max: usize) -> Vec<T> {
+        let n = usize::min(max, self.sorted.len());
+        let popped: Vec<T> = self.sorted.drain(..n).collect();
+        self.rwnd
+            .set_size(self.len.saturating_sub(self.sorted.len()));
+        self.check_rep();
+        popped
+    }
+
+    /// Where `seq` would land if inserted
+    #[must_use]
+    pub fn location(&self, seq: TSeq) -> SeqLocationToRwnd {
+        self.rwnd.location(seq)
+    }
+
+    /// The value held out of order at `seq`, which an insert at `seq` would replace
+    #[must_use]
+    pub fn get_out_of_order(&self, seq: TSeq) -> Option<&T> {
+        self.rwnd.get(seq)
+    }
+
+    /// Iterate the values held out of order, in order
+    pub fn out_of_order_iter(&self) -> impl Iterator<Item = &T> {
+        self.rwnd.iter()
+    }
+
+    /// Iterate the values held out of order beyond `seq`, in order
+    pub fn out_of_order_iter_after(&self, seq: TSeq) -> impl Iterator<Item = &T> {
+        self.rwnd.iter_after(seq)
+    }
+
+    /// Remove the value held out of order furthest ahead if it is beyond `seq`
+    #[must_use]
+    pub fn pop_out_of_order_after(&mut self, seq: TSeq) -> Option<T> {
+        let v = self.rwnd.pop_last_after(seq);
+        self.check_rep();
+        v
+    }
+
     #[must_use]
     pub fn insert(&mut self, seq: TSeq, v: T) -> SeqLocationToRwnd {
         let location = self.rwnd.location(seq);
@@ -67,6 +114,69 @@ where
         location
     }
 
+    /// Drop every value and expect `start` next
+    pub fn reset(&mut self, start: TSeq) {
+        self.sorted.clear();
+        self.rwnd.reset(start);
+        self.rwnd.set_size(self.len);
+        self.check_rep();
+    }
+
+    /// Drop the values before `start`, in order, and expect `start` next
+    ///
+    /// The values held out of order from `start` on are kept, and those right at `start` become ready.
+    #[must_use]
+    pub fn skip_to(&mut self, start: TSeq) -> Vec<T> {
+        let mut skipped: Vec<T> = self.sorted.drain(..).collect();
+        skipped.extend(self.rwnd.skip_to(start));
+        while let Some(v) = self.rwnd.pop_next() {
+            self.sorted.push_back(v);
+        }
+        self.rwnd
+            .set_size(self.len.saturating_sub(self.sorted.len()));
+        self.check_rep();
+        skipped
+    }
+
+    /// Unit: sequence
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Resize the buffer without evicting any value
+    ///
+    /// Unit: sequence
+    pub fn set_capacity(&mut self, len: usize) {
+        self.len = len;
+        self.rwnd
+            .set_size(self.len.saturating_sub(self.sorted.len()));
+        self.check_rep();
+    }
+
+    /// Unit: sequence
+    #[must_use]
+    pub fn sorted_len(&self) -> usize {
+        self.sorted.len()
+    }
+
+    /// Unit: sequence
+    #[must_use]
+    pub fn out_of_order_len(&self) -> usize {
+        self.rwnd.len()
+    }
+
+    /// Contiguous runs of the sequences held out of order, in order
+    #[must_use]
+    pub fn out_of_order_ranges(&self) -> Vec<Range<TSeq>> {
+        self.rwnd.ranges()
+    }
+
+    /// Iterate the values ready to be popped, from front to back
+    pub fn sorted_iter(&self) -> impl Iterator<Item = &T> {
+        self.sorted.iter()
+    }
+
     #[must_use]
     pub fn next_seq_to_receive(&self) -> TSeq {
         self.rwnd.start()
@@ -149,10 +259,143 @@ mod tests {
             _ => panic!(),
         }
 
+        assert_eq!(*buf.get_out_of_order(Seq32::from_u32(5)).unwrap(), 5);
+        assert!(buf.get_out_of_order(Seq32::from_u32(4)).is_none());
+        match buf.location(Seq32::from_u32(6)) {
+            SeqLocationToRwnd::TooEarly => (),
+            _ => panic!(),
+        }
+
         assert_eq!(buf.pop_front().unwrap(), 1);
 
         //         0  1  2  3  4  5  6
         // rwnd         [         5   ]
         // sorted      ][
     }
+
+    #[test]
+    fn test_skip_to() {
+        let mut buf = RecvBuf::new(5);
+        let _ = buf.insert(Seq32::from_u32(0), 0);
+        let _ = buf.insert(Seq32::from_u32(2), 2);
+        let _ = buf.insert(Seq32::from_u32(4), 4);
+
+        //         0  1  2  3  4
+        // rwnd      [   2     4]
+        // sorted [0]
+
+        assert_eq!(buf.skip_to(Seq32::from_u32(3)), vec![0, 2]);
+
+        //         0  1  2  3  4  5  6  7
+        // rwnd               [   4        ]
+        // sorted            ][
+
+        assert_eq!(buf.next_seq_to_receive(), Seq32::from_u32(3));
+        assert_eq!(buf.rwnd_size(), 5);
+        assert!(buf.pop_front().is_none());
+        assert_eq!(buf.skip_to(Seq32::from_u32(4)), vec![]);
+
+        //         0  1  2  3  4  5  6  7  8
+        // rwnd                  [            ]
+        // sorted               [4]
+
+        assert_eq!(buf.next_seq_to_receive(), Seq32::from_u32(5));
+        assert_eq!(buf.rwnd_size(), 4);
+        assert_eq!(buf.pop_front(), Some(4));
+    }
+
+    #[test]
+    fn test_shrink() {
+        let mut buf = RecvBuf::new(5);
+        let _ = buf.insert(Seq32::from_u32(0), 0);
+        let _ = buf.insert(Seq32::from_u32(3), 3);
+        let _ = buf.insert(Seq32::from_u32(4), 4);
+
+        //         0  1  2  3  4
+        // rwnd      [      3  4]
+        // sorted [0]
+
+        buf.set_capacity(2);
+
+        //         0  1  2  3  4
+        // rwnd      [ ]    3  4
+        // sorted [0]
+
+        assert_eq!(buf.rwnd_size(), 1);
+        assert_eq!(buf.out_of_order_len(), 2);
+        match buf.insert(Seq32::from_u32(2), 2) {
+            SeqLocationToRwnd::TooEarly => (),
+            _ => panic!(),
+        }
+        match buf.insert(Seq32::from_u32(1), 1) {
+            SeqLocationToRwnd::AtRecvWindowStart => (),
+            _ => panic!(),
+        }
+        assert_eq!(buf.rwnd_size(), 0);
+
+        assert_eq!(buf.pop_front().unwrap(), 0);
+
+        //         0  1  2  3  4
+        // rwnd         [ ] 3  4
+        // sorted    [1]
+
+        assert_eq!(buf.rwnd_size(), 1);
+        match buf.insert(Seq32::from_u32(2), 2) {
+            SeqLocationToRwnd::AtRecvWindowStart => (),
+            _ => panic!(),
+        }
+
+        //         0  1  2  3  4  5
+        // rwnd                  [][
+        // sorted    [1  2  3  4]
+
+        assert_eq!(buf.rwnd_size(), 0);
+        assert_eq!(buf.next_seq_to_receive(), Seq32::from_u32(5));
+        assert_eq!(buf.pop_front_up_to(4), vec![1, 2, 3, 4]);
+        assert_eq!(buf.rwnd_size(), 2);
+    }
+
+    #[test]
+    fn test_shrink_below_sorted() {
+        let mut buf = RecvBuf::new(3);
+        let _ = buf.insert(Seq32::from_u32(0), 0);
+        let _ = buf.insert(Seq32::from_u32(1), 1);
+        let _ = buf.insert(Seq32::from_u32(2), 2);
+
+        buf.set_capacity(1);
+
+        //         0  1  2  3
+        // rwnd             []
+        // sorted [0  1  2]
+
+        assert_eq!(buf.rwnd_size(), 0);
+        assert_eq!(buf.pop_front().unwrap(), 0);
+        assert_eq!(buf.rwnd_size(), 0);
+        assert_eq!(buf.pop_front().unwrap(), 1);
+        assert_eq!(buf.rwnd_size(), 0);
+        assert_eq!(buf.pop_front().unwrap(), 2);
+        assert_eq!(buf.rwnd_size(), 1);
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut buf = RecvBuf::new(2);
+        let _ = buf.insert(Seq32::from_u32(0), 0);
+        let _ = buf.insert(Seq32::from_u32(1), 1);
+        assert_eq!(buf.rwnd_size(), 0);
+
+        buf.set_capacity(4);
+
+        //         0  1  2  3
+        // rwnd         [    ]
+        // sorted [0  1]
+
+        assert_eq!(buf.rwnd_size(), 2);
+        match buf.insert(Seq32::from_u32(3), 3) {
+            SeqLocationToRwnd::InRecvWindow => (),
+            _ => panic!(),
+        }
+        assert_eq!(buf.pop_front().unwrap(), 0);
+        assert_eq!(buf.rwnd_size(), 3);
+    }
 }
@@ -1,6 +1,7 @@
 use super::SeqLocationToRwnd;
 use crate::utils::Seq;
-use std::collections::BTreeMap;
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::ops::{Bound, Range};
 
 pub struct Rwnd<TSeq, T>
 where
@@ -16,7 +17,7 @@ where
     TSeq: Seq,
 {
     fn check_rep(&self) {
-        assert!(self.wnd.len() <= self.size);
+        // after a shrink, the values held out of order can outnumber the slots
         // for (&seq, _) in &self.wnd {
         //     assert!(self.next_seq_to_receive < seq);
         //     break;
@@ -40,12 +41,59 @@ where
         self.check_rep();
     }
 
+    /// Drop every value and restart the window from `start`
+    pub fn reset(&mut self, start: TSeq) {
+        self.wnd.clear();
+        self.start = start;
+        self.check_rep();
+    }
+
+    /// Remove the values before `start` and restart the window from it
+    ///
+    /// The size is left to the caller.
+    #[must_use]
+    pub fn skip_to(&mut self, start: TSeq) -> Vec<T> {
+        assert!(self.start <= start);
+        let kept = self.wnd.split_off(&start);
+        let skipped = core::mem::replace(&mut self.wnd, kept);
+        self.start = start;
+        self.check_rep();
+        skipped.into_values().collect()
+    }
+
+    /// Values held out of order stay even if they fall beyond the new size
+    #[inline]
+    pub fn set_size(&mut self, size: usize) {
+        self.size = size;
+        self.check_rep();
+    }
+
     #[must_use]
     #[inline]
     pub fn size(&self) -> usize {
         self.size
     }
 
+    /// Number of values held out of order
+    #[must_use]
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.wnd.len()
+    }
+
+    /// Contiguous runs of the sequences held out of order, in order
+    #[must_use]
+    pub fn ranges(&self) -> Vec<Range<TSeq>> {
+        let mut ranges: Vec<Range<TSeq>> = Vec::new();
+        for &seq in self.wnd.keys() {
+            match ranges.last_mut() {
+                Some(last) if last.end == seq => last.end = seq.add_usize(1),
+                _ => ranges.push(seq..seq.add_usize(1)),
+            }
+        }
+        ranges
+    }
+
     #[must_use]
     #[inline]
     pub fn start(&self) -> TSeq {
@@ -68,7 +116,7 @@ where
     pub fn location(&self, seq: TSeq) -> SeqLocationToRwnd {
         if !(self.start <= seq) {
             SeqLocationToRwnd::TooLate
-        } else if !(seq < self.start.add_usize(self.size)) {
+        } else if !seq.is_within_window(self.start, self.size) {
             SeqLocationToRwnd::TooEarly
         } else if self.start == seq {
             SeqLocationToRwnd::AtRecvWindowStart
@@ -77,6 +125,37 @@ where
         }
     }
 
+    /// The value held out of order at `seq`
+    #[must_use]
+    #[inline]
+    pub fn get(&self, seq: TSeq) -> Option<&T> {
+        self.wnd.get(&seq)
+    }
+
+    /// The values held out of order, in order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.wnd.values()
+    }
+
+    /// The values held out of order beyond `seq`, in order
+    pub fn iter_after(&self, seq: TSeq) -> impl Iterator<Item = &T> {
+        self.wnd
+            .range((Bound::Excluded(seq), Bound::Unbounded))
+            .map(|(_, v)| v)
+    }
+
+    /// Remove the value held furthest ahead if it is beyond `seq`
+    #[must_use]
+    pub fn pop_last_after(&mut self, seq: TSeq) -> Option<T> {
+        let (&last, _) = self.wnd.last_key_value()?;
+        if !(seq < last) {
+            return None;
+        }
+        let v = self.wnd.remove(&last);
+        self.check_rep();
+        v
+    }
+
     #[inline]
     pub fn insert(&mut self, seq: TSeq, v: T) -> Option<T> {
         if !self.is_acceptable(seq) {
@@ -120,7 +199,7 @@ where
     #[inline]
     fn wnd_proceed(&mut self) {
         self.start = self.start.add_usize(1);
-        self.size -= 1;
+        self.size = self.size.saturating_sub(1);
         self.check_rep();
     }
 }
@@ -180,4 +259,39 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn test_wraparound() {
+        let mut rwnd = Rwnd::new(4);
+        rwnd.start = Seq32::from_u32(u32::MAX - 1);
+        // [MAX-1 MAX 0 1]
+
+        rwnd.insert(Seq32::from_u32(1), 1);
+        rwnd.insert(Seq32::from_u32(u32::MAX), u32::MAX);
+        rwnd.insert(Seq32::from_u32(0), 0);
+        // _ MAX 0 1
+        assert_eq!(
+            rwnd.ranges(),
+            vec![Seq32::from_u32(u32::MAX)..Seq32::from_u32(2)]
+        );
+        match rwnd.location(Seq32::from_u32(u32::MAX - 2)) {
+            SeqLocationToRwnd::TooLate => (),
+            _ => panic!(),
+        }
+        match rwnd.location(Seq32::from_u32(2)) {
+            SeqLocationToRwnd::TooEarly => (),
+            _ => panic!(),
+        }
+
+        let first = rwnd
+            .insert_then_pop_next(Seq32::from_u32(u32::MAX - 1), u32::MAX - 1)
+            .unwrap();
+        assert_eq!(first, u32::MAX - 1);
+        assert_eq!(rwnd.pop_next(), Some(u32::MAX));
+        assert_eq!(rwnd.pop_next(), Some(0));
+        assert_eq!(rwnd.pop_next(), Some(1));
+        assert_eq!(rwnd.pop_next(), None);
+        assert_eq!(rwnd.start(), Seq32::from_u32(2));
+        assert_eq!(rwnd.size, 0);
+    }
 }
@@ -0,0 +1,161 @@
+use crate::utils::CHECK_REP;
+use core::time::Duration;
+
+const ALPHA: f64 = 1.0 / 8.0;
+const BETA: f64 = 1.0 / 4.0;
+const K: u32 = 4;
+
+/// Retransmission timeout estimation from RFC 6298
+///
+/// Per Karn's algorithm, the RTT of a retransmitted segment is ambiguous and should not be sampled.
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    initial_rto: Duration,
+    min_rto: Duration,
+    max_rto: Duration,
+}
+
+pub struct RttEstimatorBuilder {
+    /// The RTO before any RTT is sampled
+    pub initial_rto: Duration,
+    pub min_rto: Duration,
+    pub max_rto: Duration,
+}
+
+impl RttEstimatorBuilder {
+    pub fn build(self) -> Result<RttEstimator, RttEstimatorBuildError> {
+        if self.min_rto > self.max_rto {
+            return Err(RttEstimatorBuildError::MinRtoAboveMax);
+        }
+        let this = RttEstimator {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            initial_rto: self.initial_rto,
+            min_rto: self.min_rto,
+            max_rto: self.max_rto,
+        };
+        this.check_rep();
+        Ok(this)
+    }
+}
+
+#[derive(Debug)]
+pub enum RttEstimatorBuildError {
+    MinRtoAboveMax,
+}
+
+impl RttEstimator {
+    #[inline]
+    fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
+        assert!(self.min_rto <= self.max_rto);
+        if self.srtt.is_none() {
+            assert_eq!(self.rttvar, Duration::ZERO);
+        }
+    }
+
+    pub fn on_rtt_sample(&mut self, sample: Duration) {
+        match self.srtt {
+            Some(srtt) => {
+                let diff = match srtt > sample {
+                    true => srtt - sample,
+                    false => sample - srtt,
+                };
+                self.rttvar = self.rttvar.mul_f64(1.0 - BETA) + diff.mul_f64(BETA);
+                self.srtt = Some(srtt.mul_f64(1.0 - ALPHA) + sample.mul_f64(ALPHA));
+            }
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+        }
+        self.check_rep();
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    /// `srtt + 4 * rttvar`, clamped to `[min_rto, max_rto]`
+    #[must_use]
+    pub fn rto(&self) -> Duration {
+        let rto = match self.srtt {
+            Some(srtt) => srtt + self.rttvar * K,
+            None => self.initial_rto,
+        };
+        rto.clamp(self.min_rto, self.max_rto)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimator() -> RttEstimator {
+        RttEstimatorBuilder {
+            initial_rto: Duration::from_secs(1),
+            min_rto: Duration::from_millis(100),
+            max_rto: Duration::from_secs(60),
+        }
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_initial_sample() {
+        let mut rtt = estimator();
+        assert_eq!(rtt.srtt(), None);
+        assert_eq!(rtt.rto(), Duration::from_secs(1));
+
+        rtt.on_rtt_sample(Duration::from_millis(200));
+        assert_eq!(rtt.srtt(), Some(Duration::from_millis(200)));
+        assert_eq!(rtt.rttvar(), Duration::from_millis(100));
+        // 200 + 4 * 100
+        assert_eq!(rtt.rto(), Duration::from_millis(600));
+    }
+
+    #[test]
+    fn test_subsequent_samples() {
+        let mut rtt = estimator();
+        rtt.on_rtt_sample(Duration::from_millis(200));
+        rtt.on_rtt_sample(Duration::from_millis(280));
+        // 3/4 * 100 + 1/4 * 80
+        assert_eq!(rtt.rttvar(), Duration::from_millis(95));
+        // 7/8 * 200 + 1/8 * 280
+        assert_eq!(rtt.srtt(), Some(Duration::from_millis(210)));
+        assert_eq!(rtt.rto(), Duration::from_millis(590));
+
+        // converges on a steady RTT
+        for _ in 0..100 {
+            rtt.on_rtt_sample(Duration::from_millis(50));
+        }
+        assert!(rtt.srtt().unwrap() - Duration::from_millis(50) < Duration::from_millis(1));
+        assert_eq!(rtt.rto(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_clamp() {
+        let mut rtt = estimator();
+        rtt.on_rtt_sample(Duration::from_secs(30));
+        assert_eq!(rtt.rto(), Duration::from_secs(60));
+
+        assert!(RttEstimatorBuilder {
+            initial_rto: Duration::from_secs(1),
+            min_rto: Duration::from_secs(2),
+            max_rto: Duration::from_secs(1),
+        }
+        .build()
+        .is_err());
+    }
+}
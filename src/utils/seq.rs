@@ -1,7 +1,132 @@
-use std::fmt::Debug;
+use core::{cmp::Ordering, fmt::Debug, ops::Sub};
 
 pub trait Seq: PartialOrd + Ord + Copy + Debug {
     fn add_usize(&self, n: usize) -> Self;
     fn sub(&self, other: &Self) -> usize;
     fn zero() -> Self;
+
+    /// Whether `self` is in `[start, start + window_len)`, across any wraparound
+    fn is_within_window(&self, start: Self, window_len: usize) -> bool {
+        self.sub(&start) < window_len
+    }
+
+    /// Distance forward from `start` to `self`, or `0` if `self` is before `start`
+    fn saturating_window_offset(&self, start: Self) -> usize {
+        if *self < start {
+            0
+        } else {
+            self.sub(&start)
+        }
+    }
+
+    /// Signed distance from `self` forward to `other`
+    fn distance_to(&self, other: &Self) -> i64 {
+        if self <= other {
+            other.sub(self) as i64
+        } else {
+            -(self.sub(other) as i64)
+        }
+    }
+}
+
+/// Compare `a` and `b` in a wrapping sequence space, where values more than `half` apart have wrapped around
+///
+/// `half` is half the space, e.g. `u32::MAX / 2`.
+pub fn wrapping_cmp<T>(a: T, b: T, half: T) -> Ordering
+where
+    T: Ord + Sub<Output = T> + Copy,
+{
+    match a.cmp(&b) {
+        Ordering::Less => match b - a <= half {
+            true => Ordering::Less,
+            false => Ordering::Greater,
+        },
+        Ordering::Equal => Ordering::Equal,
+        Ordering::Greater => match a - b <= half {
+            true => Ordering::Greater,
+            false => Ordering::Less,
+        },
+    }
+}
+
+/// Iterate `[start, end)` across any wraparound
+///
+/// Nothing is yielded if `end` is not after `start`.
+pub fn range<TSeq: Seq>(start: TSeq, end: TSeq) -> SeqRange<TSeq> {
+    SeqRange { next: start, end }
+}
+
+pub struct SeqRange<TSeq> {
+    next: TSeq,
+    end: TSeq,
+}
+
+impl<TSeq: Seq> Iterator for SeqRange<TSeq> {
+    type Item = TSeq;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let seq = self.next;
+        self.next = self.next.add_usize(1);
+        Some(seq)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end.saturating_window_offset(self.next);
+        (len, Some(len))
+    }
+}
+
+impl<TSeq: Seq> ExactSizeIterator for SeqRange<TSeq> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::Seq32;
+
+    use super::*;
+
+    #[test]
+    fn range_across_wraparound() {
+        let start = Seq32::from_u32(u32::MAX - 1);
+        let end = Seq32::from_u32(2);
+        let seqs: Vec<u32> = range(start, end).map(|seq| seq.to_u32()).collect();
+        assert_eq!(seqs, vec![u32::MAX - 1, u32::MAX, 0, 1]);
+        assert_eq!(range(start, end).len(), 4);
+    }
+
+    #[test]
+    fn range_empty() {
+        let start = Seq32::from_u32(2);
+        let end = Seq32::from_u32(u32::MAX - 1);
+        assert_eq!(range(start, end).next(), None);
+        assert_eq!(range(start, end).len(), 0);
+        assert_eq!(range(start, start).next(), None);
+    }
+
+    #[test]
+    fn wrapping_cmp_u16() {
+        let half = u16::MAX / 2;
+        assert_eq!(wrapping_cmp(0u16, 1, half), Ordering::Less);
+        assert_eq!(wrapping_cmp(1u16, 0, half), Ordering::Greater);
+        assert_eq!(wrapping_cmp(7u16, 7, half), Ordering::Equal);
+        // wraparound
+        assert_eq!(wrapping_cmp(u16::MAX, 0, half), Ordering::Less);
+        assert_eq!(wrapping_cmp(0, u16::MAX, half), Ordering::Greater);
+        // far
+        assert_eq!(wrapping_cmp(0, half, half), Ordering::Less);
+        assert_eq!(wrapping_cmp(0, half + 1, half), Ordering::Greater);
+        assert_eq!(wrapping_cmp(half + 1, 0, half), Ordering::Less);
+    }
+
+    #[test]
+    fn saturating_window_offset() {
+        let start = Seq32::from_u32(u32::MAX);
+        assert_eq!(Seq32::from_u32(1).saturating_window_offset(start), 2);
+        assert_eq!(
+            Seq32::from_u32(u32::MAX - 1).saturating_window_offset(start),
+            0
+        );
+    }
 }
@@ -1,5 +1,5 @@
-use crate::utils::Seq;
-use std::{cmp::Ordering, num::Wrapping};
+use crate::utils::{wrapping_cmp, Seq};
+use core::{cmp::Ordering, num::Wrapping};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Seq32 {
@@ -45,31 +45,14 @@ impl Seq for Seq32 {
 }
 
 impl PartialOrd for Seq32 {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
 impl Ord for Seq32 {
     fn cmp(&self, other: &Self) -> Ordering {
-        let ord = match self.n.partial_cmp(&other.n).unwrap() {
-            Ordering::Less => {
-                let diff = other.n - self.n;
-                match diff <= u32::MAX / 2 {
-                    true => Ordering::Less,
-                    false => Ordering::Greater,
-                }
-            }
-            Ordering::Equal => Ordering::Equal,
-            Ordering::Greater => {
-                let diff = self.n - other.n;
-                match diff <= u32::MAX / 2 {
-                    true => Ordering::Greater,
-                    false => Ordering::Less,
-                }
-            }
-        };
-        ord
+        wrapping_cmp(self.n, other.n, u32::MAX / 2)
     }
 }
 
@@ -143,4 +126,26 @@ mod tests {
         let b = Seq32::from_u32(1);
         assert_eq!(a.sub(&b), 2);
     }
+
+    #[test]
+    fn within_window_wraparound() {
+        let start = Seq32::from_u32(u32::MAX - 1);
+        assert!(start.is_within_window(start, 4));
+        assert!(Seq32::from_u32(u32::MAX).is_within_window(start, 4));
+        assert!(Seq32::from_u32(0).is_within_window(start, 4));
+        assert!(Seq32::from_u32(1).is_within_window(start, 4));
+        assert!(!Seq32::from_u32(2).is_within_window(start, 4));
+        assert!(!Seq32::from_u32(u32::MAX - 2).is_within_window(start, 4));
+        assert!(!start.is_within_window(start, 0));
+    }
+
+    #[test]
+    fn distance_to_wraparound() {
+        let a = Seq32::from_u32(u32::MAX - 1);
+        let b = Seq32::from_u32(2);
+        assert_eq!(a.distance_to(&b), 4);
+        assert_eq!(b.distance_to(&a), -4);
+        assert_eq!(a.distance_to(&a), 0);
+        assert_eq!(Seq32::from_u32(0).distance_to(&Seq32::from_u32(3)), 3);
+    }
 }
@@ -1,5 +1,8 @@
-use crate::utils::Seq;
-use std::collections::{btree_map, BTreeMap};
+use crate::utils::{Seq, CHECK_REP};
+use alloc::{
+    collections::{btree_map, BTreeMap},
+    vec::Vec,
+};
 
 pub struct Swnd<TSeq, T>
 where
@@ -16,6 +19,9 @@ where
     TSeq: Seq,
 {
     fn check_rep(&self) {
+        if !CHECK_REP {
+            return;
+        }
         assert!(self.wnd.len() <= self.wnd_size_cap);
         assert!(self.start() <= self.end);
         for (&seq, _) in &self.wnd {
@@ -43,6 +49,12 @@ where
         self.remote_rwnd_size = n;
     }
 
+    /// Unit: sequence
+    #[must_use]
+    pub fn remote_rwnd_size(&self) -> usize {
+        self.remote_rwnd_size
+    }
+
     #[must_use]
     pub fn is_empty(&self) -> bool {
         self.wnd.len() == 0
@@ -53,6 +65,10 @@ where
         self.end
     }
 
+    pub fn iter(&self) -> btree_map::Iter<'_, TSeq, T> {
+        self.wnd.iter()
+    }
+
     #[must_use]
     pub fn range_mut(&mut self, start: TSeq, end: TSeq) -> btree_map::RangeMut<'_, TSeq, T> {
         self.wnd.range_mut(start..end)
@@ -65,7 +81,7 @@ where
     }
 
     #[must_use]
-    fn start(&self) -> TSeq {
+    pub fn start(&self) -> TSeq {
         let mut first = None;
         for (&seq, _) in &self.wnd {
             first = Some(seq);
@@ -99,7 +115,7 @@ where
         ret
     }
 
-    pub fn remove_before(&mut self, nack: TSeq) {
+    pub fn remove_before(&mut self, nack: TSeq) -> Vec<T> {
         let mut to_removes = Vec::new();
         for (&seq, _) in &self.wnd {
             if seq < nack {
@@ -108,11 +124,13 @@ where
                 break;
             }
         }
+        let mut removed = Vec::new();
         for to_remove in to_removes {
             // println!("swnd: remove_before: {:?}", to_remove);
-            self.wnd.remove(&to_remove);
+            removed.push(self.wnd.remove(&to_remove).unwrap());
         }
         self.check_rep();
+        removed
     }
 }
 
@@ -226,4 +244,73 @@ mod tests {
 
         assert!(wnd.is_full());
     }
+
+    #[test]
+    fn test_acks_in_order() {
+        let mut wnd = Swnd::<Seq32, i32>::new(4);
+        wnd.set_remote_rwnd_size(4);
+        for i in 0..4 {
+            wnd.push_back(i);
+        }
+        assert_eq!(wnd.size(), 4);
+
+        assert_eq!(wnd.remove(&Seq32::from_u32(0)), Some(0));
+        assert_eq!(wnd.remove(&Seq32::from_u32(1)), Some(1));
+        assert_eq!(wnd.remove(&Seq32::from_u32(2)), Some(2));
+        assert_eq!(wnd.start().to_u32(), 3);
+        assert_eq!(wnd.size(), 1);
+        assert!(!wnd.is_full());
+
+        // a nack covering the rest slides the window past it
+        assert_eq!(wnd.remove_before(Seq32::from_u32(4)), vec![3]);
+        assert_eq!(wnd.start().to_u32(), 4);
+        assert_eq!(wnd.size(), 0);
+        assert!(wnd.is_empty());
+    }
+
+    #[test]
+    fn test_acks_out_of_order() {
+        let mut wnd = Swnd::<Seq32, i32>::new(4);
+        wnd.set_remote_rwnd_size(4);
+        for i in 0..4 {
+            wnd.push_back(i);
+        }
+
+        assert_eq!(wnd.remove(&Seq32::from_u32(2)), Some(2));
+        assert_eq!(wnd.remove(&Seq32::from_u32(1)), Some(1));
+        // the hole at 0 holds the window back
+        assert_eq!(wnd.start().to_u32(), 0);
+        assert_eq!(wnd.size(), 4);
+        assert!(wnd.is_full());
+        assert_eq!(wnd.remove(&Seq32::from_u32(1)), None);
+
+        assert_eq!(wnd.remove(&Seq32::from_u32(0)), Some(0));
+        assert_eq!(wnd.start().to_u32(), 3);
+        assert_eq!(wnd.size(), 1);
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let mut wnd = Swnd::<Seq32, u32>::new(4);
+        wnd.set_remote_rwnd_size(4);
+        wnd.end = Seq32::from_u32(u32::MAX - 1);
+        // [MAX-1 MAX 0 1]
+        for n in [u32::MAX - 1, u32::MAX, 0, 1] {
+            wnd.push_back(n);
+        }
+        assert_eq!(wnd.start().to_u32(), u32::MAX - 1);
+        assert_eq!(wnd.end().to_u32(), 2);
+        assert_eq!(wnd.size(), 4);
+
+        assert_eq!(wnd.remove(&Seq32::from_u32(0)), Some(0));
+        assert_eq!(wnd.start().to_u32(), u32::MAX - 1);
+        assert_eq!(
+            wnd.remove_before(Seq32::from_u32(0)),
+            vec![u32::MAX - 1, u32::MAX]
+        );
+        assert_eq!(wnd.start().to_u32(), 1);
+        assert_eq!(wnd.size(), 1);
+        let seqs: Vec<u32> = wnd.iter().map(|(seq, _)| seq.to_u32()).collect();
+        assert_eq!(seqs, vec![1]);
+    }
 }
@@ -0,0 +1,291 @@
+//! JS bindings for running the framing in a browser, e.g. over WebTransport datagrams
+//!
+//! Built on `protocol` and `utils` alone, so nothing here reads a clock or spawns a thread.
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Debug;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    protocol::{
+        frag::{Body, Frag, FragBuilder, FragCommand},
+        packet::Packet,
+        packet_hdr::{scale_rwnd, PacketHeader, PacketHeaderBuilder, CURRENT_VERSION},
+        DecodingError,
+    },
+    utils::{
+        buf::{BorrowedBufSlice, BufRdr, BufSlice},
+        RecvBuf, Seq32, SeqLocationToRwnd,
+    },
+};
+
+/// Thrown to JS in place of the errors of `protocol`
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct Error {
+    message: String,
+}
+
+#[wasm_bindgen]
+impl Error {
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+impl Error {
+    fn new(e: impl Debug) -> Self {
+        Error {
+            message: format!("{:?}", e),
+        }
+    }
+}
+
+impl From<DecodingError> for Error {
+    fn from(e: DecodingError) -> Self {
+        Error::new(e)
+    }
+}
+
+#[wasm_bindgen]
+pub struct DecodedPacketHeader {
+    pub version: u8,
+    /// Unit: fragment
+    pub rwnd: u32,
+    pub nack: u32,
+    pub checksum: bool,
+    pub ece: bool,
+    /// Unit: byte
+    pub hdr_len: usize,
+}
+
+/// A header without a checksum, timestamps or a length, for fragments to be appended to
+#[wasm_bindgen(js_name = encodePacketHeader)]
+pub fn encode_packet_header(rwnd: usize, nack: u32) -> Result<Vec<u8>, Error> {
+    let (rwnd, rwnd_shift) = scale_rwnd(rwnd);
+    let hdr = PacketHeaderBuilder {
+        version: CURRENT_VERSION,
+        rwnd,
+        rwnd_shift,
+        checksum: false,
+        ece: false,
+        timestamps: None,
+        delimited: false,
+        nack: Seq32::from_u32(nack),
+    }
+    .build()
+    .map_err(Error::new)?;
+    Ok(hdr.to_bytes())
+}
+
+/// The fragments follow the header at `hdr_len`
+#[wasm_bindgen(js_name = decodePacketHeader)]
+pub fn decode_packet_header(datagram: &[u8]) -> Result<DecodedPacketHeader, Error> {
+    let hdr = PacketHeader::from_slice(&mut BorrowedBufSlice::from_slice(datagram))?;
+    Ok(DecodedPacketHeader {
+        version: hdr.version(),
+        rwnd: hdr.scaled_rwnd(),
+        nack: hdr.nack().to_u32(),
+        checksum: hdr.checksum(),
+        ece: hdr.ece(),
+        hdr_len: hdr.hdr_len(),
+    })
+}
+
+#[wasm_bindgen(js_name = encodePush)]
+pub fn encode_push(seq: u32, body: &[u8], checksum: bool, more: bool) -> Result<Vec<u8>, Error> {
+    encode_frag(FragBuilder {
+        seq: Seq32::from_u32(seq),
+        cmd: FragCommand::Push {
+            body: Body::Slice(BufSlice::from_bytes(body.to_vec())),
+        },
+        checksum,
+        more,
+    })
+}
+
+#[wasm_bindgen(js_name = encodeAck)]
+pub fn encode_ack(seq: u32) -> Result<Vec<u8>, Error> {
+    encode_frag(FragBuilder {
+        seq: Seq32::from_u32(seq),
+        cmd: FragCommand::Ack,
+        checksum: false,
+        more: false,
+    })
+}
+
+fn encode_frag(frag: FragBuilder) -> Result<Vec<u8>, Error> {
+    let frag = frag.build().map_err(Error::new)?;
+    Ok(frag.to_bytes())
+}
+
+#[wasm_bindgen]
+pub struct DecodedFrag {
+    pub seq: u32,
+    /// `CommandType` as a number
+    pub cmd: u8,
+    pub more: bool,
+    /// Bytes taken by the fragment, so the next one starts past them
+    pub len: usize,
+    body: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl DecodedFrag {
+    /// The body of a `Push`
+    #[wasm_bindgen(getter)]
+    pub fn body(&self) -> Option<Vec<u8>> {
+        self.body.clone()
+    }
+}
+
+/// Decode the fragment at the start of `data`
+#[wasm_bindgen(js_name = decodeFrag)]
+pub fn decode_frag(data: &[u8]) -> Result<DecodedFrag, Error> {
+    let mut rdr = BorrowedBufSlice::from_slice(data);
+    let frag = Frag::from_slice(&mut rdr)?;
+    let seq = frag.seq().to_u32();
+    let more = frag.more();
+    let len = data.len() - rdr.len();
+    let (cmd, body) = match frag.into_builder().cmd {
+        FragCommand::Push { body } => (0, Some(body_to_vec(body))),
+        FragCommand::Ack => (1, None),
+        FragCommand::Ping => (2, None),
+        FragCommand::Pong => (3, None),
+        FragCommand::Reset { .. } => (4, None),
+        FragCommand::ResetAck => (5, None),
+    };
+    Ok(DecodedFrag {
+        seq,
+        cmd,
+        more,
+        len,
+        body,
+    })
+}
+
+fn body_to_vec(body: Body) -> Vec<u8> {
+    match body {
+        Body::Slice(x) => x.data().to_vec(),
+        Body::Pasta(x) => {
+            let mut v = Vec::with_capacity(x.len());
+            for slice in x.slices() {
+                v.extend_from_slice(slice.data());
+            }
+            v
+        }
+    }
+}
+
+/// Receive pushes from datagrams and deliver them in order
+///
+/// The acks are left to the caller, as are the timers of the full `layer::Downloader`.
+#[wasm_bindgen(js_name = Downloader)]
+pub struct WasmDownloader {
+    recv_buf: RecvBuf<Seq32, BufSlice>,
+}
+
+#[wasm_bindgen(js_class = Downloader)]
+impl WasmDownloader {
+    #[wasm_bindgen(constructor)]
+    pub fn new(recv_buf_len: usize) -> Result<WasmDownloader, Error> {
+        if recv_buf_len == 0 {
+            return Err(Error::new("RecvBufLenZero"));
+        }
+        Ok(WasmDownloader {
+            recv_buf: RecvBuf::new(recv_buf_len),
+        })
+    }
+
+    /// Return the seqs of the pushes to ack
+    ///
+    /// Pushes beyond the rwnd are dropped without an ack.
+    pub fn write(&mut self, datagram: &[u8]) -> Result<Vec<u32>, Error> {
+        let packet = Packet::from_slice(&mut BorrowedBufSlice::from_slice(datagram))?;
+        let mut seqs_to_ack = Vec::new();
+        for frag in packet.into_builder().frags {
+            let seq = frag.seq();
+            let body = match frag.into_builder().cmd {
+                FragCommand::Push { body } => body,
+                _ => continue,
+            };
+            let body = match body {
+                Body::Slice(x) => x,
+                body => BufSlice::from_bytes(body_to_vec(body)),
+            };
+            match self.recv_buf.insert(seq, body) {
+                SeqLocationToRwnd::InRecvWindow
+                | SeqLocationToRwnd::AtRecvWindowStart
+                | SeqLocationToRwnd::TooLate => seqs_to_ack.push(seq.to_u32()),
+                SeqLocationToRwnd::TooEarly => (),
+            }
+        }
+        Ok(seqs_to_ack)
+    }
+
+    /// The next body in order, if any
+    pub fn emit(&mut self) -> Option<Vec<u8>> {
+        self.recv_buf.pop_front().map(|x| x.data().to_vec())
+    }
+
+    /// The cumulative nack to stamp into outgoing packet headers
+    #[wasm_bindgen(getter)]
+    pub fn nack(&self) -> u32 {
+        self.recv_buf.next_seq_to_receive().to_u32()
+    }
+
+    /// Unit: fragment
+    #[wasm_bindgen(getter, js_name = rwndSize)]
+    pub fn rwnd_size(&self) -> usize {
+        self.recv_buf.rwnd_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The same bytes a page would pass through the bindings
+    #[test]
+    fn test_round_trip() {
+        let mut datagram1 = encode_packet_header(16, 7).unwrap();
+        datagram1.extend(encode_ack(3).unwrap());
+        datagram1.extend(encode_push(1, &[3, 4], false, false).unwrap());
+        let mut datagram2 = encode_packet_header(16, 7).unwrap();
+        datagram2.extend(encode_push(0, &[0, 1, 2], true, true).unwrap());
+
+        let hdr = decode_packet_header(&datagram1).unwrap();
+        assert_eq!(hdr.version, CURRENT_VERSION);
+        assert_eq!(hdr.rwnd, 16);
+        assert_eq!(hdr.nack, 7);
+        assert!(!hdr.checksum);
+        let ack = decode_frag(&datagram1[hdr.hdr_len..]).unwrap();
+        assert_eq!((ack.seq, ack.cmd, ack.body()), (3, 1, None));
+        let push = decode_frag(&datagram1[hdr.hdr_len + ack.len..]).unwrap();
+        assert_eq!((push.seq, push.cmd), (1, 0));
+        assert_eq!(push.body(), Some(vec![3, 4]));
+        assert_eq!(hdr.hdr_len + ack.len + push.len, datagram1.len());
+        let push = decode_frag(&datagram2[hdr.hdr_len..]).unwrap();
+        assert!(push.more);
+
+        // out of order
+        let mut downloader = WasmDownloader::new(4).unwrap();
+        assert_eq!(downloader.write(&datagram1).unwrap(), vec![1]);
+        assert_eq!(downloader.emit(), None);
+        assert_eq!(downloader.nack(), 0);
+        assert_eq!(downloader.write(&datagram2).unwrap(), vec![0]);
+        assert_eq!(downloader.nack(), 2);
+        assert_eq!(downloader.emit(), Some(vec![0, 1, 2]));
+        assert_eq!(downloader.emit(), Some(vec![3, 4]));
+        assert_eq!(downloader.emit(), None);
+        assert_eq!(downloader.rwnd_size(), 4);
+
+        let mut corrupted = datagram2.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(decode_frag(&corrupted[hdr.hdr_len..]).is_err());
+        assert!(decode_packet_header(&datagram1[..2]).is_err());
+        assert!(encode_push(0, &[], false, false).is_err());
+        assert!(WasmDownloader::new(0).is_err());
+    }
+}